@@ -0,0 +1,8 @@
+ALTER TABLE clips
+    ADD COLUMN IF NOT EXISTS container TEXT,
+    ADD COLUMN IF NOT EXISTS video_codec TEXT,
+    ADD COLUMN IF NOT EXISTS audio_codec TEXT,
+    ADD COLUMN IF NOT EXISTS width INTEGER,
+    ADD COLUMN IF NOT EXISTS height INTEGER,
+    ADD COLUMN IF NOT EXISTS frame_rate DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS audio_channels INTEGER;
@@ -0,0 +1,4 @@
+ALTER TABLE clips
+    ADD COLUMN IF NOT EXISTS time_base TEXT,
+    ADD COLUMN IF NOT EXISTS pixel_format TEXT,
+    ADD COLUMN IF NOT EXISTS audio_sample_rate INTEGER;
@@ -0,0 +1,2 @@
+ALTER TABLE merged_outputs
+    ADD COLUMN IF NOT EXISTS trims JSONB NOT NULL DEFAULT '[]'::jsonb;
@@ -0,0 +1,3 @@
+ALTER TABLE merged_outputs
+    ADD COLUMN IF NOT EXISTS overlays JSONB NOT NULL DEFAULT '[]'::jsonb,
+    ADD COLUMN IF NOT EXISTS layout JSONB;
@@ -1,11 +1,13 @@
-use std::ops::Deref;
-use std::time::Duration;
+use std::future::Future;
 use std::time::Instant;
 
 use actix::{Actor, StreamHandler};
 
-//TODO:: Use an appropriate custom error for response type.
-use actix_http::ws::HandshakeError;
+use actix_files::{file_extension_to_mime, NamedFile};
+use actix_web::http::header::{
+    ContentDisposition, DispositionParam, DispositionType, HeaderValue, CACHE_CONTROL, LOCATION,
+};
+use actix_web::web::Bytes;
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 
 use actix_http::ws::Item::Continue;
@@ -16,13 +18,27 @@ use actix_http::ws::Item::Last;
 use actix::prelude::*;
 use actix_web_actors::ws;
 
-use serde::Deserialize;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::helpers::errors::ClipError;
 use crate::helpers::errors::ClipErrorType;
-use crate::helpers::utilities::Utilities;
-use crate::services::ffmpeg::FFMpegService;
+use crate::models::ids::{ClipId, SessionId};
+use crate::models::protocol::{ChunkFrame, ClipEnvelope, ClipMessage, PROTOCOL_VERSION};
+use crate::models::{
+    ClipRecord, ExportFormat, JobState, MergeOutput, MergedOutputRecord, PipLayout, Preview,
+    SessionRecord,
+};
+use crate::services::database::Database;
+use crate::services::export::ExportService;
+use crate::services::ffmpeg::{FFMpegService, MergeSpec};
+use crate::services::jobs::{JobQueue, JobSubscriber, JobUpdate};
+use crate::services::profiles::EncodingProfiles;
+use crate::services::retention::RetentionService;
+use crate::services::session::SessionService;
+use crate::services::storage::Storage;
+use crate::services::upload::{UploadService, UploadUsage};
+use crate::settings::Settings;
 
 #[derive(Debug, Clone)]
 pub struct ClipDetails {
@@ -33,9 +49,17 @@ pub struct ClipDetails {
 #[derive(Debug, Clone)]
 struct ClipWS {
     request_type: ClipRequest,
-    session_id: String,
-    chunks: Vec<u8>,
+    session_id: SessionId,
+    /// Fragments of a chunk frame that is still arriving in continuation frames.
+    fragments: Vec<u8>,
+    jobs: JobQueue,
+    profiles: EncodingProfiles,
+    upload_correlation_id: Option<String>,
     clips: Vec<ClipDetails>,
+    db: Database,
+    storage: Storage,
+    usage: UploadUsage,
+    settings: web::Data<Settings>,
     pub hb: Instant,
 }
 
@@ -46,15 +70,11 @@ pub enum ClipRequest {
     Submission,
 }
 
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-/// How long before lack of client response causes a timeout
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(100);
-
 impl ClipWS {
     fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+        ctx.run_interval(self.settings.websocket.heartbeat_interval(), |act, ctx| {
             // check client heartbeats
-            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+            if Instant::now().duration_since(act.hb) > act.settings.websocket.client_timeout() {
                 // heartbeat timed out
                 println!("Websocket Client heartbeat failed, disconnecting!");
 
@@ -68,12 +88,369 @@ impl ClipWS {
             // ctx.ping(b"");
         });
     }
+
+    fn send(
+        &self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        correlation_id: &str,
+        message: ClipMessage,
+    ) {
+        let envelope = ClipEnvelope::new(correlation_id.to_string(), message);
+
+        match serde_json::to_string(&envelope) {
+            Ok(text) => ctx.text(text),
+            Err(err) => log::error!(
+                "Message to session {} was not sent: {}",
+                self.session_id,
+                err
+            ),
+        }
+    }
+
+    fn send_error(
+        &self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        correlation_id: &str,
+        error: &ClipError,
+    ) {
+        self.send(
+            ctx,
+            correlation_id,
+            ClipMessage::Error {
+                code: error.code().to_string(),
+                message: error.message.clone().unwrap_or_default(),
+            },
+        );
+    }
+
+    fn on_text(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let envelope: ClipEnvelope = match serde_json::from_str(text) {
+            Ok(envelope) => envelope,
+            Err(err) => {
+                let error = ClipError::from(format!("Malformed message: {}", err));
+                self.send_error(ctx, "", &error);
+                return;
+            }
+        };
+
+        let correlation_id = envelope.correlation_id.as_str();
+
+        if envelope.version != PROTOCOL_VERSION {
+            let error = ClipError::from(format!(
+                "Unsupported protocol version {}, expected {}",
+                envelope.version, PROTOCOL_VERSION
+            ));
+            self.send_error(ctx, correlation_id, &error);
+            return;
+        }
+
+        match (&self.request_type, envelope.message) {
+            (
+                ClipRequest::OnPlayback,
+                ClipMessage::MergeRequest {
+                    clips,
+                    profile,
+                    layout,
+                    audio_format,
+                },
+            ) => {
+                if let Some(err) = clips.iter().find_map(|clip| clip.validate().err()) {
+                    self.send_error(ctx, correlation_id, &ClipError::from(err));
+                    return;
+                }
+
+                let output = match audio_format {
+                    Some(format) => MergeOutput::Audio(format),
+                    None => MergeOutput::Video(layout.unwrap_or(self.settings.pip)),
+                };
+                if let MergeOutput::Video(layout) = &output {
+                    if let Err(err) = layout.validate() {
+                        self.send_error(ctx, correlation_id, &ClipError::from(err));
+                        return;
+                    }
+                }
+
+                let profile = match self.profiles.resolve(profile.as_deref()) {
+                    Ok(profile) => profile,
+                    Err(err) => {
+                        self.send_error(ctx, correlation_id, &err);
+                        return;
+                    }
+                };
+
+                //Overlays are clips of the session too, and can't double as one of the others
+                let clip_ids = clips
+                    .iter()
+                    .map(|clip| clip.id.clone())
+                    .chain(clips.iter().filter_map(|clip| clip.overlay.clone()))
+                    .collect();
+                let check = UploadService::check_ownership(
+                    self.session_id,
+                    clip_ids,
+                    self.storage.temp_directory().to_path_buf(),
+                    self.db.clone(),
+                );
+                let correlation_id = correlation_id.to_string();
+
+                ctx.wait(check.into_actor(self).map(move |result, act, ctx| {
+                    if let Err(err) = result {
+                        act.send_error(ctx, correlation_id.as_str(), &err);
+                        return;
+                    }
+
+                    let job = match act.jobs.submit_merge(
+                        MergeSpec {
+                            clips,
+                            profile,
+                            output,
+                        },
+                        act.session_id,
+                        act.db.clone(),
+                        Some(JobSubscriber {
+                            correlation_id: correlation_id.clone(),
+                            recipient: ctx.address().recipient(),
+                        }),
+                    ) {
+                        Ok(job) => job,
+                        Err(err) => {
+                            act.send_error(ctx, correlation_id.as_str(), &err);
+                            return;
+                        }
+                    };
+
+                    act.send(
+                        ctx,
+                        correlation_id.as_str(),
+                        ClipMessage::MergeQueued {
+                            job_id: job.id.to_string(),
+                        },
+                    );
+                }));
+            }
+            (ClipRequest::Chunk, ClipMessage::BeginClip) => {
+                let session_id = self.session_id;
+                let max_chunk_bytes = self.settings.upload.max_chunk_bytes;
+                let storage = self.storage.clone();
+
+                self.run_upload(ctx, correlation_id.to_string(), async move {
+                    let clip_id =
+                        UploadService::begin(session_id, storage.temp_directory()).await?;
+
+                    Ok(ClipMessage::ClipStarted {
+                        clip_id: clip_id.to_string(),
+                        max_chunk_bytes,
+                    })
+                });
+            }
+            (ClipRequest::Chunk, ClipMessage::ResumeQuery { clip_id }) => {
+                let session_id = self.session_id;
+                let db = self.db.clone();
+                let storage = self.storage.clone();
+                let settings = self.settings.clone();
+
+                self.run_upload(ctx, correlation_id.to_string(), async move {
+                    let clip_id = ClipId::parse(clip_id.as_str())?;
+                    let root = storage.temp_directory();
+
+                    // Finalized while the client was away, it only missed the ack.
+                    if UploadService::is_finalized(&session_id, &clip_id, root).await {
+                        let record = UploadService::finalized_clip(
+                            session_id,
+                            clip_id,
+                            root,
+                            &settings.ffmpeg,
+                            db,
+                        )
+                        .await?;
+
+                        return Ok(upload_ack(record));
+                    }
+
+                    let received =
+                        UploadService::received_chunks(&session_id, &clip_id, root).await?;
+
+                    Ok(ClipMessage::ResumeState {
+                        clip_id: clip_id.to_string(),
+                        received,
+                    })
+                });
+            }
+            (
+                ClipRequest::Chunk,
+                ClipMessage::FinalizeClip {
+                    clip_id,
+                    chunk_count,
+                },
+            ) => {
+                let session_id = self.session_id;
+                let db = self.db.clone();
+                let storage = self.storage.clone();
+                let settings = self.settings.clone();
+
+                self.run_upload(ctx, correlation_id.to_string(), async move {
+                    let clip_id = ClipId::parse(clip_id.as_str())?;
+                    let record = UploadService::finalize(
+                        session_id,
+                        clip_id,
+                        chunk_count,
+                        &settings.ffmpeg,
+                        db,
+                        storage,
+                    )
+                    .await?;
+
+                    Ok(upload_ack(record))
+                });
+            }
+            (_, message) => {
+                let error = ClipError::from(format!("Unexpected message: {:?}", message));
+                self.send_error(ctx, correlation_id, &error);
+            }
+        }
+    }
+
+    /// Runs an upload step on the async file sink and replies with its outcome. The actor
+    /// holds back further frames until the step completes, so acks go out in order and a
+    /// slow disk pushes back on the client instead of piling frames up in memory.
+    fn run_upload<F>(
+        &mut self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        correlation_id: String,
+        step: F,
+    ) where
+        F: Future<Output = Result<ClipMessage, ClipError>> + 'static,
+    {
+        ctx.wait(
+            step.into_actor(self)
+                .map(move |result, act, ctx| match result {
+                    Ok(message) => act.send(ctx, correlation_id.as_str(), message),
+                    Err(err) => {
+                        log::warn!("Upload step of session {} failed: {}", act.session_id, err);
+                        act.send_error(ctx, correlation_id.as_str(), &err);
+
+                        if let ClipErrorType::QuotaExceeded = err.err_type {
+                            act.close(ctx, ws::CloseCode::Size, err.message);
+                        }
+                    }
+                }),
+        );
+    }
+
+    fn close(
+        &mut self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        code: ws::CloseCode,
+        description: Option<String>,
+    ) {
+        ctx.close(Some(ws::CloseReason { code, description }));
+        ctx.stop();
+    }
+
+    /// Stores one chunk frame and acknowledges it. Acks are correlated by clip id since
+    /// binary frames carry no envelope.
+    fn on_chunk(&mut self, frame: Bytes, ctx: &mut ws::WebsocketContext<Self>) {
+        self.upload_correlation_id = None;
+
+        let chunk = match ChunkFrame::parse(frame) {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                log::warn!(
+                    "Chunk frame of session {} rejected: {}",
+                    self.session_id,
+                    err
+                );
+                self.send_error(ctx, "", &err);
+                return;
+            }
+        };
+
+        let session_id = self.session_id;
+        let quota = self.settings.upload;
+        let storage = self.storage.clone();
+        let usage = self.usage.clone();
+
+        self.run_upload(ctx, chunk.clip_id.to_string(), async move {
+            UploadService::write_chunk(
+                session_id,
+                chunk.clip_id.clone(),
+                chunk.seq,
+                chunk.data,
+                storage.temp_directory(),
+                quota,
+                &usage,
+            )
+            .await?;
+
+            Ok(ClipMessage::ChunkAck {
+                clip_id: chunk.clip_id.to_string(),
+                seq: chunk.seq,
+            })
+        });
+    }
+
+    fn on_fragment(&mut self, data: &[u8], ctx: &mut ws::WebsocketContext<Self>) {
+        if self.fragments.len() + data.len() > self.settings.upload.max_chunk_bytes {
+            let description = format!(
+                "Chunk exceeds the {} byte frame limit",
+                self.settings.upload.max_chunk_bytes
+            );
+
+            self.fragments = vec![];
+            self.close(ctx, ws::CloseCode::Size, Some(description));
+            return;
+        }
+
+        self.fragments.extend_from_slice(data);
+
+        let correlation_id = self
+            .upload_correlation_id
+            .get_or_insert_with(|| Uuid::new_v4().to_string())
+            .clone();
+
+        self.send(
+            ctx,
+            correlation_id.as_str(),
+            ClipMessage::Progress {
+                received_bytes: self.fragments.len() as u64,
+            },
+        );
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ClipDetailRequest {
-    pub id: String,
-    pub duration: f64,
+fn upload_ack(record: ClipRecord) -> ClipMessage {
+    ClipMessage::UploadAck {
+        clip_id: record.id,
+        byte_size: record.byte_size as u64,
+        metadata: record.metadata.unwrap_or_default(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionDetailResponse {
+    pub session: SessionRecord,
+    pub clips: Vec<ClipRecord>,
+    pub merged_output: Option<MergedOutputRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StreamQuery {
+    pub format: Option<ExportFormat>,
+}
+
+/// Renders the latest merge again, with its camera overlays laid out as `layout` says or
+/// as the `[pip]` settings do. Voice feedback is merged to its audio format again.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderRequest {
+    #[serde(default)]
+    pub layout: Option<PipLayout>,
+    #[serde(default)]
+    pub profile: Option<String>,
 }
 
 impl Actor for ClipWS {
@@ -84,73 +461,138 @@ impl Actor for ClipWS {
     }
 }
 
+impl Handler<JobUpdate> for ClipWS {
+    type Result = ();
+
+    fn handle(&mut self, update: JobUpdate, ctx: &mut Self::Context) {
+        let correlation_id = update.correlation_id.as_str();
+        let job = update.job;
+
+        if let Some(progress) = update.progress {
+            self.send(
+                ctx,
+                correlation_id,
+                ClipMessage::MergeProgress {
+                    job_id: job.id.to_string(),
+                    out_time: progress.out_time,
+                    speed: progress.speed,
+                    frame: progress.frame,
+                    percent: progress.percent,
+                },
+            );
+            return;
+        }
+
+        match job.state {
+            JobState::Queued => {}
+            JobState::Running => self.send(
+                ctx,
+                correlation_id,
+                ClipMessage::MergeStarted {
+                    job_id: job.id.to_string(),
+                    clip_count: job.clip_ids.len(),
+                },
+            ),
+            JobState::Succeeded => {
+                if let Some(output) = job.output {
+                    self.send(
+                        ctx,
+                        correlation_id,
+                        ClipMessage::MergeComplete {
+                            job_id: job.id.to_string(),
+                            duration: output.duration,
+                            byte_size: output.byte_size as u64,
+                        },
+                    );
+                }
+            }
+            JobState::Failed => {
+                let error = ClipError::from(job.error.unwrap_or_default());
+                self.send_error(ctx, correlation_id, &error);
+            }
+        }
+    }
+}
+
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ClipWS {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
             Ok(ws::Message::Text(text)) => {
-                if let ClipRequest::OnPlayback = self.request_type {
-                    let clips: Vec<ClipDetailRequest> =
-                        serde_json::from_str(text.to_string().as_str()).unwrap();
-
-                    println!("{:?}", clips);
-                    let clip_path = FFMpegService::merge_clips(clips, self.session_id.clone());
-
-                    println!("{:?}", clip_path);
-                    match clip_path {
-                        Ok(_b) => {
-                            ctx.text("passed");
-                        }
-                        Err(e) => {
-                            println!("{:?}", e);
-                        }
-                    }
-                }
+                self.on_text(text.to_string().as_str(), ctx);
             }
             Ok(ws::Message::Binary(bin)) => {
                 if let ClipRequest::Chunk = self.request_type {
-                    self.chunks.append(&mut bin.to_vec());
-
-                    let file_path = self.session_id.clone();
-
-                    let clip_id = FFMpegService::create_file(file_path, self.chunks.clone());
-
-                    ctx.text(clip_id);
+                    self.on_chunk(bin, ctx);
                 }
             }
             Ok(ws::Message::Continuation(item)) => {
                 if let ClipRequest::Chunk = self.request_type {
                     match item {
                         FirstText(data) => {
-                            self.chunks.append(&mut data.to_vec());
+                            self.on_fragment(&data, ctx);
                         }
                         FirstBinary(data) => {
-                            self.chunks.append(&mut data.to_vec());
+                            self.on_fragment(&data, ctx);
                         }
                         Continue(data) => {
-                            self.chunks.append(&mut data.to_vec());
+                            self.on_fragment(&data, ctx);
                         }
                         Last(data) => {
-                            self.chunks.append(&mut data.to_vec());
-
-                            let file_path = self.session_id.clone();
+                            self.on_fragment(&data, ctx);
 
-                            let clip_id =
-                                FFMpegService::create_file(file_path, self.chunks.clone());
-
-                            ctx.text(clip_id);
+                            let frame = std::mem::take(&mut self.fragments);
+                            self.on_chunk(Bytes::from(frame), ctx);
                         }
                     }
                 }
             }
             Ok(ws::Message::Pong(_t)) => {}
-            Ok(ws::Message::Close(_t)) => {
-                //Remove temporary session directory.
+            Ok(ws::Message::Close(reason)) => {
+                if let ClipRequest::Chunk = self.request_type {
+                    let session_id = self.session_id;
+                    let storage = self.storage.clone();
+                    let usage = self.usage.clone();
+
+                    // Detached, the actor stops before the cleanup finishes.
+                    actix_web::rt::spawn(async move {
+                        match RetentionService::release(session_id, storage.temp_directory()).await
+                        {
+                            Ok(reclaimed) if reclaimed.files > 0 || reclaimed.sessions > 0 => {
+                                if reclaimed.sessions > 0 {
+                                    usage.forget(&session_id);
+                                }
+                                log::info!(
+                                    "Released session {}, reclaimed {} bytes in {} files",
+                                    session_id,
+                                    reclaimed.bytes,
+                                    reclaimed.files
+                                )
+                            }
+                            Ok(_) => {}
+                            Err(err) => {
+                                log::warn!("Release of session {} failed: {}", session_id, err)
+                            }
+                        }
+                    });
+                }
+
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(ws::Message::Nop) => {}
+            Err(ws::ProtocolError::Overflow) => {
+                let description = format!(
+                    "Frame exceeds the {} byte frame limit",
+                    self.settings.upload.max_chunk_bytes
+                );
+
+                self.close(ctx, ws::CloseCode::Size, Some(description));
             }
-            Ok(ws::Message::Nop) => {
-                println!("{:?}", "nop");
+            Err(err) => {
+                log::warn!("Socket of session {} failed: {}", self.session_id, err);
+                ctx.stop();
             }
-            Err(_err) => (),
         }
     }
 }
@@ -168,6 +610,60 @@ impl ClipController {
                     }),
                 ),
         );
+        cfg.service(
+            web::resource("/clip/session/export")
+                .route(web::get().to(Self::export_session))
+                .route(
+                    web::head().to(|| -> actix_web::HttpResponseBuilder {
+                        HttpResponse::MethodNotAllowed()
+                    }),
+                ),
+        );
+        cfg.service(
+            web::resource("/clip/session/render")
+                .route(web::post().to(Self::render_session))
+                .route(
+                    web::head().to(|| -> actix_web::HttpResponseBuilder {
+                        HttpResponse::MethodNotAllowed()
+                    }),
+                ),
+        );
+        cfg.service(
+            web::resource("/clip/session/poster")
+                .route(web::get().to(Self::get_poster))
+                .route(
+                    web::head().to(|| -> actix_web::HttpResponseBuilder {
+                        HttpResponse::MethodNotAllowed()
+                    }),
+                ),
+        );
+        cfg.service(
+            web::resource("/clip/session/sprite")
+                .route(web::get().to(Self::get_sprite))
+                .route(
+                    web::head().to(|| -> actix_web::HttpResponseBuilder {
+                        HttpResponse::MethodNotAllowed()
+                    }),
+                ),
+        );
+        cfg.service(
+            web::resource("/clip/session/preview")
+                .route(web::get().to(Self::get_animated_preview))
+                .route(
+                    web::head().to(|| -> actix_web::HttpResponseBuilder {
+                        HttpResponse::MethodNotAllowed()
+                    }),
+                ),
+        );
+        cfg.service(
+            web::resource("/clip/session/details")
+                .route(web::get().to(Self::get_session_details))
+                .route(
+                    web::head().to(|| -> actix_web::HttpResponseBuilder {
+                        HttpResponse::MethodNotAllowed()
+                    }),
+                ),
+        );
         cfg.service(
             web::resource("/ws/clips")
                 .route(web::get().to(Self::start_clip_session_ws))
@@ -188,71 +684,272 @@ impl ClipController {
         );
     }
 
-    async fn get_clip_stream(req: HttpRequest) -> Result<HttpResponse, ClipError> {
-        let cookies = req.cookies().unwrap();
+    /// Streams the merged session. Range requests, `ETag` and `Last-Modified` are handled by
+    /// `NamedFile`, so players can seek and revalidate instead of downloading it again.
+    /// Stores that hand out URLs serve it themselves.
+    async fn get_clip_stream(
+        req: HttpRequest,
+        query: web::Query<StreamQuery>,
+        db: web::Data<Database>,
+        jobs: web::Data<JobQueue>,
+        storage: web::Data<Storage>,
+        settings: web::Data<Settings>,
+    ) -> Result<HttpResponse, ClipError> {
+        let session_id = SessionService::verify_request(&req)?;
+        let format = query.format.unwrap_or(ExportFormat::Webm);
+
+        let path = ExportService::export(
+            session_id,
+            format,
+            settings.get_ref(),
+            db.get_ref(),
+            jobs.get_ref(),
+            storage.get_ref(),
+        )
+        .await?;
+
+        if let Some(url) = storage.url(&path, None)? {
+            return Ok(Self::redirect(url));
+        }
 
-        let session_id: String = Utilities::get_cookie_value(cookies.deref(), "X-FDot-Session");
+        let file = NamedFile::open_async(path)
+            .await?
+            .set_content_type(file_extension_to_mime(format.extension()));
 
-        if !session_id.is_empty() {
-            let stream = FFMpegService::get_session_clip_by_id(session_id).await;
-            Ok(stream.into_response(&req))
-        } else {
-            let mut error = ClipError::from(String::from("Invalid URI"));
-            error.set_type(ClipErrorType::InvalidUri);
+        let mut response = file.into_response(&req);
+        // A new merge replaces the file in place, so always revalidate.
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
 
-            Err(error)
-        }
+        Ok(response)
+    }
+
+    async fn get_poster(
+        req: HttpRequest,
+        storage: web::Data<Storage>,
+    ) -> Result<HttpResponse, ClipError> {
+        Self::get_preview(req, Preview::Poster, storage.get_ref()).await
     }
 
-    async fn submit_clip_ws(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
-        let cookies = req.cookies().unwrap();
+    async fn get_sprite(
+        req: HttpRequest,
+        storage: web::Data<Storage>,
+    ) -> Result<HttpResponse, ClipError> {
+        Self::get_preview(req, Preview::Sprite, storage.get_ref()).await
+    }
 
-        let session_id = Utilities::get_cookie_value(cookies.deref(), "X-FDot-Session");
+    async fn get_animated_preview(
+        req: HttpRequest,
+        storage: web::Data<Storage>,
+    ) -> Result<HttpResponse, ClipError> {
+        Self::get_preview(req, Preview::Animated, storage.get_ref()).await
+    }
 
-        if !session_id.is_empty() {
-            let session = ws::WsResponseBuilder::new(
-                ClipWS {
-                    chunks: vec![],
-                    session_id,
-                    clips: vec![],
-                    request_type: ClipRequest::OnPlayback,
-                    hb: Instant::now(),
-                },
-                &req,
-                stream,
-            )
-            .frame_size(10_000_234) //Really important for large streams. Determine Appropriate size.
-            .start();
-            session
-        } else {
-            Err(Error::from(HandshakeError::UnsupportedVersion))
+    async fn get_preview(
+        req: HttpRequest,
+        preview: Preview,
+        storage: &Storage,
+    ) -> Result<HttpResponse, ClipError> {
+        let session_id = SessionService::verify_request(&req)?;
+
+        let path = FFMpegService::get_session_preview(&session_id, preview, storage).await?;
+
+        if let Some(url) = storage.url(&path, None)? {
+            return Ok(Self::redirect(url));
         }
+
+        let file = NamedFile::open_async(path).await?;
+        Ok(file.into_response(&req))
+    }
+
+    async fn export_session(
+        req: HttpRequest,
+        query: web::Query<ExportQuery>,
+        db: web::Data<Database>,
+        jobs: web::Data<JobQueue>,
+        storage: web::Data<Storage>,
+        settings: web::Data<Settings>,
+    ) -> Result<HttpResponse, ClipError> {
+        let session_id = SessionService::verify_request(&req)?;
+
+        let path = ExportService::export(
+            session_id,
+            query.format,
+            settings.get_ref(),
+            db.get_ref(),
+            jobs.get_ref(),
+            storage.get_ref(),
+        )
+        .await?;
+
+        let filename = format!("feedback-{}.{}", session_id, query.format.extension());
+
+        if let Some(url) = storage.url(&path, Some(filename.as_str()))? {
+            return Ok(Self::redirect(url));
+        }
+
+        let file = NamedFile::open_async(path)
+            .await?
+            .set_content_disposition(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(filename)],
+            });
+
+        Ok(file.into_response(&req))
+    }
+
+    /// Queues the latest merge to be rendered again from its clips, e.g. for a reviewer to
+    /// move the camera overlay. Answers with the job, to follow at `GET /jobs/{id}`.
+    async fn render_session(
+        req: HttpRequest,
+        body: web::Json<RenderRequest>,
+        db: web::Data<Database>,
+        jobs: web::Data<JobQueue>,
+        profiles: web::Data<EncodingProfiles>,
+        settings: web::Data<Settings>,
+    ) -> Result<HttpResponse, ClipError> {
+        let session_id = SessionService::verify_request(&req)?;
+        let body = body.into_inner();
+
+        let layout = body.layout.unwrap_or(settings.pip);
+        layout.validate()?;
+
+        let profile = profiles.resolve(body.profile.as_deref())?;
+
+        let merged = match db
+            .get_latest_merged_output(session_id.to_string().as_str())
+            .await?
+        {
+            Some(merged) => merged,
+            None => {
+                let mut error = ClipError::from(String::from("Session has not been merged"));
+                error.set_type(ClipErrorType::InvalidUri);
+
+                return Err(error);
+            }
+        };
+
+        let job = jobs.submit_merge(
+            MergeSpec {
+                clips: merged.clip_requests(),
+                profile,
+                output: merged.output(layout),
+            },
+            session_id,
+            db.get_ref().clone(),
+            None,
+        )?;
+
+        Ok(HttpResponse::Accepted().json(job))
+    }
+
+    /// Sends the browser to a presigned clip store URL. Not cached, as the URL expires.
+    fn redirect(url: String) -> HttpResponse {
+        HttpResponse::TemporaryRedirect()
+            .insert_header((LOCATION, url))
+            .insert_header((CACHE_CONTROL, "no-store"))
+            .finish()
+    }
+
+    async fn get_session_details(
+        req: HttpRequest,
+        db: web::Data<Database>,
+    ) -> Result<HttpResponse, ClipError> {
+        let session_id = SessionService::verify_request(&req)?;
+
+        let session_id = session_id.to_string();
+
+        let session = match db.get_session(session_id.as_str()).await? {
+            Some(session) => session,
+            None => {
+                let mut error = ClipError::from(String::from("Invalid URI"));
+                error.set_type(ClipErrorType::InvalidUri);
+
+                return Err(error);
+            }
+        };
+
+        let clips = db.list_session_clips(session_id.as_str()).await?;
+        let merged_output = db.get_latest_merged_output(session_id.as_str()).await?;
+
+        Ok(HttpResponse::Ok().json(SessionDetailResponse {
+            session,
+            clips,
+            merged_output,
+        }))
+    }
+
+    async fn submit_clip_ws(
+        req: HttpRequest,
+        stream: web::Payload,
+        db: web::Data<Database>,
+        jobs: web::Data<JobQueue>,
+        profiles: web::Data<EncodingProfiles>,
+        storage: web::Data<Storage>,
+        settings: web::Data<Settings>,
+    ) -> Result<HttpResponse, Error> {
+        let session_id = SessionService::verify_request(&req)?;
+        let max_chunk_bytes = settings.upload.max_chunk_bytes;
+        let usage = req
+            .app_data::<web::Data<UploadUsage>>()
+            .expect("UPLOAD_USAGE_NOT_REGISTERED");
+
+        ws::WsResponseBuilder::new(
+            ClipWS {
+                fragments: vec![],
+                jobs: jobs.get_ref().clone(),
+                profiles: profiles.get_ref().clone(),
+                upload_correlation_id: None,
+                session_id,
+                clips: vec![],
+                db: db.get_ref().clone(),
+                storage: storage.get_ref().clone(),
+                usage: usage.get_ref().clone(),
+                settings,
+                request_type: ClipRequest::OnPlayback,
+                hb: Instant::now(),
+            },
+            &req,
+            stream,
+        )
+        .frame_size(max_chunk_bytes)
+        .start()
     }
     async fn start_clip_session_ws(
         req: HttpRequest,
         stream: web::Payload,
+        db: web::Data<Database>,
+        jobs: web::Data<JobQueue>,
+        profiles: web::Data<EncodingProfiles>,
+        storage: web::Data<Storage>,
+        settings: web::Data<Settings>,
     ) -> Result<HttpResponse, Error> {
-        let cookies = req.cookies().unwrap();
-
-        let session_id = Utilities::get_cookie_value(cookies.deref(), "X-FDot-Session");
-
-        if !session_id.is_empty() {
-            let session = ws::WsResponseBuilder::new(
-                ClipWS {
-                    chunks: vec![],
-                    session_id,
-                    clips: vec![],
-                    request_type: ClipRequest::Chunk,
-                    hb: Instant::now(),
-                },
-                &req,
-                stream,
-            )
-            .frame_size(10_000_234) //Really important for large streams. Determine Appropriate size.
-            .start();
-            session
-        } else {
-            Err(Error::from(HandshakeError::UnsupportedVersion))
-        }
+        let session_id = SessionService::verify_request(&req)?;
+        let max_chunk_bytes = settings.upload.max_chunk_bytes;
+        let usage = req
+            .app_data::<web::Data<UploadUsage>>()
+            .expect("UPLOAD_USAGE_NOT_REGISTERED");
+
+        ws::WsResponseBuilder::new(
+            ClipWS {
+                fragments: vec![],
+                jobs: jobs.get_ref().clone(),
+                profiles: profiles.get_ref().clone(),
+                upload_correlation_id: None,
+                session_id,
+                clips: vec![],
+                db: db.get_ref().clone(),
+                storage: storage.get_ref().clone(),
+                usage: usage.get_ref().clone(),
+                settings,
+                request_type: ClipRequest::Chunk,
+                hb: Instant::now(),
+            },
+            &req,
+            stream,
+        )
+        .frame_size(max_chunk_bytes)
+        .start()
     }
 }
@@ -0,0 +1,47 @@
+use actix_web::{web, HttpResponse};
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::helpers::errors::FeedbackError;
+use crate::services::database::Database;
+use crate::services::feedback::FeedbackService;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedbackRequest {
+    pub name: String,
+    pub email: String,
+    pub your_message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedbackResponse {
+    pub id: String,
+}
+
+pub struct FeedbackController {}
+
+impl FeedbackController {
+    pub fn register_routes(cfg: &mut web::ServiceConfig) {
+        cfg.service(
+            web::resource("/feedback")
+                .route(web::post().to(Self::submit_feedback))
+                .route(
+                    web::head().to(|| -> actix_web::HttpResponseBuilder {
+                        HttpResponse::MethodNotAllowed()
+                    }),
+                ),
+        );
+    }
+
+    async fn submit_feedback(
+        db: web::Data<Database>,
+        body: web::Json<FeedbackRequest>,
+    ) -> Result<HttpResponse, FeedbackError> {
+        let feedback = FeedbackService::validate(body.into_inner())?;
+
+        let id = FeedbackService::store(&db, feedback).await?;
+
+        Ok(HttpResponse::Created().json(FeedbackResponse { id }))
+    }
+}
@@ -0,0 +1,46 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use uuid::Uuid;
+
+use crate::helpers::errors::{ClipError, ClipErrorType};
+use crate::services::jobs::JobQueue;
+use crate::services::session::SessionService;
+
+pub struct JobController {}
+
+impl JobController {
+    pub fn register_routes(cfg: &mut web::ServiceConfig) {
+        cfg.service(
+            web::resource("/jobs/{id}")
+                .route(web::get().to(Self::get_job))
+                .route(
+                    web::head().to(|| -> actix_web::HttpResponseBuilder {
+                        HttpResponse::MethodNotAllowed()
+                    }),
+                ),
+        );
+    }
+
+    /// Status of a merge job. Jobs are only visible to the session that queued them.
+    async fn get_job(
+        req: HttpRequest,
+        path: web::Path<String>,
+        jobs: web::Data<JobQueue>,
+    ) -> Result<HttpResponse, ClipError> {
+        let session_id = SessionService::verify_request(&req)?.to_string();
+
+        let job = Uuid::parse_str(path.as_str())
+            .ok()
+            .and_then(|job_id| jobs.get(&job_id))
+            .filter(|job| job.session_id == session_id);
+
+        match job {
+            Some(job) => Ok(HttpResponse::Ok().json(job)),
+            None => {
+                let mut error = ClipError::from(String::from("Invalid URI"));
+                error.set_type(ClipErrorType::InvalidUri);
+
+                Err(error)
+            }
+        }
+    }
+}
@@ -1 +1,4 @@
 pub mod clips;
+pub mod feedback;
+pub mod jobs;
+pub mod session;
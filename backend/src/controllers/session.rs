@@ -0,0 +1,34 @@
+use actix_web::{web, HttpResponse};
+
+use crate::helpers::errors::ClipError;
+use crate::services::database::Database;
+use crate::services::session::SessionService;
+use crate::settings::Settings;
+
+pub struct SessionController {}
+
+impl SessionController {
+    pub fn register_routes(cfg: &mut web::ServiceConfig) {
+        cfg.service(
+            web::resource("/session")
+                .route(web::post().to(Self::create_session))
+                .route(
+                    web::head().to(|| -> actix_web::HttpResponseBuilder {
+                        HttpResponse::MethodNotAllowed()
+                    }),
+                ),
+        );
+    }
+
+    async fn create_session(
+        db: web::Data<Database>,
+        settings: web::Data<Settings>,
+    ) -> Result<HttpResponse, ClipError> {
+        let session =
+            SessionService::issue(settings.session_secret.as_str(), settings.session_ttl())?;
+
+        db.touch_session(session.session_id.as_str()).await?;
+
+        Ok(HttpResponse::Created().json(session))
+    }
+}
@@ -1,9 +1,18 @@
 use actix_http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
+use feedback_protocol::ProtocolError;
 
 #[derive(Debug, Clone)]
 pub enum ClipErrorType {
     InvalidUri,
+    InvalidId,
+    Unauthorized,
+    QuotaExceeded,
+    UnknownProfile,
+    InvalidTrim,
+    UnknownClip,
+    InvalidLayout,
+    MergeRunning,
     Error,
 }
 
@@ -23,6 +32,20 @@ impl ClipError {
     pub fn set_type(&mut self, err_type: ClipErrorType) {
         self.err_type = err_type;
     }
+    pub fn code(&self) -> &'static str {
+        match self.err_type {
+            ClipErrorType::InvalidUri => "invalid_uri",
+            ClipErrorType::InvalidId => "invalid_id",
+            ClipErrorType::Unauthorized => "unauthorized",
+            ClipErrorType::QuotaExceeded => "quota_exceeded",
+            ClipErrorType::UnknownProfile => "unknown_profile",
+            ClipErrorType::InvalidTrim => "invalid_trim",
+            ClipErrorType::UnknownClip => "unknown_clip",
+            ClipErrorType::InvalidLayout => "invalid_layout",
+            ClipErrorType::MergeRunning => "merge_running",
+            ClipErrorType::Error => "error",
+        }
+    }
 }
 
 impl std::fmt::Display for ClipError {
@@ -40,10 +63,59 @@ impl From<String> for ClipError {
     }
 }
 
+impl From<ProtocolError> for ClipError {
+    fn from(err: ProtocolError) -> ClipError {
+        let err_type = match err {
+            ProtocolError::InvalidTrim(_) => ClipErrorType::InvalidTrim,
+            ProtocolError::InvalidLayout(_) => ClipErrorType::InvalidLayout,
+        };
+
+        ClipError {
+            message: Some(err.to_string()),
+            err_type,
+        }
+    }
+}
+
+impl From<std::io::Error> for ClipError {
+    fn from(err: std::io::Error) -> ClipError {
+        ClipError {
+            message: Some(err.to_string()),
+            err_type: ClipErrorType::Error,
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for ClipError {
+    fn from(err: tokio_postgres::Error) -> ClipError {
+        ClipError {
+            message: Some(err.to_string()),
+            err_type: ClipErrorType::Error,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClipError {
+    fn from(err: reqwest::Error) -> ClipError {
+        ClipError {
+            message: Some(err.to_string()),
+            err_type: ClipErrorType::Error,
+        }
+    }
+}
+
 impl ResponseError for ClipError {
     fn status_code(&self) -> actix_http::StatusCode {
         match self.err_type {
             ClipErrorType::InvalidUri => StatusCode::NOT_FOUND,
+            ClipErrorType::InvalidId => StatusCode::BAD_REQUEST,
+            ClipErrorType::Unauthorized => StatusCode::UNAUTHORIZED,
+            ClipErrorType::QuotaExceeded => StatusCode::PAYLOAD_TOO_LARGE,
+            ClipErrorType::UnknownProfile => StatusCode::BAD_REQUEST,
+            ClipErrorType::InvalidTrim => StatusCode::BAD_REQUEST,
+            ClipErrorType::UnknownClip => StatusCode::BAD_REQUEST,
+            ClipErrorType::InvalidLayout => StatusCode::BAD_REQUEST,
+            ClipErrorType::MergeRunning => StatusCode::CONFLICT,
             ClipErrorType::Error => StatusCode::BAD_REQUEST,
         }
     }
@@ -51,3 +123,57 @@ impl ResponseError for ClipError {
         HttpResponse::build(self.status_code()).json(self.message.clone())
     }
 }
+
+#[derive(Debug, Clone)]
+pub enum FeedbackErrorType {
+    Validation,
+    Error,
+}
+
+#[derive(Debug)]
+pub struct FeedbackError {
+    pub message: Option<String>,
+    pub err_type: FeedbackErrorType,
+}
+
+impl FeedbackError {
+    pub fn set_type(&mut self, err_type: FeedbackErrorType) {
+        self.err_type = err_type;
+    }
+}
+
+impl std::fmt::Display for FeedbackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl From<String> for FeedbackError {
+    fn from(err: String) -> FeedbackError {
+        FeedbackError {
+            message: Some(err),
+            err_type: FeedbackErrorType::Error,
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for FeedbackError {
+    fn from(err: tokio_postgres::Error) -> FeedbackError {
+        FeedbackError {
+            message: Some(err.to_string()),
+            err_type: FeedbackErrorType::Error,
+        }
+    }
+}
+
+impl ResponseError for FeedbackError {
+    fn status_code(&self) -> actix_http::StatusCode {
+        match self.err_type {
+            FeedbackErrorType::Validation => StatusCode::UNPROCESSABLE_ENTITY,
+            FeedbackErrorType::Error => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self.message.clone())
+    }
+}
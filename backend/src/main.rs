@@ -2,21 +2,68 @@ extern crate dotenv;
 
 mod controllers;
 mod helpers;
+mod models;
 mod services;
+mod settings;
 
 use controllers::clips::ClipController;
+use controllers::feedback::FeedbackController;
+use controllers::jobs::JobController;
+use controllers::session::SessionController;
 use dotenv::dotenv;
+use services::database::Database;
+use services::jobs::JobQueue;
+use services::profiles::EncodingProfiles;
+use services::retention::RetentionSweeper;
+use services::storage::Storage;
+use services::upload::UploadUsage;
+use settings::Settings;
+
+use actix::Actor;
 
 use actix_cors::Cors;
-use actix_web::{http, App, HttpServer};
+use actix_web::{http, web, App, HttpServer};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let settings = web::Data::new(Settings::load().expect("SETTINGS_INVALID"));
+
+    let db = Database::connect(
+        settings.database_backend,
+        settings.database_url.as_deref().unwrap_or_default(),
+    )
+    .await
+    .expect("DATABASE_CONNECTION_FAILED");
+    db.migrate().await.expect("DATABASE_MIGRATION_FAILED");
+
+    let storage = Storage::from_settings(&settings.storage, &settings.temp_directory)
+        .expect("STORAGE_INVALID");
+    let jobs = JobQueue::new(&settings.ffmpeg, storage.clone());
+    let usage = UploadUsage::default();
+    let profiles = EncodingProfiles::load(&settings.ffmpeg.encoding_profiles)
+        .expect("ENCODING_PROFILES_INVALID");
+
+    RetentionSweeper {
+        policy: settings.retention,
+        session_ttl: settings.session_ttl(),
+        db: db.clone(),
+        jobs: jobs.clone(),
+        storage: storage.clone(),
+        usage: usage.clone(),
+    }
+    .start();
+
+    let server = settings.server.clone();
 
-    HttpServer::new(|| {
-        let cors = Cors::default()
-            .allowed_origin("http://127.0.0.1:42069")
+    HttpServer::new(move || {
+        let cors = settings
+            .server
+            .cors_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
             .allowed_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
             .allowed_headers(vec![
                 http::header::ORIGIN,
@@ -25,14 +72,24 @@ async fn main() -> std::io::Result<()> {
                 http::header::CONTENT_TYPE,
             ]);
 
-        let mut app = App::new().wrap(cors);
+        let mut app = App::new()
+            .wrap(cors)
+            .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(jobs.clone()))
+            .app_data(web::Data::new(profiles.clone()))
+            .app_data(web::Data::new(storage.clone()))
+            .app_data(web::Data::new(usage.clone()))
+            .app_data(settings.clone());
 
         app = app.configure(ClipController::register_routes);
+        app = app.configure(FeedbackController::register_routes);
+        app = app.configure(JobController::register_routes);
+        app = app.configure(SessionController::register_routes);
 
         app
     })
-    .workers(4)
-    .bind(("127.0.0.1", 9011))?
+    .workers(server.workers)
+    .bind((server.host.as_str(), server.port))?
     .run()
     .await
 }
@@ -0,0 +1,150 @@
+use crate::helpers::errors::{ClipError, ClipErrorType};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+const CLIP_ID_PREFIX: &str = "clip-";
+const CLIP_ID_MAX_LENGTH: usize = 64;
+
+/// A validated session id. Always rendered as a hyphenated UUID, so it is safe to use as a
+/// directory name under `TEMP_DIRECTORY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionId(Uuid);
+
+impl SessionId {
+    pub fn generate() -> SessionId {
+        SessionId(Uuid::new_v4())
+    }
+
+    pub fn parse(value: &str) -> Result<SessionId, ClipError> {
+        Uuid::parse_str(value)
+            .map(SessionId)
+            .map_err(|_| invalid_id(format!("Invalid session id: {:?}", value)))
+    }
+
+    /// The session's working directory under `root`, `TEMP_DIRECTORY/<session id>`.
+    pub fn directory(&self, root: &Path) -> PathBuf {
+        root.join(self.to_string())
+    }
+
+    /// A file inside the session directory named `<prefix>-<session id>.<extension>`.
+    pub fn output_path(&self, root: &Path, prefix: &str, extension: &str) -> PathBuf {
+        self.directory(root)
+            .join(format!("{}-{}.{}", prefix, self, extension))
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.hyphenated())
+    }
+}
+
+/// A validated clip id of the form `clip-<alphanumerics>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClipId(String);
+
+impl ClipId {
+    pub fn generate() -> ClipId {
+        ClipId(format!("{}{}", CLIP_ID_PREFIX, Uuid::new_v4().simple()))
+    }
+
+    pub fn parse(value: &str) -> Result<ClipId, ClipError> {
+        let suffix = match value.strip_prefix(CLIP_ID_PREFIX) {
+            Some(suffix) => suffix,
+            None => return Err(invalid_id(format!("Invalid clip id: {:?}", value))),
+        };
+
+        if suffix.is_empty()
+            || value.len() > CLIP_ID_MAX_LENGTH
+            || !suffix.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return Err(invalid_id(format!("Invalid clip id: {:?}", value)));
+        }
+
+        Ok(ClipId(value.to_string()))
+    }
+
+    /// The clip's file inside its session directory.
+    pub fn path(&self, root: &Path, session_id: &SessionId) -> PathBuf {
+        session_id.directory(root).join(format!("{}.webm", self.0))
+    }
+
+    /// Where the chunks of a clip upload are kept until it is finalized.
+    pub fn parts_directory(&self, root: &Path, session_id: &SessionId) -> PathBuf {
+        session_id.directory(root).join(format!("{}.parts", self.0))
+    }
+}
+
+impl fmt::Display for ClipId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn invalid_id(message: String) -> ClipError {
+    let mut error = ClipError::from(message);
+    error.set_type(ClipErrorType::InvalidId);
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_id_round_trips_a_uuid() {
+        let id = SessionId::generate();
+
+        assert_eq!(SessionId::parse(&id.to_string()).unwrap(), id);
+    }
+
+    #[test]
+    fn session_id_rejects_anything_else() {
+        for value in ["", "not-a-uuid", "../etc", "a1cfef81-866d-4ee0-9062"] {
+            assert_eq!(SessionId::parse(value).unwrap_err().code(), "invalid_id");
+        }
+    }
+
+    #[test]
+    fn clip_id_round_trips_a_generated_id() {
+        let id = ClipId::generate();
+
+        assert_eq!(ClipId::parse(&id.to_string()).unwrap(), id);
+    }
+
+    #[test]
+    fn clip_id_rejects_a_missing_prefix_or_suffix() {
+        for value in ["", "clip-", "abc123", "Clip-abc123"] {
+            assert_eq!(ClipId::parse(value).unwrap_err().code(), "invalid_id");
+        }
+    }
+
+    #[test]
+    fn clip_id_rejects_characters_outside_the_suffix_alphabet() {
+        for value in [
+            "clip-abc/123",
+            "clip-../abc",
+            "clip-abc 123",
+            "clip-abc-123",
+            "clip-é",
+        ] {
+            assert_eq!(ClipId::parse(value).unwrap_err().code(), "invalid_id");
+        }
+    }
+
+    #[test]
+    fn clip_id_is_at_most_64_characters() {
+        let longest = format!(
+            "{}{}",
+            CLIP_ID_PREFIX,
+            "a".repeat(CLIP_ID_MAX_LENGTH - CLIP_ID_PREFIX.len())
+        );
+
+        assert!(ClipId::parse(&longest).is_ok());
+        assert_eq!(
+            ClipId::parse(&format!("{}a", longest)).unwrap_err().code(),
+            "invalid_id"
+        );
+    }
+}
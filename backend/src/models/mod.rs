@@ -0,0 +1,212 @@
+pub mod ids;
+pub mod protocol;
+
+use crate::models::ids::SessionId;
+use crate::models::protocol::ClipDetailRequest;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+pub use feedback_protocol::{AudioFormat, ClipMetadata, PipCorner, PipLayout, PipShape, TrimRange};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionRecord {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub submitted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClipRecord {
+    pub id: String,
+    pub session_id: String,
+    pub duration: Option<f64>,
+    pub byte_size: i64,
+    pub metadata: Option<ClipMetadata>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<AudioFormat> for ExportFormat {
+    fn from(format: AudioFormat) -> ExportFormat {
+        match format {
+            AudioFormat::Ogg => ExportFormat::Ogg,
+            AudioFormat::Mp3 => ExportFormat::Mp3,
+        }
+    }
+}
+
+/// What a merge writes to the session's output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeOutput {
+    /// A WebM with the camera overlays laid out as the layout says.
+    Video(PipLayout),
+    /// The clips' audio only, the video of every clip is dropped.
+    Audio(AudioFormat),
+}
+
+impl MergeOutput {
+    /// Kept in the session directory as `output.<extension>`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MergeOutput::Video(_) => "webm",
+            MergeOutput::Audio(format) => format.extension(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MergedOutputRecord {
+    pub id: Uuid,
+    pub session_id: String,
+    pub path: String,
+    pub clip_ids: Vec<String>,
+    /// How each of `clip_ids` was trimmed, `None` for a clip kept whole. Empty for merges
+    /// recorded before clips could be trimmed.
+    pub trims: Vec<Option<TrimRange>>,
+    /// Camera clip drawn over each of `clip_ids`, `None` for a clip without one.
+    pub overlays: Vec<Option<String>>,
+    /// How the overlays were laid out, `None` for merges recorded before layouts were kept.
+    pub layout: Option<PipLayout>,
+    /// Set for voice feedback merged to audio only.
+    pub audio_format: Option<AudioFormat>,
+    pub duration: f64,
+    pub byte_size: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl MergedOutputRecord {
+    /// The clips as the merge asked for them, to render them again.
+    pub fn clip_requests(&self) -> Vec<ClipDetailRequest> {
+        self.clip_ids
+            .iter()
+            .enumerate()
+            .map(|(clip_idx, clip_id)| {
+                let trim = self.trims.get(clip_idx).copied().flatten();
+
+                ClipDetailRequest {
+                    id: clip_id.clone(),
+                    //Only used when the clip's probed duration is unknown
+                    duration: trim.map(|trim| trim.end).unwrap_or_default(),
+                    start: trim.map(|trim| trim.start),
+                    end: trim.map(|trim| trim.end),
+                    overlay: self.overlays.get(clip_idx).cloned().flatten(),
+                }
+            })
+            .collect()
+    }
+
+    /// What the merge wrote, laid out as `layout` if it was a video merge.
+    pub fn output(&self, layout: PipLayout) -> MergeOutput {
+        match self.audio_format {
+            Some(format) => MergeOutput::Audio(format),
+            None => MergeOutput::Video(layout),
+        }
+    }
+}
+
+/// Formats a merged session can be downloaded in from `GET /clip/session/export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// The merged output as encoded.
+    Webm,
+    /// H.264/AAC with faststart, for Safari and tooling that can't read WebM.
+    Mp4,
+    /// The audio only, as Opus in Ogg.
+    Ogg,
+    /// The audio only, as MP3.
+    Mp3,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Webm => "webm",
+            ExportFormat::Mp4 => "mp4",
+            ExportFormat::Ogg => "ogg",
+            ExportFormat::Mp3 => "mp3",
+        }
+    }
+
+    /// The audio format of the audio-only exports.
+    pub fn audio(&self) -> Option<AudioFormat> {
+        match self {
+            ExportFormat::Webm | ExportFormat::Mp4 => None,
+            ExportFormat::Ogg => Some(AudioFormat::Ogg),
+            ExportFormat::Mp3 => Some(AudioFormat::Mp3),
+        }
+    }
+}
+
+/// Images generated from a session's merged output for reviewers to skim.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preview {
+    /// A single frame near the start.
+    Poster,
+    /// A grid of evenly spaced thumbnails.
+    Sprite,
+    /// A looping time-lapse of the whole merge.
+    Animated,
+}
+
+impl Preview {
+    pub const ALL: [Preview; 3] = [Preview::Poster, Preview::Sprite, Preview::Animated];
+
+    /// Kept in the session directory next to the merged output.
+    pub fn path(&self, root: &Path, session_id: &SessionId) -> PathBuf {
+        match self {
+            Preview::Poster => session_id.output_path(root, "poster", "jpg"),
+            Preview::Sprite => session_id.output_path(root, "sprite", "jpg"),
+            Preview::Animated => session_id.output_path(root, "preview", "gif"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedbackRecord {
+    pub id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One `-progress` report from a running merge.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MergeProgress {
+    /// Seconds of output encoded so far.
+    pub out_time: f64,
+    /// Encoding speed relative to real time, when ffmpeg knows it.
+    pub speed: Option<f64>,
+    pub frame: u64,
+    /// `out_time` against the summed clip durations, 0 to 100.
+    pub percent: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// A merge run by the background job queue. Jobs live in memory only.
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeJob {
+    pub id: Uuid,
+    pub session_id: String,
+    pub state: JobState,
+    pub clip_ids: Vec<String>,
+    /// Name of the encoding profile the merge uses.
+    pub profile: String,
+    pub progress: Option<MergeProgress>,
+    pub output: Option<MergedOutputRecord>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
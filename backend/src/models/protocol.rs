@@ -0,0 +1,101 @@
+use crate::helpers::errors::ClipError;
+use crate::models::ids::ClipId;
+use actix_web::web::Bytes;
+
+pub use feedback_protocol::{ClipDetailRequest, ClipEnvelope, ClipMessage, PROTOCOL_VERSION};
+
+/// A binary frame on `/ws/clips`, one chunk of a clip upload.
+///
+/// Laid out as `[clip id length: u8][clip id][seq: u32 big endian][chunk bytes]`.
+#[derive(Debug)]
+pub struct ChunkFrame {
+    pub clip_id: ClipId,
+    pub seq: u32,
+    pub data: Bytes,
+}
+
+impl ChunkFrame {
+    pub fn parse(frame: Bytes) -> Result<ChunkFrame, ClipError> {
+        let malformed = || ClipError::from(String::from("Malformed chunk frame"));
+
+        let id_length = *frame.first().ok_or_else(malformed)? as usize;
+        let header_length = 1 + id_length + 4;
+
+        if frame.len() < header_length {
+            return Err(malformed());
+        }
+
+        let clip_id = std::str::from_utf8(&frame[1..1 + id_length]).map_err(|_| malformed())?;
+        let seq = &frame[1 + id_length..header_length];
+
+        Ok(ChunkFrame {
+            clip_id: ClipId::parse(clip_id)?,
+            seq: u32::from_be_bytes([seq[0], seq[1], seq[2], seq[3]]),
+            data: frame.slice(header_length..),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(clip_id: &[u8], seq: &[u8], data: &[u8]) -> Bytes {
+        let mut frame = vec![clip_id.len() as u8];
+        frame.extend_from_slice(clip_id);
+        frame.extend_from_slice(seq);
+        frame.extend_from_slice(data);
+
+        Bytes::from(frame)
+    }
+
+    #[test]
+    fn chunk_frame_splits_header_and_data() {
+        let chunk = ChunkFrame::parse(frame(b"clip-abc123", &[0, 0, 1, 2], b"webm")).unwrap();
+
+        assert_eq!(chunk.clip_id.to_string(), "clip-abc123");
+        assert_eq!(chunk.seq, 258);
+        assert_eq!(&chunk.data[..], b"webm");
+    }
+
+    #[test]
+    fn chunk_frame_may_carry_no_data() {
+        let chunk = ChunkFrame::parse(frame(b"clip-abc123", &[0, 0, 0, 0], b"")).unwrap();
+
+        assert!(chunk.data.is_empty());
+    }
+
+    #[test]
+    fn chunk_frame_rejects_truncated_headers() {
+        let whole = frame(b"clip-abc123", &[0, 0, 0, 7], b"");
+
+        for length in 0..whole.len() {
+            assert!(
+                ChunkFrame::parse(whole.slice(..length)).is_err(),
+                "{} byte frame parsed",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn chunk_frame_rejects_an_id_length_past_the_frame() {
+        let mut frame = frame(b"clip-abc123", &[0, 0, 0, 0], b"data").to_vec();
+        frame[0] = u8::MAX;
+
+        assert!(ChunkFrame::parse(Bytes::from(frame)).is_err());
+    }
+
+    #[test]
+    fn chunk_frame_rejects_an_oversized_clip_id() {
+        let clip_id = format!("clip-{}", "a".repeat(250));
+        let error = ChunkFrame::parse(frame(clip_id.as_bytes(), &[0, 0, 0, 0], b"data"));
+
+        assert_eq!(error.unwrap_err().code(), "invalid_id");
+    }
+
+    #[test]
+    fn chunk_frame_rejects_a_clip_id_that_is_not_utf8() {
+        assert!(ChunkFrame::parse(frame(&[0xff, 0xfe], &[0, 0, 0, 0], b"data")).is_err());
+    }
+}
@@ -0,0 +1,633 @@
+use crate::models::{
+    AudioFormat, ClipMetadata, ClipRecord, FeedbackRecord, MergedOutputRecord, PipLayout,
+    SessionRecord, TrimRange,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio_postgres::types::Json;
+use tokio_postgres::{Client, NoTls, Row};
+
+/// Schema migrations embedded into the binary, applied in order on startup.
+const MIGRATIONS: &[(i32, &str)] = &[
+    (1, include_str!("../../migrations/0001_initial.sql")),
+    (2, include_str!("../../migrations/0002_clip_metadata.sql")),
+    (
+        3,
+        include_str!("../../migrations/0003_clip_stream_parameters.sql"),
+    ),
+    (
+        4,
+        include_str!("../../migrations/0004_merged_output_trims.sql"),
+    ),
+    (
+        5,
+        include_str!("../../migrations/0005_merged_output_overlays.sql"),
+    ),
+    (
+        6,
+        include_str!("../../migrations/0006_merged_output_audio_format.sql"),
+    ),
+];
+
+/// Where sessions, clips and feedback are kept, `DATABASE_BACKEND`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+    /// PostgreSQL at `DATABASE_URL`.
+    #[default]
+    Postgres,
+    /// Lost on restart, for development only.
+    Memory,
+}
+
+impl FromStr for DatabaseBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<DatabaseBackend, String> {
+        match value {
+            "postgres" => Ok(DatabaseBackend::Postgres),
+            "memory" => Ok(DatabaseBackend::Memory),
+            other => Err(format!(
+                "unknown backend {}, expected postgres or memory",
+                other
+            )),
+        }
+    }
+}
+
+/// A PostgreSQL connection that is opened again once it is lost, so a database restart
+/// only fails the queries made while it is down.
+#[derive(Debug)]
+pub struct PostgresConnection {
+    url: String,
+    client: tokio::sync::Mutex<Arc<Client>>,
+}
+
+impl PostgresConnection {
+    async fn open(url: &str) -> Result<Arc<Client>, tokio_postgres::Error> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+
+        actix_web::rt::spawn(async move {
+            if let Err(err) = connection.await {
+                log::error!("Database connection lost: {}", err);
+            }
+        });
+
+        Ok(Arc::new(client))
+    }
+
+    /// The open client, reconnecting first if the connection was lost.
+    async fn client(&self) -> Result<Arc<Client>, tokio_postgres::Error> {
+        let mut client = self.client.lock().await;
+
+        if client.is_closed() {
+            log::warn!("Reconnecting to the database");
+            *client = Self::open(self.url.as_str()).await?;
+            log::info!("Reconnected to the database");
+        }
+
+        Ok(client.clone())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    sessions: HashMap<String, SessionRecord>,
+    clips: Vec<ClipRecord>,
+    merged_outputs: Vec<MergedOutputRecord>,
+    feedback: Vec<FeedbackRecord>,
+}
+
+/// Persistence layer for sessions, clips, merged outputs and feedback.
+///
+/// Backed by PostgreSQL, or by an in-memory store that is lost on restart when
+/// `DATABASE_BACKEND=memory` asks for one.
+#[derive(Debug, Clone)]
+pub enum Database {
+    Postgres(Arc<PostgresConnection>),
+    Memory(Arc<Mutex<MemoryStore>>),
+}
+
+impl Database {
+    /// Opens the configured backend, `url` is only read by PostgreSQL.
+    pub async fn connect(
+        backend: DatabaseBackend,
+        url: &str,
+    ) -> Result<Database, tokio_postgres::Error> {
+        match backend {
+            DatabaseBackend::Postgres => {
+                let client = PostgresConnection::open(url).await?;
+
+                Ok(Database::Postgres(Arc::new(PostgresConnection {
+                    url: url.to_string(),
+                    client: tokio::sync::Mutex::new(client),
+                })))
+            }
+            DatabaseBackend::Memory => {
+                log::warn!("Using the in-memory database, nothing is kept across restarts");
+                Ok(Database::memory())
+            }
+        }
+    }
+
+    pub fn memory() -> Database {
+        Database::Memory(Arc::new(Mutex::new(MemoryStore::default())))
+    }
+
+    pub async fn migrate(&self) -> Result<(), tokio_postgres::Error> {
+        let client = match self {
+            Database::Postgres(postgres) => postgres.client().await?,
+            Database::Memory(_) => return Ok(()),
+        };
+
+        client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (
+                    version INTEGER PRIMARY KEY,
+                    applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+                )",
+            )
+            .await?;
+
+        for (version, sql) in MIGRATIONS.iter() {
+            let applied = client
+                .query_opt(
+                    "SELECT version FROM schema_migrations WHERE version = $1",
+                    &[version],
+                )
+                .await?;
+
+            if applied.is_some() {
+                continue;
+            }
+
+            client.batch_execute("BEGIN").await?;
+
+            let result = async {
+                client.batch_execute(sql).await?;
+                client
+                    .execute(
+                        "INSERT INTO schema_migrations (version) VALUES ($1)",
+                        &[version],
+                    )
+                    .await
+            }
+            .await;
+
+            match result {
+                Ok(_) => client.batch_execute("COMMIT").await?,
+                Err(err) => {
+                    client.batch_execute("ROLLBACK").await?;
+                    return Err(err);
+                }
+            }
+
+            log::info!("Applied migration {}", version);
+        }
+
+        Ok(())
+    }
+
+    /// Creates the session if it does not exist yet and bumps its last activity.
+    pub async fn touch_session(&self, session_id: &str) -> Result<(), tokio_postgres::Error> {
+        match self {
+            Database::Postgres(postgres) => {
+                let client = postgres.client().await?;
+                client
+                    .execute(
+                        "INSERT INTO sessions (id) VALUES ($1)
+                         ON CONFLICT (id) DO UPDATE SET last_seen_at = now()",
+                        &[&session_id],
+                    )
+                    .await?;
+            }
+            Database::Memory(store) => {
+                let now = Utc::now();
+                let mut store = store.lock().unwrap();
+                store
+                    .sessions
+                    .entry(session_id.to_string())
+                    .and_modify(|session| session.last_seen_at = now)
+                    .or_insert(SessionRecord {
+                        id: session_id.to_string(),
+                        created_at: now,
+                        last_seen_at: now,
+                        submitted_at: None,
+                    });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_session(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<SessionRecord>, tokio_postgres::Error> {
+        match self {
+            Database::Postgres(postgres) => {
+                let client = postgres.client().await?;
+                let row = client
+                    .query_opt(
+                        "SELECT id, created_at, last_seen_at, submitted_at
+                         FROM sessions WHERE id = $1",
+                        &[&session_id],
+                    )
+                    .await?;
+
+                Ok(row.map(|row| SessionRecord {
+                    id: row.get("id"),
+                    created_at: row.get("created_at"),
+                    last_seen_at: row.get("last_seen_at"),
+                    submitted_at: row.get("submitted_at"),
+                }))
+            }
+            Database::Memory(store) => {
+                let store = store.lock().unwrap();
+                Ok(store.sessions.get(session_id).cloned())
+            }
+        }
+    }
+
+    /// Deletes the session along with its clips and merged outputs.
+    pub async fn delete_session(&self, session_id: &str) -> Result<(), tokio_postgres::Error> {
+        match self {
+            Database::Postgres(postgres) => {
+                let client = postgres.client().await?;
+                client
+                    .execute("DELETE FROM sessions WHERE id = $1", &[&session_id])
+                    .await?;
+            }
+            Database::Memory(store) => {
+                let mut store = store.lock().unwrap();
+                store.sessions.remove(session_id);
+                store.clips.retain(|clip| clip.session_id != session_id);
+                store
+                    .merged_outputs
+                    .retain(|output| output.session_id != session_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Inserts the clip, or refreshes its size and metadata if it is already recorded.
+    pub async fn insert_clip(&self, clip: ClipRecord) -> Result<(), tokio_postgres::Error> {
+        self.touch_session(clip.session_id.as_str()).await?;
+
+        match self {
+            Database::Postgres(postgres) => {
+                let client = postgres.client().await?;
+                let container = clip.metadata.as_ref().map(|m| m.container.clone());
+                let metadata = clip.metadata.clone().unwrap_or_default();
+
+                client
+                    .execute(
+                        "INSERT INTO clips (id, session_id, duration, byte_size, created_at,
+                            container, video_codec, audio_codec, width, height, frame_rate,
+                            audio_channels, time_base, pixel_format, audio_sample_rate)
+                         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14,
+                            $15)
+                         ON CONFLICT (id) DO UPDATE SET
+                            duration = EXCLUDED.duration,
+                            byte_size = EXCLUDED.byte_size,
+                            container = EXCLUDED.container,
+                            video_codec = EXCLUDED.video_codec,
+                            audio_codec = EXCLUDED.audio_codec,
+                            width = EXCLUDED.width,
+                            height = EXCLUDED.height,
+                            frame_rate = EXCLUDED.frame_rate,
+                            audio_channels = EXCLUDED.audio_channels,
+                            time_base = EXCLUDED.time_base,
+                            pixel_format = EXCLUDED.pixel_format,
+                            audio_sample_rate = EXCLUDED.audio_sample_rate",
+                        &[
+                            &clip.id,
+                            &clip.session_id,
+                            &clip.duration,
+                            &clip.byte_size,
+                            &clip.created_at,
+                            &container,
+                            &metadata.video_codec,
+                            &metadata.audio_codec,
+                            &metadata.width,
+                            &metadata.height,
+                            &metadata.frame_rate,
+                            &metadata.audio_channels,
+                            &metadata.time_base,
+                            &metadata.pixel_format,
+                            &metadata.audio_sample_rate,
+                        ],
+                    )
+                    .await?;
+            }
+            Database::Memory(store) => {
+                let mut store = store.lock().unwrap();
+                store.clips.retain(|existing| existing.id != clip.id);
+                store.clips.push(clip);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_clip(
+        &self,
+        session_id: &str,
+        clip_id: &str,
+    ) -> Result<Option<ClipRecord>, tokio_postgres::Error> {
+        match self {
+            Database::Postgres(postgres) => {
+                let client = postgres.client().await?;
+                let row = client
+                    .query_opt(
+                        "SELECT * FROM clips WHERE session_id = $1 AND id = $2",
+                        &[&session_id, &clip_id],
+                    )
+                    .await?;
+
+                Ok(row.as_ref().map(Self::clip_from_row))
+            }
+            Database::Memory(store) => {
+                let store = store.lock().unwrap();
+                Ok(store
+                    .clips
+                    .iter()
+                    .find(|clip| clip.session_id == session_id && clip.id == clip_id)
+                    .cloned())
+            }
+        }
+    }
+
+    pub async fn list_session_clips(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<ClipRecord>, tokio_postgres::Error> {
+        match self {
+            Database::Postgres(postgres) => {
+                let client = postgres.client().await?;
+                let rows = client
+                    .query(
+                        "SELECT * FROM clips WHERE session_id = $1 ORDER BY created_at",
+                        &[&session_id],
+                    )
+                    .await?;
+
+                Ok(rows.iter().map(Self::clip_from_row).collect())
+            }
+            Database::Memory(store) => {
+                let store = store.lock().unwrap();
+                Ok(store
+                    .clips
+                    .iter()
+                    .filter(|clip| clip.session_id == session_id)
+                    .cloned()
+                    .collect())
+            }
+        }
+    }
+
+    /// Records a merged output and marks its session as submitted.
+    pub async fn insert_merged_output(
+        &self,
+        output: MergedOutputRecord,
+    ) -> Result<(), tokio_postgres::Error> {
+        self.touch_session(output.session_id.as_str()).await?;
+
+        match self {
+            Database::Postgres(postgres) => {
+                let client = postgres.client().await?;
+                client
+                    .execute(
+                        "INSERT INTO merged_outputs
+                            (id, session_id, path, clip_ids, trims, overlays, layout,
+                             audio_format, duration, byte_size, created_at)
+                         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+                        &[
+                            &output.id,
+                            &output.session_id,
+                            &output.path,
+                            &output.clip_ids,
+                            &Json(&output.trims),
+                            &Json(&output.overlays),
+                            &output.layout.as_ref().map(Json),
+                            &output.audio_format.as_ref().map(Json),
+                            &output.duration,
+                            &output.byte_size,
+                            &output.created_at,
+                        ],
+                    )
+                    .await?;
+                client
+                    .execute(
+                        "UPDATE sessions SET submitted_at = $2 WHERE id = $1",
+                        &[&output.session_id, &output.created_at],
+                    )
+                    .await?;
+            }
+            Database::Memory(store) => {
+                let mut store = store.lock().unwrap();
+                if let Some(session) = store.sessions.get_mut(output.session_id.as_str()) {
+                    session.submitted_at = Some(output.created_at);
+                }
+                store.merged_outputs.push(output);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_latest_merged_output(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<MergedOutputRecord>, tokio_postgres::Error> {
+        match self {
+            Database::Postgres(postgres) => {
+                let client = postgres.client().await?;
+                let row = client
+                    .query_opt(
+                        "SELECT id, session_id, path, clip_ids, trims, overlays, layout,
+                                audio_format, duration, byte_size, created_at
+                         FROM merged_outputs WHERE session_id = $1
+                         ORDER BY created_at DESC LIMIT 1",
+                        &[&session_id],
+                    )
+                    .await?;
+
+                Ok(row.map(|row| MergedOutputRecord {
+                    id: row.get("id"),
+                    session_id: row.get("session_id"),
+                    path: row.get("path"),
+                    clip_ids: row.get("clip_ids"),
+                    trims: row.get::<_, Json<Vec<Option<TrimRange>>>>("trims").0,
+                    overlays: row.get::<_, Json<Vec<Option<String>>>>("overlays").0,
+                    layout: row
+                        .get::<_, Option<Json<PipLayout>>>("layout")
+                        .map(|layout| layout.0),
+                    audio_format: row
+                        .get::<_, Option<Json<AudioFormat>>>("audio_format")
+                        .map(|format| format.0),
+                    duration: row.get("duration"),
+                    byte_size: row.get("byte_size"),
+                    created_at: row.get("created_at"),
+                }))
+            }
+            Database::Memory(store) => {
+                let store = store.lock().unwrap();
+                Ok(store
+                    .merged_outputs
+                    .iter()
+                    .filter(|output| output.session_id == session_id)
+                    .max_by_key(|output| output.created_at)
+                    .cloned())
+            }
+        }
+    }
+
+    pub async fn insert_feedback(
+        &self,
+        feedback: &FeedbackRecord,
+    ) -> Result<(), tokio_postgres::Error> {
+        match self {
+            Database::Postgres(postgres) => {
+                let client = postgres.client().await?;
+                client
+                    .execute(
+                        "INSERT INTO feedback (id, name, email, message, created_at)
+                         VALUES ($1, $2, $3, $4, $5)",
+                        &[
+                            &feedback.id,
+                            &feedback.name,
+                            &feedback.email,
+                            &feedback.message,
+                            &feedback.created_at,
+                        ],
+                    )
+                    .await?;
+            }
+            Database::Memory(store) => {
+                store.lock().unwrap().feedback.push(feedback.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn clip_from_row(row: &Row) -> ClipRecord {
+        let duration: Option<f64> = row.get("duration");
+        let container: Option<String> = row.get("container");
+
+        ClipRecord {
+            id: row.get("id"),
+            session_id: row.get("session_id"),
+            duration,
+            byte_size: row.get("byte_size"),
+            metadata: container.map(|container| ClipMetadata {
+                container,
+                video_codec: row.get("video_codec"),
+                audio_codec: row.get("audio_codec"),
+                width: row.get("width"),
+                height: row.get("height"),
+                frame_rate: row.get("frame_rate"),
+                duration: duration.unwrap_or_default(),
+                audio_channels: row.get("audio_channels"),
+                time_base: row.get("time_base"),
+                pixel_format: row.get("pixel_format"),
+                audio_sample_rate: row.get("audio_sample_rate"),
+            }),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration};
+    use uuid::Uuid;
+
+    fn clip(id: &str, session_id: &str, byte_size: i64) -> ClipRecord {
+        ClipRecord {
+            id: id.to_string(),
+            session_id: session_id.to_string(),
+            duration: Some(1.0),
+            byte_size,
+            metadata: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn merged_output(session_id: &str, created_at: DateTime<Utc>) -> MergedOutputRecord {
+        MergedOutputRecord {
+            id: Uuid::new_v4(),
+            session_id: session_id.to_string(),
+            path: String::from("output.webm"),
+            clip_ids: vec![String::from("clip-a")],
+            trims: vec![None],
+            overlays: vec![None],
+            layout: None,
+            audio_format: None,
+            duration: 1.0,
+            byte_size: 1,
+            created_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn inserting_a_recorded_clip_again_replaces_it() {
+        let db = Database::memory();
+
+        db.insert_clip(clip("clip-a", "session", 1)).await.unwrap();
+        db.insert_clip(clip("clip-a", "session", 2)).await.unwrap();
+
+        let clips = db.list_session_clips("session").await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].byte_size, 2);
+    }
+
+    #[tokio::test]
+    async fn latest_merged_output_is_the_most_recently_created() {
+        let db = Database::memory();
+        let now = Utc::now();
+        let latest = merged_output("session", now);
+
+        db.insert_merged_output(latest.clone()).await.unwrap();
+        db.insert_merged_output(merged_output("session", now - Duration::minutes(1)))
+            .await
+            .unwrap();
+        db.insert_merged_output(merged_output("other", now + Duration::minutes(1)))
+            .await
+            .unwrap();
+
+        let found = db
+            .get_latest_merged_output("session")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, latest.id);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_session_drops_its_clips_and_merged_outputs() {
+        let db = Database::memory();
+        db.insert_clip(clip("clip-a", "session", 1)).await.unwrap();
+        db.insert_clip(clip("clip-b", "other", 1)).await.unwrap();
+        db.insert_merged_output(merged_output("session", Utc::now()))
+            .await
+            .unwrap();
+
+        db.delete_session("session").await.unwrap();
+
+        assert!(db.get_session("session").await.unwrap().is_none());
+        assert!(db.list_session_clips("session").await.unwrap().is_empty());
+        assert!(db
+            .get_latest_merged_output("session")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(db.list_session_clips("other").await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::helpers::errors::{ClipError, ClipErrorType};
+use crate::models::ids::SessionId;
+use crate::models::ExportFormat;
+use crate::services::database::Database;
+use crate::services::ffmpeg::{ConcatInput, FFMpegService};
+use crate::services::jobs::JobQueue;
+use crate::services::storage::Storage;
+use crate::settings::Settings;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Produces downloadable copies of a session's latest merged output. Exports are kept
+/// next to the merged output, published to the clip store and only rebuilt once the session
+/// is merged again.
+pub struct ExportService {}
+
+impl ExportService {
+    /// The session's latest merge in `format`, encoding it first if need be. Any merge can
+    /// be fetched as audio only, voice feedback is already merged to one audio format.
+    /// Fails with `InvalidUri` until the session has been merged, and for video formats of
+    /// a merge that kept only the audio.
+    pub async fn export(
+        session_id: SessionId,
+        format: ExportFormat,
+        settings: &Settings,
+        db: &Database,
+        jobs: &JobQueue,
+        storage: &Storage,
+    ) -> Result<PathBuf, ClipError> {
+        let root = storage.temp_directory();
+
+        let merged = match db
+            .get_latest_merged_output(session_id.to_string().as_str())
+            .await?
+        {
+            Some(merged) => merged,
+            None => return Err(Self::not_merged()),
+        };
+
+        let merged_path = match merged.audio_format {
+            Some(_) if format.audio().is_none() => {
+                let mut error = ClipError::from(String::from("Session was merged as audio only"));
+                error.set_type(ClipErrorType::InvalidUri);
+
+                return Err(error);
+            }
+            Some(audio_format) => {
+                let merged_path = session_id.output_path(root, "output", audio_format.extension());
+
+                if ExportFormat::from(audio_format) == format {
+                    if !storage.stage(&merged_path).await? {
+                        return Err(Self::not_merged());
+                    }
+
+                    return Ok(merged_path);
+                }
+
+                merged_path
+            }
+            None => {
+                let merged_path = session_id.output_path(root, "output", "webm");
+
+                if format == ExportFormat::Webm {
+                    if !storage.stage(&merged_path).await? {
+                        return Err(Self::not_merged());
+                    }
+
+                    return Ok(merged_path);
+                }
+
+                merged_path
+            }
+        };
+
+        let export = session_id.output_path(root, "export", format.extension());
+        // Only the merged output is fetched, a fetched export would always look current.
+        storage.stage(&merged_path).await?;
+
+        if Self::is_current(&export, &merged_path).await {
+            return Ok(export);
+        }
+
+        let _permit = jobs.reserve().await?;
+
+        // Another request may have built it while this one waited for a slot.
+        if Self::is_current(&export, &merged_path).await {
+            return Ok(export);
+        }
+
+        let stored = db
+            .list_session_clips(session_id.to_string().as_str())
+            .await?;
+
+        // Encoding from the original clips avoids a second generation of VP9 loss.
+        let sources = match FFMpegService::concat_inputs(
+            &merged.clip_ids,
+            &merged.trims,
+            &merged.overlays,
+            &session_id,
+            &stored,
+            storage,
+        )
+        .await
+        {
+            Ok(inputs) => inputs,
+            Err(_) if Self::is_file(&merged_path).await => vec![ConcatInput {
+                path: merged_path,
+                metadata: None,
+                trim: None,
+                overlay: None,
+            }],
+            Err(_) => return Err(Self::not_merged()),
+        };
+
+        let partial = export.with_extension(format!("{}.partial", format.extension()));
+
+        let layout = merged.layout.unwrap_or(settings.pip);
+
+        let ffmpeg = &settings.ffmpeg;
+        let result = match format.audio() {
+            Some(audio_format) => {
+                FFMpegService::export_audio(
+                    &sources,
+                    audio_format,
+                    merged.duration,
+                    &partial,
+                    ffmpeg,
+                )
+                .await
+            }
+            None => {
+                FFMpegService::export_mp4(&sources, &layout, merged.duration, &partial, ffmpeg)
+                    .await
+            }
+        };
+
+        if let Err(err) = result {
+            if fs::metadata(&partial).await.is_ok() {
+                fs::remove_file(&partial).await?;
+            }
+
+            return Err(err);
+        }
+
+        fs::rename(&partial, &export).await?;
+
+        storage.publish(&export).await?;
+
+        Ok(export)
+    }
+
+    /// Whether `export` exists and was written after the merged output it was made from.
+    async fn is_current(export: &Path, merged: &Path) -> bool {
+        let exported_at = match fs::metadata(export).await.and_then(|meta| meta.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return false,
+        };
+
+        match fs::metadata(merged).await.and_then(|meta| meta.modified()) {
+            Ok(merged_at) => exported_at >= merged_at,
+            Err(_) => true,
+        }
+    }
+
+    async fn is_file(path: &Path) -> bool {
+        fs::metadata(path)
+            .await
+            .map(|metadata| metadata.is_file())
+            .unwrap_or(false)
+    }
+
+    fn not_merged() -> ClipError {
+        let mut error = ClipError::from(String::from("Session has not been merged"));
+        error.set_type(ClipErrorType::InvalidUri);
+        error
+    }
+}
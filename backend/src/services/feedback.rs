@@ -0,0 +1,133 @@
+use crate::controllers::feedback::FeedbackRequest;
+use crate::helpers::errors::{FeedbackError, FeedbackErrorType};
+use crate::models::FeedbackRecord;
+use crate::services::database::Database;
+use chrono::Utc;
+use regex::Regex;
+use std::sync::LazyLock;
+use uuid::Uuid;
+
+const MAX_NAME_LENGTH: usize = 120;
+const MAX_EMAIL_LENGTH: usize = 254;
+const MAX_MESSAGE_LENGTH: usize = 5000;
+
+static EMAIL_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[^@\s]+@[^@\s]+\.[^@\s]+$").unwrap());
+
+pub struct FeedbackService {}
+
+impl FeedbackService {
+    pub fn validate(request: FeedbackRequest) -> Result<FeedbackRecord, FeedbackError> {
+        let name = request.name.trim().to_string();
+        let email = request.email.trim().to_string();
+        let message = request.your_message.trim().to_string();
+
+        if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+            return Err(Self::validation_error(format!(
+                "name must be between 1 and {} characters",
+                MAX_NAME_LENGTH
+            )));
+        }
+
+        if email.len() > MAX_EMAIL_LENGTH || !EMAIL_PATTERN.is_match(email.as_str()) {
+            return Err(Self::validation_error(String::from(
+                "email must be a valid email address",
+            )));
+        }
+
+        if message.is_empty() || message.chars().count() > MAX_MESSAGE_LENGTH {
+            return Err(Self::validation_error(format!(
+                "message must be between 1 and {} characters",
+                MAX_MESSAGE_LENGTH
+            )));
+        }
+
+        Ok(FeedbackRecord {
+            id: Uuid::new_v4(),
+            name,
+            email,
+            message,
+            created_at: Utc::now(),
+        })
+    }
+
+    pub async fn store(db: &Database, feedback: FeedbackRecord) -> Result<String, FeedbackError> {
+        db.insert_feedback(&feedback).await?;
+
+        Ok(feedback.id.to_string())
+    }
+
+    fn validation_error(message: String) -> FeedbackError {
+        let mut error = FeedbackError::from(message);
+        error.set_type(FeedbackErrorType::Validation);
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(name: &str, email: &str, message: &str) -> FeedbackRequest {
+        FeedbackRequest {
+            name: name.to_string(),
+            email: email.to_string(),
+            your_message: message.to_string(),
+        }
+    }
+
+    fn rejection(request: FeedbackRequest) -> String {
+        let err = FeedbackService::validate(request).unwrap_err();
+        assert!(matches!(err.err_type, FeedbackErrorType::Validation));
+
+        err.message.unwrap()
+    }
+
+    #[test]
+    fn validate_trims_fields() {
+        let record =
+            FeedbackService::validate(request("  Ada ", " ada@example.org ", "\nHello\n")).unwrap();
+
+        assert_eq!(record.name, "Ada");
+        assert_eq!(record.email, "ada@example.org");
+        assert_eq!(record.message, "Hello");
+    }
+
+    #[test]
+    fn validate_rejects_blank_name() {
+        assert_eq!(
+            rejection(request(" \t ", "ada@example.org", "Hello")),
+            "name must be between 1 and 120 characters"
+        );
+    }
+
+    #[test]
+    fn validate_rejects_bad_email() {
+        for email in [
+            "",
+            "ada",
+            "ada@example",
+            "ada@@example.org",
+            "a da@example.org",
+        ] {
+            assert_eq!(
+                rejection(request("Ada", email, "Hello")),
+                "email must be a valid email address",
+                "{}",
+                email
+            );
+        }
+    }
+
+    #[test]
+    fn validate_message_length() {
+        let longest = "x".repeat(MAX_MESSAGE_LENGTH);
+        assert!(FeedbackService::validate(request("Ada", "ada@example.org", &longest)).is_ok());
+
+        let too_long = "x".repeat(MAX_MESSAGE_LENGTH + 1);
+        assert_eq!(
+            rejection(request("Ada", "ada@example.org", &too_long)),
+            "message must be between 1 and 5000 characters"
+        );
+    }
+}
@@ -1,156 +1,1548 @@
-use crate::{controllers::clips::ClipDetailRequest, helpers::utilities::Utilities};
-use actix_files::NamedFile;
-use std::io::{BufRead, BufReader};
-use std::process::{Command, Stdio};
-use std::{
-    fs,
-    io::{Error, ErrorKind},
-    path::Path,
+use crate::helpers::errors::{ClipError, ClipErrorType};
+use crate::models::ids::{ClipId, SessionId};
+use crate::models::protocol::ClipDetailRequest;
+use crate::models::{
+    AudioFormat, ClipMetadata, ClipRecord, MergeOutput, MergeProgress, MergedOutputRecord,
+    PipCorner, PipLayout, PipShape, Preview, TrimRange,
 };
+use crate::services::database::Database;
+use crate::services::profiles::EncodingProfile;
+use crate::services::storage::Storage;
+use crate::settings::FFmpegSettings;
+use chrono::Utc;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use uuid::Uuid;
+
+const MP4_PRESET: &str = "veryfast";
+const MP4_CRF: &str = "23";
+const MP4_AUDIO_BITRATE: &str = "128k";
+/// Voice feedback is speech, Opus keeps it clear well below music bitrates.
+const OPUS_BITRATE: &str = "64k";
+const MP3_BITRATE: &str = "128k";
+
+/// Sprite sheets are a `SPRITE_COLUMNS` x `SPRITE_ROWS` grid of evenly spaced thumbnails.
+const SPRITE_COLUMNS: u32 = 5;
+const SPRITE_ROWS: u32 = 2;
+const THUMBNAIL_WIDTH: u32 = 160;
+/// The animated preview shows this many evenly spaced frames at `PREVIEW_FRAME_RATE`.
+const PREVIEW_FRAMES: u32 = 20;
+const PREVIEW_FRAME_RATE: u32 = 5;
+const PREVIEW_WIDTH: u32 = 320;
+
+/// Used for black frames and silence when no clip in the merge says otherwise.
+const PLACEHOLDER_SIZE: (i32, i32) = (1280, 720);
+const PLACEHOLDER_FRAME_RATE: u32 = 30;
+const PLACEHOLDER_SAMPLE_RATE: i32 = 48000;
 
 pub struct FFMpegService {}
 
+/// What a merge joins and how it writes it.
+#[derive(Debug, Clone)]
+pub struct MergeSpec {
+    pub clips: Vec<ClipDetailRequest>,
+    pub profile: EncodingProfile,
+    pub output: MergeOutput,
+}
+
+/// A file to join, with what ffprobe recorded about it, the part of it to keep and the
+/// camera clip drawn over it. Files without metadata, such as a merged output, are taken to
+/// have both streams.
+pub struct ConcatInput {
+    pub path: PathBuf,
+    pub metadata: Option<ClipMetadata>,
+    pub trim: Option<TrimRange>,
+    /// Recorded at the same time and cut to the same trim.
+    pub overlay: Option<Box<ConcatInput>>,
+}
+
+impl ConcatInput {
+    /// Length of the part that is kept.
+    fn duration(&self) -> f64 {
+        match (&self.trim, &self.metadata) {
+            (Some(trim), _) => trim.duration(),
+            (None, Some(clip)) => clip.duration,
+            (None, None) => 0.0,
+        }
+    }
+
+    fn has_video(&self) -> bool {
+        self.metadata
+            .as_ref()
+            .map(|clip| clip.video_codec.is_some())
+            .unwrap_or(true)
+    }
+
+    fn has_audio(&self) -> bool {
+        self.metadata
+            .as_ref()
+            .map(|clip| clip.audio_codec.is_some())
+            .unwrap_or(true)
+    }
+}
+
 impl FFMpegService {
-    pub async fn get_session_clip_by_id(session_id: String) -> NamedFile {
-        let mut temp_directory = dotenv::var("TEMP_DIRECTORY").expect("TEMP_DIRECTORY_NOT_SET");
-        temp_directory.push_str(session_id.as_str());
+    /// The staged preview file, fetched from the clip store if it isn't on disk.
+    pub async fn get_session_preview(
+        session_id: &SessionId,
+        preview: Preview,
+        storage: &Storage,
+    ) -> Result<PathBuf, ClipError> {
+        let path = preview.path(storage.temp_directory(), session_id);
+
+        if !storage.stage(&path).await? {
+            let mut error = ClipError::from(String::from("No preview for this session"));
+            error.set_type(ClipErrorType::InvalidUri);
+
+            return Err(error);
+        }
+
+        Ok(path)
+    }
+
+    /// Merges the clips into the session's output, calling `on_progress` with each report
+    /// from ffmpeg's `-progress` output. Each clip is cut to its `start` and `end` and, for
+    /// a video output, gets its camera overlay drawn over it as the layout says. Untrimmed
+    /// clips without overlays that already match `profile` are stream copied, anything else
+    /// is encoded with it. Audio outputs keep only the clips' audio and ignore `profile`.
+    /// Clips are staged from the clip store and the output is published back to it.
+    pub async fn merge_clips<F>(
+        spec: &MergeSpec,
+        session_id: &SessionId,
+        ffmpeg: &FFmpegSettings,
+        db: &Database,
+        storage: &Storage,
+        on_progress: F,
+    ) -> Result<MergedOutputRecord, ClipError>
+    where
+        F: Fn(MergeProgress),
+    {
+        //Probed durations win over what the client reports
+        let stored = db
+            .list_session_clips(session_id.to_string().as_str())
+            .await?;
+
+        let mut total_duration = 0.0;
+        let mut trims = vec![];
+
+        for clip in &spec.clips {
+            let duration = stored
+                .iter()
+                .find(|record| record.id == clip.id)
+                .and_then(|record| record.duration)
+                .unwrap_or(clip.duration);
+            let trim = clip.trim(duration)?;
+
+            total_duration += trim.map(|trim| trim.duration()).unwrap_or(duration);
+            trims.push(trim);
+        }
+
+        let clip_ids: Vec<String> = spec.clips.iter().map(|clip| clip.id.clone()).collect();
+        let overlays: Vec<Option<String>> =
+            spec.clips.iter().map(|clip| clip.overlay.clone()).collect();
+        let inputs =
+            Self::concat_inputs(&clip_ids, &trims, &overlays, session_id, &stored, storage).await?;
+
+        let output =
+            session_id.output_path(storage.temp_directory(), "output", spec.output.extension());
+        //Renamed over the output once complete, so a failed merge leaves the last one in place
+        let partial = output.with_extension(format!("{}.partial", spec.output.extension()));
+
+        let result =
+            Self::encode_merge(&inputs, spec, &partial, total_duration, ffmpeg, on_progress).await;
+
+        if let Err(err) = result {
+            if fs::metadata(&partial).await.is_ok() {
+                fs::remove_file(&partial).await?;
+            }
+
+            return Err(err);
+        }
+
+        fs::rename(&partial, &output).await?;
+
+        let metadata = fs::metadata(&output).await?;
+        if metadata.len() == 0 {
+            log::warn!("Merge of session {} wrote an empty output", session_id);
+        }
+
+        storage.publish(&output).await?;
+
+        let record = MergedOutputRecord {
+            id: Uuid::new_v4(),
+            session_id: session_id.to_string(),
+            path: output.display().to_string(),
+            clip_ids,
+            trims,
+            overlays,
+            layout: match spec.output {
+                MergeOutput::Video(layout) => Some(layout),
+                MergeOutput::Audio(_) => None,
+            },
+            audio_format: match spec.output {
+                MergeOutput::Video(_) => None,
+                MergeOutput::Audio(format) => Some(format),
+            },
+            duration: total_duration,
+            byte_size: metadata.len() as i64,
+            created_at: Utc::now(),
+        };
+
+        if let Err(err) = db.insert_merged_output(record.clone()).await {
+            log::error!("Merge of session {} was not recorded: {}", session_id, err);
+        }
 
-        let output = format!(
-            "{}/output-{}.webm",
-            temp_directory.clone(),
-            session_id.clone()
+        Ok(record)
+    }
+
+    /// Runs the ffmpeg steps of a merge, writing to `output`.
+    async fn encode_merge<F>(
+        inputs: &[ConcatInput],
+        spec: &MergeSpec,
+        output: &Path,
+        total_duration: f64,
+        ffmpeg: &FFmpegSettings,
+        on_progress: F,
+    ) -> Result<(), ClipError>
+    where
+        F: Fn(MergeProgress),
+    {
+        let profile = &spec.profile;
+
+        match &spec.output {
+            MergeOutput::Video(layout) => {
+                let mut copied = false;
+
+                if Self::can_stream_copy(inputs, profile) {
+                    match Self::concat_copy(inputs, output, total_duration, ffmpeg, &on_progress)
+                        .await
+                    {
+                        Ok(()) => copied = true,
+                        Err(err) => log::warn!("Stream copy failed, re-encoding: {}", err),
+                    }
+                }
+
+                if !copied {
+                    let mut args = Self::concat_args(inputs, layout, profile.scale_filter());
+
+                    args.extend(profile.ffmpeg_args());
+
+                    args.extend(Self::webm_output_args(output));
+
+                    Self::run(ffmpeg, args, total_duration, on_progress).await?;
+                }
+            }
+            MergeOutput::Audio(format) => {
+                let mut args = Self::audio_concat_args(inputs);
+
+                args.extend(Self::audio_output_args(*format, output));
+
+                Self::run(ffmpeg, args, total_duration, on_progress).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the poster frame, sprite sheet and animated preview of the session's merged
+    /// output next to it, replacing those of any earlier merge, and publishes them.
+    pub async fn generate_previews(
+        session_id: &SessionId,
+        duration: f64,
+        ffmpeg: &FFmpegSettings,
+        storage: &Storage,
+    ) -> Result<(), ClipError> {
+        let root = storage.temp_directory();
+        let merged = session_id
+            .output_path(root, "output", "webm")
+            .display()
+            .to_string();
+
+        for preview in Preview::ALL {
+            let path = preview.path(root, session_id);
+
+            if fs::metadata(&path).await.is_ok() {
+                fs::remove_file(&path).await?;
+            }
+        }
+
+        // Some recorders leave the duration unknown, treat those as one second long.
+        let duration = if duration > 0.0 { duration } else { 1.0 };
+
+        let mut poster = Self::base_args();
+        poster.extend([
+            "-ss".to_string(),
+            format!("{:.3}", (duration / 2.0).min(1.0)),
+            "-i".to_string(),
+            merged.clone(),
+            "-frames:v".to_string(),
+            "1".to_string(),
+            "-q:v".to_string(),
+            "3".to_string(),
+        ]);
+        poster.push(Preview::Poster.path(root, session_id).display().to_string());
+
+        Self::run(ffmpeg, poster, 0.0, |_| {}).await?;
+
+        let thumbnails = SPRITE_COLUMNS * SPRITE_ROWS;
+
+        let mut sprite = Self::base_args();
+        sprite.extend([
+            "-i".to_string(),
+            merged.clone(),
+            "-vf".to_string(),
+            format!(
+                "fps={:.6},scale={}:-2,tile={}x{}",
+                thumbnails as f64 / duration,
+                THUMBNAIL_WIDTH,
+                SPRITE_COLUMNS,
+                SPRITE_ROWS
+            ),
+            "-frames:v".to_string(),
+            "1".to_string(),
+            "-q:v".to_string(),
+            "3".to_string(),
+        ]);
+        sprite.push(Preview::Sprite.path(root, session_id).display().to_string());
+
+        Self::run(ffmpeg, sprite, 0.0, |_| {}).await?;
+
+        // GIF rather than WebP, animated WebP needs an ffmpeg built with libwebp.
+        let mut preview = Self::base_args();
+        preview.extend([
+            "-i".to_string(),
+            merged,
+            "-filter_complex".to_string(),
+            format!(
+                "fps={:.6},scale={}:-2:flags=lanczos,setpts=N/{}/TB,split[frames][copy];\
+                     [copy]palettegen[palette];[frames][palette]paletteuse",
+                PREVIEW_FRAMES as f64 / duration,
+                PREVIEW_WIDTH,
+                PREVIEW_FRAME_RATE
+            ),
+            "-r".to_string(),
+            PREVIEW_FRAME_RATE.to_string(),
+            "-loop".to_string(),
+            "0".to_string(),
+            "-an".to_string(),
+        ]);
+        preview.push(
+            Preview::Animated
+                .path(root, session_id)
+                .display()
+                .to_string(),
         );
 
-        NamedFile::open_async(output).await.unwrap()
+        Self::run(ffmpeg, preview, 0.0, |_| {}).await?;
+
+        for preview in Preview::ALL {
+            storage.publish(&preview.path(root, session_id)).await?;
+        }
+
+        Ok(())
     }
 
-    pub fn merge_clips(clips: Vec<ClipDetailRequest>, session_id: String) -> Result<String, Error> {
-        let mut temp_directory = dotenv::var("TEMP_DIRECTORY").unwrap();
-        temp_directory.push_str(session_id.as_str());
+    /// Encodes `sources`, joined in order with their overlays laid out as `layout` says,
+    /// to an H.264/AAC MP4 at `output` with the `moov` atom up front so playback can start
+    /// before the download finishes.
+    pub async fn export_mp4(
+        sources: &[ConcatInput],
+        layout: &PipLayout,
+        duration: f64,
+        output: &Path,
+        ffmpeg: &FFmpegSettings,
+    ) -> Result<(), ClipError> {
+        // x264 with yuv420p, which Safari needs, only takes even dimensions.
+        let mut args = Self::concat_args(
+            sources,
+            layout,
+            Some(String::from("scale=trunc(iw/2)*2:trunc(ih/2)*2")),
+        );
 
-        let mut clip_paths: Vec<String> = vec![];
+        args.extend(
+            [
+                "-c:v",
+                "libx264",
+                "-preset",
+                MP4_PRESET,
+                "-crf",
+                MP4_CRF,
+                "-pix_fmt",
+                "yuv420p",
+                "-c:a",
+                "aac",
+                "-b:a",
+                MP4_AUDIO_BITRATE,
+                "-movflags",
+                "+faststart",
+                "-f",
+                "mp4",
+            ]
+            .iter()
+            .map(|arg| arg.to_string()),
+        );
 
-        let mut args: Vec<String> = vec![];
-        args.push("-y".to_string());
+        args.push(output.display().to_string());
 
-        //Clip Filter Arg Builder
-        let mut filter_complex = String::new();
+        Self::run(ffmpeg, args, duration, |_| {}).await
+    }
+
+    /// Joins the audio of `sources`, in order, into `format` at `output`.
+    pub async fn export_audio(
+        sources: &[ConcatInput],
+        format: AudioFormat,
+        duration: f64,
+        output: &Path,
+        ffmpeg: &FFmpegSettings,
+    ) -> Result<(), ClipError> {
+        let mut args = Self::audio_concat_args(sources);
 
-        let mut clip_idx = 0;
+        args.extend(Self::audio_output_args(format, output));
 
-        for clip in clips.iter() {
-            let mut path = temp_directory.clone();
-            path.push('/');
-            path.push_str(clip.id.as_str());
-            path.push_str(".webm");
+        Self::run(ffmpeg, args, duration, |_| {}).await
+    }
+
+    /// The session's clips, in the given order, with their stored metadata, the trim at
+    /// the same index in `trims` and the overlay at the same index in `overlays`, staged
+    /// from the clip store. Every clip must have been finalized.
+    pub async fn concat_inputs(
+        clip_ids: &[String],
+        trims: &[Option<TrimRange>],
+        overlays: &[Option<String>],
+        session_id: &SessionId,
+        stored: &[ClipRecord],
+        storage: &Storage,
+    ) -> Result<Vec<ConcatInput>, ClipError> {
+        if clip_ids.is_empty() {
+            return Err(ClipError::from(String::from("No clips to merge")));
+        }
 
+        let mut inputs = vec![];
+
+        for (clip_idx, clip_id) in clip_ids.iter().enumerate() {
+            let trim = trims.get(clip_idx).copied().flatten();
+
+            let mut input = Self::stored_input(clip_id, trim, session_id, stored, storage).await?;
+
+            if let Some(overlay) = overlays.get(clip_idx).and_then(|overlay| overlay.as_ref()) {
+                let overlay =
+                    Self::stored_input(overlay, trim, session_id, stored, storage).await?;
+                input.overlay = Some(Box::new(overlay));
+            }
+
+            inputs.push(input);
+        }
+
+        Ok(inputs)
+    }
+
+    async fn stored_input(
+        clip_id: &str,
+        trim: Option<TrimRange>,
+        session_id: &SessionId,
+        stored: &[ClipRecord],
+        storage: &Storage,
+    ) -> Result<ConcatInput, ClipError> {
+        let path = ClipId::parse(clip_id)?.path(storage.temp_directory(), session_id);
+
+        if !storage.stage(&path).await? {
+            return Err(ClipError::from(format!("Unknown clip id: {}", clip_id)));
+        }
+
+        let metadata = stored
+            .iter()
+            .find(|record| record.id == clip_id)
+            .and_then(|record| record.metadata.clone());
+
+        Ok(ConcatInput {
+            path,
+            metadata,
+            trim,
+            overlay: None,
+        })
+    }
+
+    /// Clips can skip the encoder when none is trimmed or has an overlay, they all share
+    /// codecs, resolution, pixel format, time base and audio layout, and already match what
+    /// `profile` would produce.
+    fn can_stream_copy(inputs: &[ConcatInput], profile: &EncodingProfile) -> bool {
+        if inputs
+            .iter()
+            .any(|input| input.trim.is_some() || input.overlay.is_some())
+        {
+            return false;
+        }
+
+        let first = match inputs.first().and_then(|input| input.metadata.as_ref()) {
+            Some(first) => first,
+            None => return false,
+        };
+
+        if first.time_base.is_none() {
+            return false;
+        }
+
+        let parameters = |clip: &ClipMetadata| {
+            (
+                clip.video_codec.clone(),
+                clip.audio_codec.clone(),
+                clip.width,
+                clip.height,
+                clip.pixel_format.clone(),
+                clip.time_base.clone(),
+                clip.audio_sample_rate,
+                clip.audio_channels,
+            )
+        };
+
+        inputs.iter().all(|input| match &input.metadata {
+            Some(clip) => profile.accepts(clip) && parameters(clip) == parameters(first),
+            None => false,
+        })
+    }
+
+    /// Joins the clips with the concat demuxer, copying their streams as they are.
+    async fn concat_copy<F>(
+        inputs: &[ConcatInput],
+        output: &Path,
+        total_duration: f64,
+        ffmpeg: &FFmpegSettings,
+        on_progress: F,
+    ) -> Result<(), ClipError>
+    where
+        F: Fn(MergeProgress),
+    {
+        // Entries are resolved against the list's own directory, the session directory the
+        // output and the clips are in.
+        let list = output.with_extension("concat.txt");
+        let entries: String = inputs
+            .iter()
+            .filter_map(|input| input.path.file_name())
+            .map(|name| format!("file '{}'\n", name.to_string_lossy()))
+            .collect();
+
+        fs::write(&list, entries).await?;
+
+        let mut args = Self::base_args();
+
+        args.push("-f".to_string());
+        args.push("concat".to_string());
+        args.push("-i".to_string());
+        args.push(list.display().to_string());
+
+        args.push("-c".to_string());
+        args.push("copy".to_string());
+
+        args.extend(Self::webm_output_args(output));
+
+        let result = Self::run(ffmpeg, args, total_duration, on_progress).await;
+
+        if let Err(err) = fs::remove_file(&list).await {
+            log::warn!("Concat list {} was not removed: {}", list.display(), err);
+        }
+
+        result
+    }
+
+    /// Writes the cues ahead of the clusters, so players can seek before the whole file
+    /// has been downloaded. The muxer is named as the output may be a `.partial` file.
+    fn webm_output_args(output: &Path) -> Vec<String> {
+        vec![
+            "-f".to_string(),
+            "webm".to_string(),
+            "-cues_to_front".to_string(),
+            "1".to_string(),
+            output.display().to_string(),
+        ]
+    }
+
+    /// Encodes speech into `format`, without any video.
+    fn audio_output_args(format: AudioFormat, output: &Path) -> Vec<String> {
+        let codec = match format {
+            AudioFormat::Ogg => ["-c:a", "libopus", "-b:a", OPUS_BITRATE, "-f", "ogg"],
+            AudioFormat::Mp3 => ["-c:a", "libmp3lame", "-b:a", MP3_BITRATE, "-f", "mp3"],
+        };
+
+        let mut args = vec!["-vn".to_string()];
+        args.extend(codec.iter().map(|arg| arg.to_string()));
+        args.push(output.display().to_string());
+
+        args
+    }
+
+    fn base_args() -> Vec<String> {
+        //Machine readable progress on stdout, the log stays on stderr
+        vec![
+            "-y".to_string(),
+            "-progress".to_string(),
+            "pipe:1".to_string(),
+            "-nostats".to_string(),
+        ]
+    }
+
+    /// Input and `-filter_complex` arguments concatenating `inputs`, each cut to its trim
+    /// with its overlay drawn over it as `layout` says, with `video_filter` applied to the
+    /// joined video, mapped to the output. Clips are scaled and padded to the largest clip's
+    /// size when their sizes differ. A clip without audio gets silence and one without video
+    /// gets black frames of that size, so recordings made with a denied camera or microphone
+    /// still join.
+    fn concat_args(
+        inputs: &[ConcatInput],
+        layout: &PipLayout,
+        video_filter: Option<String>,
+    ) -> Vec<String> {
+        let mut args = Self::base_args();
+        //Overlays are read after the clips, so clip inputs keep their index
+        let mut overlay_args = vec![];
+
+        let recorded = || inputs.iter().filter_map(|input| input.metadata.as_ref());
+
+        let sizes: Vec<(i32, i32)> = recorded()
+            .filter_map(|clip| clip.width.zip(clip.height))
+            .collect();
+        let (width, height) = sizes
+            .iter()
+            .copied()
+            .max_by_key(|(width, height)| width * height)
+            .unwrap_or(PLACEHOLDER_SIZE);
+        //Clips of another size, like a screen recording next to camera ones, are letterboxed
+        let fit = sizes.iter().any(|size| *size != (width, height)).then(|| {
+            format!(
+                "scale={w}:{h}:force_original_aspect_ratio=decrease,\
+                 pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1",
+                w = width,
+                h = height
+            )
+        });
+        let (sample_rate, channel_layout) = Self::silence_format(inputs);
+
+        //Clip Filter Arg Builder
+        let mut sources = String::new();
+        let mut segments = String::new();
+
+        for (clip_idx, input) in inputs.iter().enumerate() {
             args.push("-i".to_string());
-            args.push(path.clone());
+            args.push(input.path.display().to_string());
+
+            let duration = input.duration();
+
+            //Trimmed streams restart their timestamps so the concat sees no gap
+            let mut video_filters = vec![];
+            if let Some(trim) = input.trim {
+                video_filters.push(format!(
+                    "trim=start={:.3}:end={:.3},setpts=PTS-STARTPTS",
+                    trim.start, trim.end
+                ));
+            }
+            if let Some(fit) = &fit {
+                video_filters.push(fit.clone());
+            }
+
+            let mut video = match (input.has_video(), video_filters.is_empty()) {
+                (true, false) => {
+                    sources.push_str(
+                        format!(
+                            "[{}:v]{}[v{}];",
+                            clip_idx,
+                            video_filters.join(","),
+                            clip_idx
+                        )
+                        .as_str(),
+                    );
+                    format!("[v{}]", clip_idx)
+                }
+                (true, true) => format!("[{}:v]", clip_idx),
+                (false, _) => {
+                    sources.push_str(
+                        format!(
+                            "color=c=black:s={}x{}:r={}:d={:.3}[v{}];",
+                            width, height, PLACEHOLDER_FRAME_RATE, duration, clip_idx
+                        )
+                        .as_str(),
+                    );
+                    format!("[v{}]", clip_idx)
+                }
+            };
 
-            filter_complex.push_str(format!("[{}:v][{}:a]", clip_idx, clip_idx).as_str());
+            //A camera that was denied leaves nothing to draw
+            if let Some(overlay) = input.overlay.as_deref().filter(|input| input.has_video()) {
+                let overlay_idx = inputs.len() + overlay_args.len() / 2;
 
-            clip_paths.push(path.clone());
+                overlay_args.push("-i".to_string());
+                overlay_args.push(overlay.path.display().to_string());
 
-            clip_idx += 1;
+                sources.push_str(
+                    format!(
+                        "[{}:v]{}[pip{}];{}[pip{}]overlay={}:eof_action=pass[ov{}];",
+                        overlay_idx,
+                        Self::pip_filters(overlay, duration, layout, width),
+                        clip_idx,
+                        video,
+                        clip_idx,
+                        Self::pip_position(layout),
+                        clip_idx
+                    )
+                    .as_str(),
+                );
+                video = format!("[ov{}]", clip_idx);
+            }
+
+            segments.push_str(video.as_str());
+            segments.push_str(
+                Self::audio_segment(input, clip_idx, sample_rate, channel_layout, &mut sources)
+                    .as_str(),
+            );
         }
 
-        filter_complex.push_str(format!("concat=n={}:v=1:a=1[outv][outa]", clip_idx).as_str());
+        args.extend(overlay_args);
+
+        let mut filter_complex = sources;
+        filter_complex.push_str(segments.as_str());
+        filter_complex.push_str(format!("concat=n={}:v=1:a=1[outv][outa]", inputs.len()).as_str());
+
+        let mut video_output = "[outv]";
+        if let Some(filter) = video_filter {
+            filter_complex.push_str(format!(";[outv]{}[filteredv]", filter).as_str());
+            video_output = "[filteredv]";
+        }
 
         args.push("-filter_complex".to_string());
-        args.push(format!("{}", filter_complex));
+        args.push(filter_complex);
 
         args.push("-map".to_string());
-        args.push("[outv]".to_string());
+        args.push(video_output.to_string());
 
         args.push("-map".to_string());
         args.push("[outa]".to_string());
 
-        if clips.len() != clip_paths.len() {
-            return Err(Error::from(ErrorKind::UnexpectedEof));
+        args
+    }
+
+    /// Input and `-filter_complex` arguments concatenating the audio of `inputs`, each cut
+    /// to its trim, mapped to the output. A clip without audio gets silence.
+    fn audio_concat_args(inputs: &[ConcatInput]) -> Vec<String> {
+        let mut args = Self::base_args();
+
+        let (sample_rate, channel_layout) = Self::silence_format(inputs);
+
+        let mut sources = String::new();
+        let mut segments = String::new();
+
+        for (clip_idx, input) in inputs.iter().enumerate() {
+            args.push("-i".to_string());
+            args.push(input.path.display().to_string());
+
+            segments.push_str(
+                Self::audio_segment(input, clip_idx, sample_rate, channel_layout, &mut sources)
+                    .as_str(),
+            );
         }
 
-        let output = format!(
-            "{}/output-{}.webm",
-            temp_directory.clone(),
-            session_id.clone()
-        );
+        let mut filter_complex = sources;
+        filter_complex.push_str(segments.as_str());
+        filter_complex.push_str(format!("concat=n={}:v=0:a=1[outa]", inputs.len()).as_str());
+
+        args.push("-filter_complex".to_string());
+        args.push(filter_complex);
+
+        args.push("-map".to_string());
+        args.push("[outa]".to_string());
+
+        args
+    }
+
+    /// Sample rate and channel layout of the silence standing in for missing audio, those
+    /// of the first clip that has some.
+    fn silence_format(inputs: &[ConcatInput]) -> (i32, &'static str) {
+        let recorded = || inputs.iter().filter_map(|input| input.metadata.as_ref());
+
+        let sample_rate = recorded()
+            .find_map(|clip| clip.audio_sample_rate)
+            .unwrap_or(PLACEHOLDER_SAMPLE_RATE);
+        let channel_layout = match recorded().find_map(|clip| clip.audio_channels) {
+            Some(1) => "mono",
+            _ => "stereo",
+        };
+
+        (sample_rate, channel_layout)
+    }
 
-        if Path::new(&output).is_file() {
-            fs::remove_file(&output).unwrap();
+    /// Adds the filters cutting the audio of input `clip_idx` to its trim, or making silence
+    /// as long as it when it has none, to `sources`. Returns the label to concatenate.
+    fn audio_segment(
+        input: &ConcatInput,
+        clip_idx: usize,
+        sample_rate: i32,
+        channel_layout: &str,
+        sources: &mut String,
+    ) -> String {
+        match (input.has_audio(), input.trim) {
+            (true, Some(trim)) => {
+                sources.push_str(
+                    format!(
+                        "[{}:a]atrim=start={:.3}:end={:.3},asetpts=PTS-STARTPTS[a{}];",
+                        clip_idx, trim.start, trim.end, clip_idx
+                    )
+                    .as_str(),
+                );
+                format!("[a{}]", clip_idx)
+            }
+            (true, None) => format!("[{}:a]", clip_idx),
+            (false, _) => {
+                sources.push_str(
+                    format!(
+                        "anullsrc=r={}:cl={},atrim=duration={:.3}[a{}];",
+                        sample_rate,
+                        channel_layout,
+                        input.duration(),
+                        clip_idx
+                    )
+                    .as_str(),
+                );
+                format!("[a{}]", clip_idx)
+            }
+        }
+    }
+
+    /// Filters cutting a camera overlay to its trim and the clip's `duration`, then to the
+    /// layout's shape and size against a screen `screen_width` pixels wide. Quoted
+    /// expressions keep their commas from splitting the filter chain.
+    fn pip_filters(
+        overlay: &ConcatInput,
+        duration: f64,
+        layout: &PipLayout,
+        screen_width: i32,
+    ) -> String {
+        let mut filters = vec![];
+
+        if let Some(trim) = overlay.trim {
+            filters.push(format!(
+                "trim=start={:.3}:end={:.3},setpts=PTS-STARTPTS",
+                trim.start, trim.end
+            ));
+        }
+        //Two recorders never stop at quite the same time
+        if duration > 0.0 {
+            filters.push(format!("trim=duration={:.3}", duration));
+        }
+
+        //x264 and VP9 take even sizes only
+        let width = (f64::from(screen_width) * layout.size / 2.0).round() as i32 * 2;
+
+        match layout.shape {
+            PipShape::Rectangle => filters.push(format!("scale={}:-2", width)),
+            PipShape::Square => filters.push(format!(
+                "crop='min(iw,ih)':'min(iw,ih)',scale={w}:{w}",
+                w = width
+            )),
+            PipShape::Circle => filters.push(format!(
+                "crop='min(iw,ih)':'min(iw,ih)',scale={w}:{w},format=yuva420p,\
+                 geq=lum='p(X,Y)':cb='cb(X,Y)':cr='cr(X,Y)':\
+                 a='if(lte(hypot(X-W/2,Y-H/2),W/2),255,0)'",
+                w = width
+            )),
         }
 
-        //Encoding Settings
-        args.push("-c:v".to_string());
-        args.push("libvpx-vp9".to_string());
-        args.push("-crf".to_string());
-        args.push("23".to_string());
-        args.push("-c:a".to_string());
-        args.push("libopus".to_string());
+        filters.join(",")
+    }
+
+    /// `overlay` filter coordinates putting the camera in the layout's corner.
+    fn pip_position(layout: &PipLayout) -> String {
+        let margin = layout.margin;
 
-        //Resource Settings
-        args.push("-cpu-used".to_string());
-        args.push("8".to_string());
-        args.push("-row-mt".to_string());
-        args.push("1".to_string());
-        args.push("-threads".to_string());
-        args.push("8".to_string());
-        args.push("-tile-columns".to_string());
-        args.push("2".to_string());
-        args.push("-frame-parallel".to_string());
-        args.push("1".to_string());
-        args.push("-auto-alt-ref".to_string());
-        args.push("1".to_string());
+        let (x, y) = match layout.corner {
+            PipCorner::TopLeft => (margin.to_string(), margin.to_string()),
+            PipCorner::TopRight => (format!("main_w-overlay_w-{}", margin), margin.to_string()),
+            PipCorner::BottomLeft => (margin.to_string(), format!("main_h-overlay_h-{}", margin)),
+            PipCorner::BottomRight => (
+                format!("main_w-overlay_w-{}", margin),
+                format!("main_h-overlay_h-{}", margin),
+            ),
+        };
 
-        args.push(output.to_string());
+        format!("x={}:y={}", x, y)
+    }
 
-        let mut command = Command::new("ffmpeg")
+    /// Runs ffmpeg to completion, forwarding its `-progress` reports against `total_duration`.
+    async fn run<F>(
+        ffmpeg: &FFmpegSettings,
+        args: Vec<String>,
+        total_duration: f64,
+        on_progress: F,
+    ) -> Result<(), ClipError>
+    where
+        F: Fn(MergeProgress),
+    {
+        let mut command = Command::new(&ffmpeg.ffmpeg_path)
             .args(&args)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .spawn()
-            .expect("internal_ffmpeg_error");
-
-        if let Some(stderr) = command.stderr.take() {
-            let reader = BufReader::new(stderr);
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    println!("Progress: {}", line);
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stdout = command.stdout.take();
+        let stderr = command.stderr.take();
+
+        let mut parser = ProgressParser::new(total_duration);
+
+        let read_progress = async {
+            if let Some(stdout) = stdout {
+                let mut lines = BufReader::new(stdout).lines();
+                while let Some(line) = lines.next_line().await? {
+                    if let Some(progress) = parser.push(line.as_str()) {
+                        on_progress(progress);
+                    }
                 }
             }
-        }
 
-        let status = command.wait()?;
+            Ok::<(), std::io::Error>(())
+        };
+
+        let read_log = async {
+            let mut last_line = None;
+
+            if let Some(stderr) = stderr {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Some(line) = lines.next_line().await? {
+                    log::debug!("ffmpeg: {}", line);
+                    last_line = Some(line);
+                }
+            }
+
+            Ok::<Option<String>, std::io::Error>(last_line)
+        };
+
+        let (progress, log) = tokio::join!(read_progress, read_log);
+        progress?;
+        let last_line = log?;
+
+        let status = command.wait().await?;
         if !status.success() {
-            println!("error_success");
+            return Err(ClipError::from(format!(
+                "ffmpeg exited with {}: {}",
+                status,
+                last_line.unwrap_or_default()
+            )));
         }
 
-        let metadata = std::fs::metadata(output.clone())?;
-        if metadata.len() == 0 {
-            println!("error_metadata_len");
+        Ok(())
+    }
+}
+
+/// Folds the `key=value` lines of ffmpeg's `-progress` output into reports. ffmpeg ends
+/// every block with a `progress=continue` or `progress=end` line.
+struct ProgressParser {
+    total_duration: f64,
+    current: MergeProgress,
+}
+
+impl ProgressParser {
+    fn new(total_duration: f64) -> ProgressParser {
+        ProgressParser {
+            total_duration,
+            current: MergeProgress::default(),
+        }
+    }
+
+    /// Returns a report once a block is complete.
+    fn push(&mut self, line: &str) -> Option<MergeProgress> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+
+        match key {
+            "frame" => {
+                if let Ok(frame) = value.parse() {
+                    self.current.frame = frame;
+                }
+            }
+            // Both are microseconds, `out_time_ms` is misnamed by ffmpeg.
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(micros) = value.parse::<i64>() {
+                    self.current.out_time = micros.max(0) as f64 / 1_000_000.0;
+                }
+            }
+            "speed" => {
+                self.current.speed = value.trim_end_matches('x').parse().ok();
+            }
+            "progress" => {
+                self.current.percent = if value == "end" {
+                    100.0
+                } else if self.total_duration > 0.0 {
+                    (self.current.out_time / self.total_duration * 100.0).clamp(0.0, 100.0)
+                } else {
+                    0.0
+                };
+
+                return Some(self.current.clone());
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::profiles::EncodingProfiles;
+
+    fn recorded(width: i32, height: i32, audio: bool) -> ClipMetadata {
+        ClipMetadata {
+            video_codec: Some(String::from("vp9")),
+            audio_codec: audio.then(|| String::from("opus")),
+            width: Some(width),
+            height: Some(height),
+            duration: 10.0,
+            audio_channels: audio.then_some(2),
+            audio_sample_rate: audio.then_some(48000),
+            ..ClipMetadata::default()
+        }
+    }
+
+    fn input(path: &str, metadata: ClipMetadata, trim: Option<TrimRange>) -> ConcatInput {
+        ConcatInput {
+            path: PathBuf::from(path),
+            metadata: Some(metadata),
+            trim,
+            overlay: None,
         }
+    }
+
+    /// The `-filter_complex` argument.
+    fn filter_complex(args: &[String]) -> &str {
+        let position = args
+            .iter()
+            .position(|arg| arg == "-filter_complex")
+            .unwrap();
+
+        args[position + 1].as_str()
+    }
+
+    #[test]
+    fn concat_of_same_sized_clips_joins_them_as_they_are() {
+        let inputs = [
+            input("a.webm", recorded(1280, 720, true), None),
+            input("b.webm", recorded(1280, 720, true), None),
+        ];
+
+        let args = FFMpegService::concat_args(&inputs, &PipLayout::default(), None);
+
+        assert_eq!(
+            filter_complex(&args),
+            "[0:v][0:a][1:v][1:a]concat=n=2:v=1:a=1[outv][outa]"
+        );
+        assert!(args.windows(2).any(|pair| pair == ["-i", "a.webm"]));
+        assert!(args.windows(2).any(|pair| pair == ["-i", "b.webm"]));
+        assert!(args.windows(2).any(|pair| pair == ["-map", "[outv]"]));
+    }
+
+    #[test]
+    fn concat_letterboxes_clips_to_the_largest_size() {
+        let inputs = [
+            input("camera.webm", recorded(640, 480, true), None),
+            input("screen.webm", recorded(1920, 1080, true), None),
+        ];
 
-        Ok(output)
+        let args = FFMpegService::concat_args(&inputs, &PipLayout::default(), None);
+        let fit = "scale=1920:1080:force_original_aspect_ratio=decrease,\
+                   pad=1920:1080:(ow-iw)/2:(oh-ih)/2,setsar=1";
+
+        assert_eq!(
+            filter_complex(&args),
+            format!(
+                "[0:v]{fit}[v0];[1:v]{fit}[v1];[v0][0:a][v1][1:a]concat=n=2:v=1:a=1[outv][outa]",
+                fit = fit
+            )
+        );
+    }
+
+    #[test]
+    fn concat_cuts_trimmed_clips_before_letterboxing_them() {
+        let trim = TrimRange {
+            start: 1.5,
+            end: 4.25,
+        };
+        let inputs = [
+            input("camera.webm", recorded(640, 480, true), Some(trim)),
+            input("screen.webm", recorded(1920, 1080, true), None),
+        ];
+
+        let args = FFMpegService::concat_args(&inputs, &PipLayout::default(), None);
+        let filter = filter_complex(&args);
+
+        assert!(filter
+            .starts_with("[0:v]trim=start=1.500:end=4.250,setpts=PTS-STARTPTS,scale=1920:1080:"));
+        assert!(filter.contains("[0:a]atrim=start=1.500:end=4.250,asetpts=PTS-STARTPTS[a0];"));
+        assert!(filter.ends_with("[v0][a0][v1][1:a]concat=n=2:v=1:a=1[outv][outa]"));
+    }
+
+    #[test]
+    fn concat_fills_missing_streams_for_the_kept_duration() {
+        let mut camera_only = recorded(1280, 720, false);
+        camera_only.video_codec = None;
+        camera_only.width = None;
+        camera_only.height = None;
+        let inputs = [
+            input("a.webm", recorded(1280, 720, true), None),
+            input("b.webm", recorded(1280, 720, false), None),
+            input(
+                "c.webm",
+                camera_only,
+                Some(TrimRange {
+                    start: 2.0,
+                    end: 5.0,
+                }),
+            ),
+        ];
+
+        let args = FFMpegService::concat_args(&inputs, &PipLayout::default(), None);
+        let filter = filter_complex(&args);
+
+        assert!(filter.contains("anullsrc=r=48000:cl=stereo,atrim=duration=10.000[a1];"));
+        assert!(filter.contains("color=c=black:s=1280x720:r=30:d=3.000[v2];"));
+        assert!(filter.contains("anullsrc=r=48000:cl=stereo,atrim=duration=3.000[a2];"));
+        assert!(filter.ends_with("[0:v][0:a][1:v][a1][v2][a2]concat=n=3:v=1:a=1[outv][outa]"));
     }
 
-    pub fn create_file(file_path: String, buffer: Vec<u8>) -> String {
-        let mut temp_directory = dotenv::var("TEMP_DIRECTORY").expect("TEMP_DIRECTORY_NOT_SET");
+    #[test]
+    fn concat_gives_a_clip_without_audio_silence() {
+        let mut muted = recorded(1280, 720, false);
+        muted.duration = 4.0;
+        let inputs = [
+            input("a.webm", recorded(1280, 720, true), None),
+            input("b.webm", muted, None),
+        ];
 
-        temp_directory.push_str(file_path.as_str());
+        let args = FFMpegService::concat_args(&inputs, &PipLayout::default(), None);
 
-        if !Path::new(&temp_directory).exists() {
-            if let Err(err) = fs::create_dir(temp_directory.clone()) {
-                println!("{:?}", err);
+        assert_eq!(
+            filter_complex(&args),
+            "anullsrc=r=48000:cl=stereo,atrim=duration=4.000[a1];\
+             [0:v][0:a][1:v][a1]concat=n=2:v=1:a=1[outv][outa]"
+        );
+    }
+
+    #[test]
+    fn concat_gives_a_clip_without_video_black_frames() {
+        let voice = ClipMetadata {
+            video_codec: None,
+            width: None,
+            height: None,
+            duration: 4.0,
+            ..recorded(0, 0, true)
+        };
+        let inputs = [
+            input("a.webm", recorded(1280, 720, true), None),
+            input("b.webm", voice, None),
+        ];
+
+        let args = FFMpegService::concat_args(&inputs, &PipLayout::default(), None);
+
+        assert_eq!(
+            filter_complex(&args),
+            "color=c=black:s=1280x720:r=30:d=4.000[v1];\
+             [0:v][0:a][v1][1:a]concat=n=2:v=1:a=1[outv][outa]"
+        );
+    }
+
+    #[test]
+    fn concat_applies_the_video_filter_to_the_joined_video() {
+        let inputs = [input("a.webm", recorded(1280, 720, true), None)];
+
+        let args = FFMpegService::concat_args(
+            &inputs,
+            &PipLayout::default(),
+            Some(String::from("scale=-2:480")),
+        );
+
+        assert!(filter_complex(&args).ends_with(";[outv]scale=-2:480[filteredv]"));
+        assert!(args.windows(2).any(|pair| pair == ["-map", "[filteredv]"]));
+    }
+
+    #[test]
+    fn audio_concat_trims_and_fills_the_audio_only() {
+        let inputs = [
+            input(
+                "a.webm",
+                recorded(1920, 1080, true),
+                Some(TrimRange {
+                    start: 0.5,
+                    end: 2.0,
+                }),
+            ),
+            input("b.webm", recorded(640, 480, false), None),
+            input("c.webm", recorded(640, 480, true), None),
+        ];
+
+        let args = FFMpegService::audio_concat_args(&inputs);
+
+        assert_eq!(
+            filter_complex(&args),
+            "[0:a]atrim=start=0.500:end=2.000,asetpts=PTS-STARTPTS[a0];\
+             anullsrc=r=48000:cl=stereo,atrim=duration=10.000[a1];\
+             [a0][a1][2:a]concat=n=3:v=0:a=1[outa]"
+        );
+        assert!(args.windows(2).any(|pair| pair == ["-map", "[outa]"]));
+        assert!(!args.iter().any(|arg| arg == "[outv]"));
+    }
+
+    #[test]
+    fn voice_merges_fill_silence_in_the_recorded_format() {
+        let voice = ClipMetadata {
+            video_codec: None,
+            width: None,
+            height: None,
+            audio_channels: Some(1),
+            audio_sample_rate: Some(44100),
+            ..recorded(0, 0, true)
+        };
+        let inputs = [
+            input(
+                "a.webm",
+                voice.clone(),
+                Some(TrimRange {
+                    start: 1.0,
+                    end: 3.5,
+                }),
+            ),
+            input("b.webm", recorded(1280, 720, false), None),
+            input(
+                "c.webm",
+                voice,
+                Some(TrimRange {
+                    start: 0.0,
+                    end: 2.0,
+                }),
+            ),
+        ];
+
+        let args = FFMpegService::audio_concat_args(&inputs);
+
+        assert_eq!(
+            filter_complex(&args),
+            "[0:a]atrim=start=1.000:end=3.500,asetpts=PTS-STARTPTS[a0];\
+             anullsrc=r=44100:cl=mono,atrim=duration=10.000[a1];\
+             [2:a]atrim=start=0.000:end=2.000,asetpts=PTS-STARTPTS[a2];\
+             [a0][a1][a2]concat=n=3:v=0:a=1[outa]"
+        );
+    }
+
+    #[test]
+    fn audio_output_drops_the_video_and_encodes_the_format() {
+        let output = Path::new("output.partial");
+
+        assert_eq!(
+            FFMpegService::audio_output_args(AudioFormat::Ogg, output),
+            [
+                "-vn",
+                "-c:a",
+                "libopus",
+                "-b:a",
+                OPUS_BITRATE,
+                "-f",
+                "ogg",
+                "output.partial"
+            ]
+        );
+        assert_eq!(
+            FFMpegService::audio_output_args(AudioFormat::Mp3, output),
+            [
+                "-vn",
+                "-c:a",
+                "libmp3lame",
+                "-b:a",
+                MP3_BITRATE,
+                "-f",
+                "mp3",
+                "output.partial"
+            ]
+        );
+    }
+
+    #[test]
+    fn overlays_are_read_after_every_clip() {
+        let mut screen = input("screen.webm", recorded(1920, 1080, true), None);
+        screen.overlay = Some(Box::new(input(
+            "camera.webm",
+            recorded(640, 480, false),
+            None,
+        )));
+        let inputs = [input("a.webm", recorded(1920, 1080, true), None), screen];
+
+        let args = FFMpegService::concat_args(&inputs, &PipLayout::default(), None);
+        let files: Vec<&str> = args
+            .windows(2)
+            .filter(|pair| pair[0] == "-i")
+            .map(|pair| pair[1].as_str())
+            .collect();
+
+        assert_eq!(files, ["a.webm", "screen.webm", "camera.webm"]);
+        assert_eq!(
+            filter_complex(&args),
+            "[2:v]trim=duration=10.000,scale=480:-2[pip1];\
+             [1:v][pip1]overlay=x=main_w-overlay_w-16:y=main_h-overlay_h-16:eof_action=pass[ov1];\
+             [0:v][0:a][ov1][1:a]concat=n=2:v=1:a=1[outv][outa]"
+        );
+    }
+
+    #[test]
+    fn pip_position_puts_the_camera_in_each_corner() {
+        let cases = [
+            (PipCorner::TopLeft, "x=16:y=16"),
+            (PipCorner::TopRight, "x=main_w-overlay_w-16:y=16"),
+            (PipCorner::BottomLeft, "x=16:y=main_h-overlay_h-16"),
+            (
+                PipCorner::BottomRight,
+                "x=main_w-overlay_w-16:y=main_h-overlay_h-16",
+            ),
+        ];
+
+        for (corner, position) in cases {
+            let layout = PipLayout {
+                corner,
+                ..PipLayout::default()
+            };
+
+            assert_eq!(FFMpegService::pip_position(&layout), position);
+        }
+    }
+
+    #[test]
+    fn pip_filters_cut_the_camera_to_each_shape() {
+        let camera = input(
+            "camera.webm",
+            recorded(640, 480, false),
+            Some(TrimRange {
+                start: 1.0,
+                end: 4.0,
+            }),
+        );
+        let cut = "trim=start=1.000:end=4.000,setpts=PTS-STARTPTS,trim=duration=3.000";
+        let cases = [
+            (PipShape::Rectangle, format!("{},scale=480:-2", cut)),
+            (
+                PipShape::Square,
+                format!("{},crop='min(iw,ih)':'min(iw,ih)',scale=480:480", cut),
+            ),
+            (
+                PipShape::Circle,
+                format!(
+                    "{},crop='min(iw,ih)':'min(iw,ih)',scale=480:480,format=yuva420p,\
+                     geq=lum='p(X,Y)':cb='cb(X,Y)':cr='cr(X,Y)':\
+                     a='if(lte(hypot(X-W/2,Y-H/2),W/2),255,0)'",
+                    cut
+                ),
+            ),
+        ];
+
+        for (shape, filters) in cases {
+            let layout = PipLayout {
+                shape,
+                ..PipLayout::default()
             };
+
+            assert_eq!(
+                FFMpegService::pip_filters(&camera, 3.0, &layout, 1920),
+                filters
+            );
+        }
+    }
+
+    #[test]
+    fn pip_filters_keep_the_camera_width_even() {
+        let camera = input("camera.webm", recorded(640, 480, false), None);
+        let layout = PipLayout {
+            size: 0.3,
+            ..PipLayout::default()
+        };
+
+        assert_eq!(
+            FFMpegService::pip_filters(&camera, 0.0, &layout, 1366),
+            "scale=410:-2"
+        );
+    }
+
+    /// A clip the built-in profile would produce.
+    fn copyable() -> ClipMetadata {
+        ClipMetadata {
+            time_base: Some(String::from("1/1000")),
+            pixel_format: Some(String::from("yuv420p")),
+            ..recorded(1280, 720, true)
         }
+    }
 
-        let clip_id = Utilities::rnd_id("clip-");
+    fn builtin_profile() -> EncodingProfile {
+        EncodingProfiles::load(Path::new("no-such-profiles.toml"))
+            .unwrap()
+            .resolve(None)
+            .unwrap()
+    }
+
+    #[test]
+    fn matching_clips_are_stream_copied() {
+        let inputs = [
+            input("a.webm", copyable(), None),
+            input("b.webm", copyable(), None),
+        ];
+
+        assert!(FFMpegService::can_stream_copy(&inputs, &builtin_profile()));
+    }
+
+    #[test]
+    fn clips_differing_in_codec_size_or_time_base_are_encoded() {
+        let mismatches: [fn(&mut ClipMetadata); 4] = [
+            |clip| clip.video_codec = Some(String::from("vp8")),
+            |clip| clip.audio_codec = Some(String::from("vorbis")),
+            |clip| clip.width = Some(640),
+            |clip| clip.time_base = Some(String::from("1/30")),
+        ];
+
+        for mismatch in mismatches {
+            let mut other = copyable();
+            mismatch(&mut other);
+            let inputs = [
+                input("a.webm", copyable(), None),
+                input("b.webm", other, None),
+            ];
+
+            assert!(!FFMpegService::can_stream_copy(&inputs, &builtin_profile()));
+        }
+    }
+
+    #[test]
+    fn trimmed_clips_and_clips_with_an_overlay_are_encoded() {
+        let trimmed = [input(
+            "a.webm",
+            copyable(),
+            Some(TrimRange {
+                start: 1.0,
+                end: 2.0,
+            }),
+        )];
+        let mut overlaid = [input("a.webm", copyable(), None)];
+        overlaid[0].overlay = Some(Box::new(input("camera.webm", copyable(), None)));
+
+        assert!(!FFMpegService::can_stream_copy(
+            &trimmed,
+            &builtin_profile()
+        ));
+        assert!(!FFMpegService::can_stream_copy(
+            &overlaid,
+            &builtin_profile()
+        ));
+    }
+
+    #[test]
+    fn clips_the_profile_would_change_are_encoded() {
+        let inputs = [input("a.webm", copyable(), None)];
+        let capped = EncodingProfile {
+            max_height: Some(480),
+            ..builtin_profile()
+        };
+        let forced = EncodingProfile {
+            always_encode: true,
+            ..builtin_profile()
+        };
+
+        assert!(!FFMpegService::can_stream_copy(&inputs, &capped));
+        assert!(!FFMpegService::can_stream_copy(&inputs, &forced));
+    }
+
+    fn push_all(parser: &mut ProgressParser, lines: &[&str]) -> Vec<MergeProgress> {
+        lines.iter().filter_map(|line| parser.push(line)).collect()
+    }
+
+    #[test]
+    fn progress_is_reported_once_a_block_ends() {
+        let mut parser = ProgressParser::new(10.0);
+
+        let reports = push_all(
+            &mut parser,
+            &[
+                "frame=75",
+                "out_time_us=2500000",
+                "speed=1.25x",
+                "progress=continue",
+            ],
+        );
+
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].frame, 75);
+        assert_eq!(reports[0].out_time, 2.5);
+        assert_eq!(reports[0].speed, Some(1.25));
+        assert_eq!(reports[0].percent, 25.0);
+    }
+
+    #[test]
+    fn progress_survives_lines_split_mid_block() {
+        let mut parser = ProgressParser::new(4.0);
+
+        assert!(push_all(&mut parser, &["frame=30", "out_time_us=1000000"]).is_empty());
+        let reports = push_all(&mut parser, &["speed=2x", "progress=continue"]);
+
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].frame, 30);
+        assert_eq!(reports[0].percent, 25.0);
+    }
+
+    #[test]
+    fn progress_ignores_partial_and_unknown_lines() {
+        let mut parser = ProgressParser::new(10.0);
+
+        let reports = push_all(
+            &mut parser,
+            &[
+                "",
+                "frame",
+                "fra",
+                "out_time_us=",
+                "out_time_us=N/A",
+                "speed=N/A",
+                "bitrate=1234.5kbits/s",
+                "out_time_us=5000000",
+                "progress=continue",
+            ],
+        );
+
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].frame, 0);
+        assert_eq!(reports[0].out_time, 5.0);
+        assert_eq!(reports[0].speed, None);
+        assert_eq!(reports[0].percent, 50.0);
+    }
+
+    #[test]
+    fn progress_keeps_the_last_value_of_a_truncated_line() {
+        let mut parser = ProgressParser::new(10.0);
+
+        push_all(&mut parser, &["frame=60", "progress=continue"]);
+        let reports = push_all(&mut parser, &["frame=9x", "progress=continue"]);
+
+        assert_eq!(reports[0].frame, 60);
+    }
+
+    #[test]
+    fn progress_is_clamped_and_complete_at_the_end() {
+        let mut parser = ProgressParser::new(2.0);
+
+        let running = push_all(&mut parser, &["out_time_us=3000000", "progress=continue"]);
+        let ended = push_all(&mut parser, &["progress=end"]);
+
+        assert_eq!(running[0].percent, 100.0);
+        assert_eq!(ended[0].percent, 100.0);
+    }
 
-        temp_directory.push('/');
-        temp_directory.push_str(clip_id.as_str());
-        temp_directory.push_str(".webm");
+    #[test]
+    fn progress_without_a_known_duration_stays_at_zero() {
+        let mut parser = ProgressParser::new(0.0);
 
-        fs::write(temp_directory, buffer).expect("expected_file_write");
+        let reports = push_all(&mut parser, &["out_time_ms=1000000", "progress=continue"]);
 
-        clip_id
+        assert_eq!(reports[0].out_time, 1.0);
+        assert_eq!(reports[0].percent, 0.0);
     }
 }
@@ -0,0 +1,345 @@
+use crate::helpers::errors::ClipError;
+use crate::models::ClipMetadata;
+use crate::settings::FFmpegSettings;
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+
+#[derive(Debug, Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<i32>,
+    height: Option<i32>,
+    avg_frame_rate: Option<String>,
+    r_frame_rate: Option<String>,
+    time_base: Option<String>,
+    pix_fmt: Option<String>,
+    channels: Option<i32>,
+    /// ffprobe reports sample rates as strings.
+    sample_rate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeFormat {
+    format_name: Option<String>,
+    duration: Option<String>,
+}
+
+pub struct FFProbeService {}
+
+impl FFProbeService {
+    pub async fn probe(ffmpeg: &FFmpegSettings, path: &Path) -> Result<ClipMetadata, ClipError> {
+        let stdout = Self::run(
+            ffmpeg,
+            &[
+                "-v",
+                "error",
+                "-print_format",
+                "json",
+                "-show_format",
+                "-show_streams",
+                path.to_str().unwrap_or_default(),
+            ],
+        )
+        .await?;
+
+        let (format, streams) = Self::parse_output(stdout.as_str())?;
+
+        // MediaRecorder writes WebM without a duration header, count the packets instead.
+        let duration = match format
+            .duration
+            .as_deref()
+            .and_then(|duration| duration.parse::<f64>().ok())
+        {
+            Some(duration) => duration,
+            None => Self::packet_duration(ffmpeg, path).await?,
+        };
+
+        Ok(Self::metadata(format, &streams, duration))
+    }
+
+    /// End of the last packet, in seconds.
+    async fn packet_duration(ffmpeg: &FFmpegSettings, path: &Path) -> Result<f64, ClipError> {
+        let stdout = Self::run(
+            ffmpeg,
+            &[
+                "-v",
+                "error",
+                "-show_entries",
+                "packet=pts_time,duration_time",
+                "-of",
+                "csv=p=0",
+                path.to_str().unwrap_or_default(),
+            ],
+        )
+        .await?;
+
+        Ok(Self::packet_end(stdout.as_str()))
+    }
+
+    /// The container and streams of ffprobe's `-print_format json` output, failing when
+    /// there is nothing to describe.
+    fn parse_output(stdout: &str) -> Result<(ProbeFormat, Vec<ProbeStream>), ClipError> {
+        let output: ProbeOutput = serde_json::from_str(stdout)
+            .map_err(|err| ClipError::from(format!("Unreadable ffprobe output: {}", err)))?;
+
+        let format = output
+            .format
+            .ok_or_else(|| ClipError::from(String::from("ffprobe found no container")))?;
+
+        if Self::stream(&output.streams, "video").is_none()
+            && Self::stream(&output.streams, "audio").is_none()
+        {
+            return Err(ClipError::from(String::from(
+                "ffprobe found no audio or video streams",
+            )));
+        }
+
+        Ok((format, output.streams))
+    }
+
+    /// The first stream of `codec_type`.
+    fn stream<'a>(streams: &'a [ProbeStream], codec_type: &str) -> Option<&'a ProbeStream> {
+        streams
+            .iter()
+            .find(|stream| stream.codec_type.as_deref() == Some(codec_type))
+    }
+
+    fn metadata(format: ProbeFormat, streams: &[ProbeStream], duration: f64) -> ClipMetadata {
+        let video = Self::stream(streams, "video");
+        let audio = Self::stream(streams, "audio");
+
+        ClipMetadata {
+            container: format.format_name.unwrap_or_default(),
+            video_codec: video.and_then(|stream| stream.codec_name.clone()),
+            audio_codec: audio.and_then(|stream| stream.codec_name.clone()),
+            width: video.and_then(|stream| stream.width),
+            height: video.and_then(|stream| stream.height),
+            frame_rate: video.and_then(|stream| {
+                Self::parse_rate(stream.avg_frame_rate.as_deref())
+                    .or_else(|| Self::parse_rate(stream.r_frame_rate.as_deref()))
+            }),
+            duration,
+            audio_channels: audio.and_then(|stream| stream.channels),
+            time_base: video.and_then(|stream| stream.time_base.clone()),
+            pixel_format: video.and_then(|stream| stream.pix_fmt.clone()),
+            audio_sample_rate: audio
+                .and_then(|stream| stream.sample_rate.as_deref())
+                .and_then(|rate| rate.parse().ok()),
+        }
+    }
+
+    /// Folds ffprobe's `pts_time,duration_time` CSV lines into the end of the last packet.
+    fn packet_end(stdout: &str) -> f64 {
+        stdout
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(',');
+                let pts = fields.next()?.trim().parse::<f64>().ok()?;
+                let duration = fields
+                    .next()
+                    .and_then(|duration| duration.trim().parse::<f64>().ok())
+                    .unwrap_or(0.0);
+
+                Some(pts + duration)
+            })
+            .fold(0.0, f64::max)
+    }
+
+    /// Parses ffprobe's `num/den` rates, ignoring the `0/0` it reports when unknown.
+    fn parse_rate(rate: Option<&str>) -> Option<f64> {
+        let (numerator, denominator) = rate?.split_once('/')?;
+        let numerator = numerator.parse::<f64>().ok()?;
+        let denominator = denominator.parse::<f64>().ok()?;
+
+        if numerator <= 0.0 || denominator <= 0.0 {
+            return None;
+        }
+
+        Some(numerator / denominator)
+    }
+
+    async fn run(ffmpeg: &FFmpegSettings, args: &[&str]) -> Result<String, ClipError> {
+        let output = Command::new(&ffmpeg.ffprobe_path)
+            .args(args)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            return Err(ClipError::from(format!(
+                "ffprobe exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN_RECORDING: &str = r#"{
+        "streams": [
+            {
+                "index": 0,
+                "codec_name": "vp8",
+                "codec_type": "video",
+                "width": 1280,
+                "height": 720,
+                "pix_fmt": "yuv420p",
+                "r_frame_rate": "30/1",
+                "avg_frame_rate": "0/0",
+                "time_base": "1/1000"
+            },
+            {
+                "index": 1,
+                "codec_name": "opus",
+                "codec_type": "audio",
+                "sample_rate": "48000",
+                "channels": 2,
+                "r_frame_rate": "0/0",
+                "avg_frame_rate": "0/0",
+                "time_base": "1/1000"
+            }
+        ],
+        "format": {
+            "filename": "clip.webm",
+            "nb_streams": 2,
+            "format_name": "matroska,webm",
+            "probe_score": 100
+        }
+    }"#;
+
+    fn parse(stdout: &str) -> ClipMetadata {
+        let (format, streams) = FFProbeService::parse_output(stdout).unwrap();
+
+        FFProbeService::metadata(format, &streams, 4.5)
+    }
+
+    #[test]
+    fn maps_video_and_audio_streams() {
+        let metadata = parse(SCREEN_RECORDING);
+
+        assert_eq!(metadata.container, "matroska,webm");
+        assert_eq!(metadata.video_codec.as_deref(), Some("vp8"));
+        assert_eq!(metadata.audio_codec.as_deref(), Some("opus"));
+        assert_eq!(metadata.width, Some(1280));
+        assert_eq!(metadata.height, Some(720));
+        assert_eq!(metadata.frame_rate, Some(30.0));
+        assert_eq!(metadata.duration, 4.5);
+        assert_eq!(metadata.audio_channels, Some(2));
+        assert_eq!(metadata.time_base.as_deref(), Some("1/1000"));
+        assert_eq!(metadata.pixel_format.as_deref(), Some("yuv420p"));
+        assert_eq!(metadata.audio_sample_rate, Some(48000));
+    }
+
+    #[test]
+    fn keeps_the_format_duration() {
+        let stdout = r#"{
+            "streams": [{"codec_name": "vp9", "codec_type": "video"}],
+            "format": {"format_name": "matroska,webm", "duration": "12.480000"}
+        }"#;
+
+        let (format, _) = FFProbeService::parse_output(stdout).unwrap();
+
+        assert_eq!(format.duration.as_deref(), Some("12.480000"));
+    }
+
+    #[test]
+    fn maps_an_audio_only_clip() {
+        let stdout = r#"{
+            "streams": [
+                {"codec_name": "opus", "codec_type": "audio", "sample_rate": "44100", "channels": 1}
+            ],
+            "format": {"format_name": "matroska,webm"}
+        }"#;
+
+        let metadata = parse(stdout);
+
+        assert_eq!(metadata.audio_codec.as_deref(), Some("opus"));
+        assert_eq!(metadata.audio_channels, Some(1));
+        assert_eq!(metadata.audio_sample_rate, Some(44100));
+        assert_eq!(metadata.video_codec, None);
+        assert_eq!(metadata.width, None);
+        assert_eq!(metadata.frame_rate, None);
+        assert_eq!(metadata.pixel_format, None);
+    }
+
+    #[test]
+    fn maps_a_video_only_clip() {
+        let stdout = r#"{
+            "streams": [
+                {"codec_name": "png", "codec_type": "video", "width": 64, "height": 48,
+                 "avg_frame_rate": "25/1", "r_frame_rate": "30/1"},
+                {"codec_name": "vp8", "codec_type": "video", "width": 640, "height": 480}
+            ],
+            "format": {"format_name": "matroska,webm"}
+        }"#;
+
+        let metadata = parse(stdout);
+
+        assert_eq!(metadata.video_codec.as_deref(), Some("png"));
+        assert_eq!(metadata.width, Some(64));
+        assert_eq!(metadata.frame_rate, Some(25.0));
+        assert_eq!(metadata.audio_codec, None);
+        assert_eq!(metadata.audio_channels, None);
+        assert_eq!(metadata.audio_sample_rate, None);
+    }
+
+    #[test]
+    fn rejects_unusable_output() {
+        let cases = [
+            ("", "Unreadable ffprobe output"),
+            (r#"{"streams": []}"#, "ffprobe found no container"),
+            (
+                r#"{"streams": [{"codec_type": "data"}], "format": {}}"#,
+                "ffprobe found no audio or video streams",
+            ),
+        ];
+
+        for (stdout, message) in cases {
+            let err = FFProbeService::parse_output(stdout).unwrap_err();
+
+            assert!(err.message.unwrap().starts_with(message), "{}", stdout);
+        }
+    }
+
+    #[test]
+    fn parse_rate_ignores_unknown_rates() {
+        assert_eq!(
+            FFProbeService::parse_rate(Some("30000/1001")),
+            Some(30000.0 / 1001.0)
+        );
+        assert_eq!(FFProbeService::parse_rate(Some("0/0")), None);
+        assert_eq!(FFProbeService::parse_rate(Some("30/0")), None);
+        assert_eq!(FFProbeService::parse_rate(Some("30")), None);
+        assert_eq!(FFProbeService::parse_rate(None), None);
+    }
+
+    #[test]
+    fn packet_end_takes_the_latest_packet() {
+        let stdout = "0.000000,0.033000\n\
+                      0.067000,0.033000\n\
+                      0.021000,0.020000\n\
+                      N/A,0.033000\n\
+                      0.100000,N/A\n\
+                      0.090000\n";
+
+        assert_eq!(FFProbeService::packet_end(stdout), 0.1);
+        assert_eq!(FFProbeService::packet_end(""), 0.0);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::helpers::errors::{ClipError, ClipErrorType};
+use crate::models::ids::SessionId;
+use crate::models::{JobState, MergeJob, MergeProgress, MergedOutputRecord};
+use crate::services::database::Database;
+use crate::services::ffmpeg::{FFMpegService, MergeSpec};
+use crate::services::storage::Storage;
+use crate::settings::FFmpegSettings;
+use actix::prelude::*;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
+
+/// Sent to the submitter whenever a job it queued changes state or reports progress.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct JobUpdate {
+    pub correlation_id: String,
+    pub job: MergeJob,
+    /// Set when the update is a progress report rather than a state change.
+    pub progress: Option<MergeProgress>,
+}
+
+/// Where the updates of a job go, a `/ws/clips/submit` socket for merges queued over it.
+#[derive(Debug, Clone)]
+pub struct JobSubscriber {
+    pub correlation_id: String,
+    pub recipient: Recipient<JobUpdate>,
+}
+
+impl JobSubscriber {
+    fn send(&self, job: MergeJob, progress: Option<MergeProgress>) {
+        self.recipient.do_send(JobUpdate {
+            correlation_id: self.correlation_id.clone(),
+            job,
+            progress,
+        });
+    }
+}
+
+/// Runs merges in the background on the tokio runtime, at most `MERGE_CONCURRENCY`
+/// at a time, so encodes never hold up an actix worker. Finished merges are published
+/// to `storage`, finished jobs stay queryable through `GET /jobs/{id}` for
+/// `FINISHED_JOB_RETENTION_HOURS`.
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    jobs: Arc<Mutex<HashMap<Uuid, MergeJob>>>,
+    permits: Arc<Semaphore>,
+    ffmpeg: FFmpegSettings,
+    storage: Storage,
+}
+
+impl JobQueue {
+    pub fn new(ffmpeg: &FFmpegSettings, storage: Storage) -> JobQueue {
+        JobQueue {
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            permits: Arc::new(Semaphore::new(ffmpeg.merge_concurrency)),
+            ffmpeg: ffmpeg.clone(),
+            storage,
+        }
+    }
+
+    pub fn get(&self, job_id: &Uuid) -> Option<MergeJob> {
+        self.jobs.lock().unwrap().get(job_id).cloned()
+    }
+
+    /// Whether a merge for the session is queued or running.
+    pub fn is_active(&self, session_id: &SessionId) -> bool {
+        Self::has_active(&self.jobs.lock().unwrap(), session_id.to_string().as_str())
+    }
+
+    /// Waits for one of the `MERGE_CONCURRENCY` encoding slots. Anything else that runs
+    /// ffmpeg holds one too, so encodes are limited as a whole.
+    pub async fn reserve(&self) -> Result<OwnedSemaphorePermit, ClipError> {
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|err| ClipError::from(err.to_string()))
+    }
+
+    /// Queues a merge and returns straight away. `subscriber` hears about every state
+    /// change, without one the job can only be followed through `GET /jobs/{id}`. Fails with
+    /// `MergeRunning` while another merge of the session is queued or running, as both would
+    /// write the same output.
+    pub fn submit_merge(
+        &self,
+        spec: MergeSpec,
+        session_id: SessionId,
+        db: Database,
+        subscriber: Option<JobSubscriber>,
+    ) -> Result<MergeJob, ClipError> {
+        let job = MergeJob {
+            id: Uuid::new_v4(),
+            session_id: session_id.to_string(),
+            state: JobState::Queued,
+            clip_ids: spec.clips.iter().map(|clip| clip.id.clone()).collect(),
+            profile: spec.profile.name.clone(),
+            progress: None,
+            output: None,
+            error: None,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+        };
+
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            self.prune(&mut jobs);
+
+            //Checked under the same lock as the insert, so two submits can't both pass
+            if Self::has_active(&jobs, job.session_id.as_str()) {
+                let mut error =
+                    ClipError::from(String::from("A merge is already running for this session"));
+                error.set_type(ClipErrorType::MergeRunning);
+
+                return Err(error);
+            }
+
+            jobs.insert(job.id, job.clone());
+        }
+
+        let queue = self.clone();
+        let storage = self.storage.clone();
+        let job_id = job.id;
+
+        actix_web::rt::spawn(async move {
+            let _permit = match queue.reserve().await {
+                Ok(permit) => permit,
+                Err(err) => {
+                    queue.finish(job_id, Err(err), subscriber.as_ref());
+                    return;
+                }
+            };
+
+            let job = queue.update(job_id, |job| {
+                job.state = JobState::Running;
+                job.started_at = Some(Utc::now());
+            });
+            if let (Some(job), Some(subscriber)) = (job, &subscriber) {
+                subscriber.send(job, None);
+            }
+
+            let on_progress = |progress: MergeProgress| {
+                let job = queue.update(job_id, |job| job.progress = Some(progress.clone()));
+                if let (Some(job), Some(subscriber)) = (job, &subscriber) {
+                    subscriber.send(job, Some(progress));
+                }
+            };
+
+            let result = FFMpegService::merge_clips(
+                &spec,
+                &session_id,
+                &queue.ffmpeg,
+                &db,
+                &storage,
+                on_progress,
+            )
+            .await;
+
+            //Audio merges have no frames to preview
+            if let Some(output) = result
+                .as_ref()
+                .ok()
+                .filter(|output| output.audio_format.is_none())
+            {
+                if let Err(err) = FFMpegService::generate_previews(
+                    &session_id,
+                    output.duration,
+                    &queue.ffmpeg,
+                    &storage,
+                )
+                .await
+                {
+                    log::warn!("Previews of session {} failed: {}", session_id, err);
+                }
+            }
+
+            queue.finish(job_id, result, subscriber.as_ref());
+        });
+
+        Ok(job)
+    }
+
+    fn finish(
+        &self,
+        job_id: Uuid,
+        result: Result<MergedOutputRecord, ClipError>,
+        subscriber: Option<&JobSubscriber>,
+    ) {
+        let job = self.update(job_id, |job| {
+            job.finished_at = Some(Utc::now());
+
+            match result {
+                Ok(output) => {
+                    job.state = JobState::Succeeded;
+                    job.output = Some(output);
+                }
+                Err(err) => {
+                    log::error!("Merge job {} failed: {}", job_id, err);
+                    job.state = JobState::Failed;
+                    job.error = err.message;
+                }
+            }
+        });
+
+        if let (Some(job), Some(subscriber)) = (job, subscriber) {
+            subscriber.send(job, None);
+        }
+    }
+
+    fn update<F>(&self, job_id: Uuid, change: F) -> Option<MergeJob>
+    where
+        F: FnOnce(&mut MergeJob),
+    {
+        let mut jobs = self.jobs.lock().unwrap();
+
+        jobs.get_mut(&job_id).map(|job| {
+            change(job);
+            job.clone()
+        })
+    }
+
+    fn has_active(jobs: &HashMap<Uuid, MergeJob>, session_id: &str) -> bool {
+        jobs.values().any(|job| {
+            job.session_id == session_id
+                && matches!(job.state, JobState::Queued | JobState::Running)
+        })
+    }
+
+    fn prune(&self, jobs: &mut HashMap<Uuid, MergeJob>) {
+        let cutoff = Utc::now() - self.ffmpeg.finished_job_retention();
+
+        jobs.retain(|_, job| match job.finished_at {
+            Some(finished_at) => finished_at > cutoff,
+            None => true,
+        });
+    }
+}
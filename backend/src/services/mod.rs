@@ -1 +1,11 @@
+pub mod database;
+pub mod export;
+pub mod feedback;
 pub mod ffmpeg;
+pub mod ffprobe;
+pub mod jobs;
+pub mod profiles;
+pub mod retention;
+pub mod session;
+pub mod storage;
+pub mod upload;
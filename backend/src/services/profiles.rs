@@ -0,0 +1,424 @@
+use crate::helpers::errors::{ClipError, ClipErrorType};
+use crate::models::ClipMetadata;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+const BUILTIN_PROFILE: &str = "default";
+
+/// Merged outputs are WebM, so only encoders that container accepts are allowed.
+const VIDEO_CODECS: [&str; 3] = ["libvpx", "libvpx-vp9", "libaom-av1"];
+const AUDIO_CODECS: [&str; 2] = ["libopus", "libvorbis"];
+
+/// How a merged output is encoded. Unset options are left to ffmpeg's defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncodingProfile {
+    /// Filled in from the profile's table name.
+    #[serde(skip_deserializing)]
+    pub name: String,
+    pub video_codec: String,
+    pub audio_codec: String,
+    pub crf: Option<u32>,
+    /// ffmpeg bitrate syntax, e.g. `800k`. `0` together with `crf` selects constant quality.
+    pub video_bitrate: Option<String>,
+    pub audio_bitrate: Option<String>,
+    /// Outputs taller than this are scaled down, keeping the aspect ratio.
+    pub max_height: Option<u32>,
+    pub threads: Option<u32>,
+    pub cpu_used: Option<i32>,
+    pub tile_columns: Option<u32>,
+    #[serde(default)]
+    pub row_mt: bool,
+    /// Re-encode even when the clips could be stream copied, e.g. to enforce a bitrate.
+    #[serde(default)]
+    pub always_encode: bool,
+}
+
+impl EncodingProfile {
+    /// The settings `merge_clips` used before profiles existed.
+    fn builtin() -> EncodingProfile {
+        EncodingProfile {
+            name: BUILTIN_PROFILE.to_string(),
+            video_codec: "libvpx-vp9".to_string(),
+            audio_codec: "libopus".to_string(),
+            crf: Some(23),
+            video_bitrate: None,
+            audio_bitrate: None,
+            max_height: None,
+            threads: Some(8),
+            cpu_used: Some(8),
+            tile_columns: Some(2),
+            row_mt: true,
+            always_encode: false,
+        }
+    }
+
+    /// Whether a clip already is what this profile would produce, so it can be stream
+    /// copied into the output instead of encoded.
+    pub fn accepts(&self, clip: &ClipMetadata) -> bool {
+        if self.always_encode {
+            return false;
+        }
+
+        let fits = match (self.max_height, clip.height) {
+            (Some(max_height), Some(height)) => height <= max_height as i32,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+
+        fits && clip.video_codec.as_deref() == Some(Self::codec_name(&self.video_codec))
+            && clip.audio_codec.as_deref() == Some(Self::codec_name(&self.audio_codec))
+    }
+
+    /// The codec ffprobe reports for streams made by `encoder`.
+    fn codec_name(encoder: &str) -> &str {
+        match encoder {
+            "libvpx" => "vp8",
+            "libvpx-vp9" => "vp9",
+            "libaom-av1" => "av1",
+            "libopus" => "opus",
+            "libvorbis" => "vorbis",
+            other => other,
+        }
+    }
+
+    /// Filter applied to the concatenated video, if the profile caps its height.
+    pub fn scale_filter(&self) -> Option<String> {
+        self.max_height
+            .map(|height| format!("scale=-2:'min(ih,{})'", height))
+    }
+
+    /// Codec and resource arguments, placed after the inputs and maps.
+    pub fn ffmpeg_args(&self) -> Vec<String> {
+        let mut args = vec!["-c:v".to_string(), self.video_codec.clone()];
+
+        if let Some(crf) = self.crf {
+            args.push("-crf".to_string());
+            args.push(crf.to_string());
+        }
+        if let Some(bitrate) = &self.video_bitrate {
+            args.push("-b:v".to_string());
+            args.push(bitrate.clone());
+        }
+
+        args.push("-c:a".to_string());
+        args.push(self.audio_codec.clone());
+
+        if let Some(bitrate) = &self.audio_bitrate {
+            args.push("-b:a".to_string());
+            args.push(bitrate.clone());
+        }
+
+        if let Some(cpu_used) = self.cpu_used {
+            args.push("-cpu-used".to_string());
+            args.push(cpu_used.to_string());
+        }
+        if self.row_mt {
+            args.push("-row-mt".to_string());
+            args.push("1".to_string());
+        }
+        if let Some(threads) = self.threads {
+            args.push("-threads".to_string());
+            args.push(threads.to_string());
+        }
+        if let Some(tile_columns) = self.tile_columns {
+            args.push("-tile-columns".to_string());
+            args.push(tile_columns.to_string());
+        }
+
+        if self.video_codec.starts_with("libvpx") {
+            args.push("-frame-parallel".to_string());
+            args.push("1".to_string());
+            args.push("-auto-alt-ref".to_string());
+            args.push("1".to_string());
+        }
+
+        args
+    }
+
+    fn validate(&self) -> Result<(), ClipError> {
+        if !VIDEO_CODECS.contains(&self.video_codec.as_str()) {
+            return Err(ClipError::from(format!(
+                "Profile {} uses video codec {}, expected one of {:?}",
+                self.name, self.video_codec, VIDEO_CODECS
+            )));
+        }
+
+        if !AUDIO_CODECS.contains(&self.audio_codec.as_str()) {
+            return Err(ClipError::from(format!(
+                "Profile {} uses audio codec {}, expected one of {:?}",
+                self.name, self.audio_codec, AUDIO_CODECS
+            )));
+        }
+
+        if self.max_height == Some(0) || self.threads == Some(0) {
+            return Err(ClipError::from(format!(
+                "Profile {} sets max_height or threads to 0",
+                self.name
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfilesFile {
+    default: String,
+    profiles: HashMap<String, EncodingProfile>,
+}
+
+/// The named encoding profiles a merge request can pick from, read once at startup from
+/// the TOML file at `ffmpeg.encoding_profiles` (`profiles.toml` by default). Without that file
+/// a single `default` profile matching the original hard-coded settings is used.
+#[derive(Debug, Clone)]
+pub struct EncodingProfiles {
+    default: String,
+    profiles: Arc<HashMap<String, EncodingProfile>>,
+}
+
+impl EncodingProfiles {
+    pub fn load(path: &Path) -> Result<EncodingProfiles, ClipError> {
+        if !path.exists() {
+            log::info!(
+                "No encoding profiles at {}, using the built-in default",
+                path.display()
+            );
+
+            let profile = EncodingProfile::builtin();
+
+            return Ok(EncodingProfiles {
+                default: profile.name.clone(),
+                profiles: Arc::new(HashMap::from([(profile.name.clone(), profile)])),
+            });
+        }
+
+        let contents = std::fs::read_to_string(path)?;
+        let file: ProfilesFile = toml::from_str(contents.as_str()).map_err(|err| {
+            ClipError::from(format!("Invalid profiles in {}: {}", path.display(), err))
+        })?;
+
+        let mut profiles = HashMap::new();
+        for (name, mut profile) in file.profiles {
+            profile.name = name.clone();
+            profile.validate()?;
+
+            profiles.insert(name, profile);
+        }
+
+        if !profiles.contains_key(&file.default) {
+            return Err(ClipError::from(format!(
+                "Default profile {} is not defined in {}",
+                file.default,
+                path.display()
+            )));
+        }
+
+        Ok(EncodingProfiles {
+            default: file.default,
+            profiles: Arc::new(profiles),
+        })
+    }
+
+    /// The named profile, or the default one when no name is given.
+    pub fn resolve(&self, name: Option<&str>) -> Result<EncodingProfile, ClipError> {
+        let name = name.unwrap_or(self.default.as_str());
+
+        match self.profiles.get(name) {
+            Some(profile) => Ok(profile.clone()),
+            None => {
+                let mut error = ClipError::from(format!("Unknown encoding profile: {}", name));
+                error.set_type(ClipErrorType::UnknownProfile);
+
+                Err(error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ids::SessionId;
+    use std::path::PathBuf;
+
+    /// Writes `contents` to a profiles file of its own.
+    fn profiles_file(contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("profiles-test-{}.toml", SessionId::generate()));
+        std::fs::write(&path, contents).unwrap();
+
+        path
+    }
+
+    fn load(contents: &str) -> Result<EncodingProfiles, ClipError> {
+        let path = profiles_file(contents);
+        let profiles = EncodingProfiles::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        profiles
+    }
+
+    fn clip(video_codec: &str, audio_codec: &str, height: Option<i32>) -> ClipMetadata {
+        ClipMetadata {
+            video_codec: Some(video_codec.to_string()),
+            audio_codec: Some(audio_codec.to_string()),
+            height,
+            ..ClipMetadata::default()
+        }
+    }
+
+    #[test]
+    fn clips_in_the_profiles_codecs_are_accepted() {
+        let profile = EncodingProfile::builtin();
+
+        assert!(profile.accepts(&clip("vp9", "opus", Some(1080))));
+        assert!(!profile.accepts(&clip("vp8", "opus", Some(1080))));
+        assert!(!profile.accepts(&clip("vp9", "vorbis", Some(1080))));
+    }
+
+    #[test]
+    fn clips_taller_than_the_height_cap_are_not_accepted() {
+        let profile = EncodingProfile {
+            max_height: Some(720),
+            ..EncodingProfile::builtin()
+        };
+
+        assert!(profile.accepts(&clip("vp9", "opus", Some(720))));
+        assert!(!profile.accepts(&clip("vp9", "opus", Some(1080))));
+        assert!(!profile.accepts(&clip("vp9", "opus", None)));
+    }
+
+    #[test]
+    fn always_encode_accepts_no_clip() {
+        let profile = EncodingProfile {
+            always_encode: true,
+            ..EncodingProfile::builtin()
+        };
+
+        assert!(!profile.accepts(&clip("vp9", "opus", Some(720))));
+    }
+
+    #[test]
+    fn the_shipped_profiles_load() {
+        let profiles = EncodingProfiles::load(Path::new("profiles.toml")).unwrap();
+
+        assert_eq!(profiles.resolve(None).unwrap().name, "balanced");
+        assert!(profiles.resolve(Some("fast-preview")).is_ok());
+    }
+
+    #[test]
+    fn a_missing_file_falls_back_to_the_builtin_profile() {
+        let profiles = EncodingProfiles::load(Path::new("no-such-profiles.toml")).unwrap();
+        let profile = profiles.resolve(None).unwrap();
+
+        assert_eq!(profile.name, BUILTIN_PROFILE);
+        assert_eq!(profile.video_codec, "libvpx-vp9");
+    }
+
+    #[test]
+    fn profiles_resolve_by_name_or_to_the_default() {
+        let profiles = load(
+            r#"
+            default = "small"
+
+            [profiles.small]
+            video_codec = "libvpx"
+            audio_codec = "libvorbis"
+            max_height = 480
+
+            [profiles.large]
+            video_codec = "libaom-av1"
+            audio_codec = "libopus"
+            "#,
+        )
+        .unwrap();
+
+        let default = profiles.resolve(None).unwrap();
+        assert_eq!(default.name, "small");
+        assert_eq!(default.max_height, Some(480));
+        assert_eq!(profiles.resolve(Some("large")).unwrap().name, "large");
+        assert_eq!(
+            profiles.resolve(Some("huge")).unwrap_err().code(),
+            "unknown_profile"
+        );
+    }
+
+    #[test]
+    fn profiles_with_an_unknown_codec_are_rejected() {
+        for (codecs, problem) in [
+            (
+                "video_codec = \"libx264\"\naudio_codec = \"libopus\"",
+                "Profile web uses video codec libx264",
+            ),
+            (
+                "video_codec = \"libvpx-vp9\"\naudio_codec = \"aac\"",
+                "Profile web uses audio codec aac",
+            ),
+        ] {
+            let err = load(format!("default = \"web\"\n[profiles.web]\n{}", codecs).as_str())
+                .unwrap_err();
+
+            assert!(err.message.unwrap().starts_with(problem));
+        }
+    }
+
+    #[test]
+    fn profiles_with_a_zero_height_or_thread_count_are_rejected() {
+        for setting in ["max_height = 0", "threads = 0"] {
+            let err = load(
+                format!(
+                    "default = \"web\"\n[profiles.web]\nvideo_codec = \"libvpx\"\n\
+                     audio_codec = \"libopus\"\n{}",
+                    setting
+                )
+                .as_str(),
+            )
+            .unwrap_err();
+
+            assert_eq!(
+                err.message.unwrap(),
+                "Profile web sets max_height or threads to 0"
+            );
+        }
+    }
+
+    #[test]
+    fn an_undefined_default_profile_is_rejected() {
+        let err = load(
+            r#"
+            default = "missing"
+
+            [profiles.web]
+            video_codec = "libvpx"
+            audio_codec = "libopus"
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(err
+            .message
+            .unwrap()
+            .starts_with("Default profile missing is not defined"));
+    }
+
+    #[test]
+    fn unknown_profile_options_are_rejected() {
+        let err = load(
+            r#"
+            default = "web"
+
+            [profiles.web]
+            video_codec = "libvpx"
+            audio_codec = "libopus"
+            preset = "slow"
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(err.message.unwrap().starts_with("Invalid profiles in"));
+    }
+}
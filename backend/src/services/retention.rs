@@ -0,0 +1,485 @@
+use crate::helpers::errors::ClipError;
+use crate::models::ids::{ClipId, SessionId};
+use crate::models::AudioFormat;
+use crate::services::database::Database;
+use crate::services::jobs::JobQueue;
+use crate::services::storage::Storage;
+use crate::services::upload::UploadUsage;
+use actix::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs;
+
+const DEFAULT_SUBMITTED_RETENTION_HOURS: u32 = 30 * 24;
+const DEFAULT_RETENTION_SWEEP_MINUTES: u32 = 15;
+
+/// How long session directories are kept, the `[retention]` settings or
+/// `SESSION_IDLE_MINUTES`, `SUBMITTED_RETENTION_HOURS` and `RETENTION_SWEEP_MINUTES`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionPolicy {
+    /// An unsubmitted session is removed once nothing has touched it for this long. Unset, it
+    /// matches the session token lifetime, after which an upload can't be resumed anyway.
+    pub session_idle_minutes: Option<u32>,
+    /// A submitted session is removed this long after its latest merge.
+    pub submitted_retention_hours: u32,
+    pub sweep_minutes: u32,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> RetentionPolicy {
+        RetentionPolicy {
+            session_idle_minutes: None,
+            submitted_retention_hours: DEFAULT_SUBMITTED_RETENTION_HOURS,
+            sweep_minutes: DEFAULT_RETENTION_SWEEP_MINUTES,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn session_idle(&self, session_ttl: Duration) -> Duration {
+        self.session_idle_minutes
+            .map(|minutes| Duration::minutes(i64::from(minutes)))
+            .unwrap_or(session_ttl)
+    }
+
+    pub fn submitted_retention(&self) -> Duration {
+        Duration::hours(i64::from(self.submitted_retention_hours))
+    }
+
+    pub fn sweep_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(u64::from(self.sweep_minutes) * 60)
+    }
+}
+
+/// What a cleanup removed from `TEMP_DIRECTORY`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Reclaimed {
+    pub sessions: u64,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl AddAssign for Reclaimed {
+    fn add_assign(&mut self, other: Reclaimed) {
+        self.sessions += other.sessions;
+        self.files += other.files;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Files below a directory, their total size and the newest modification time.
+#[derive(Debug, Default)]
+struct Usage {
+    files: u64,
+    bytes: u64,
+    modified: Option<SystemTime>,
+}
+
+pub struct RetentionService {}
+
+impl RetentionService {
+    /// Removes every session directory the policy says has expired, along with its stored
+    /// objects and database records. Sessions with a merge queued or running are left for
+    /// the next sweep. `session_ttl` is the idle time when the policy doesn't set one.
+    pub async fn sweep(
+        policy: RetentionPolicy,
+        session_ttl: Duration,
+        db: &Database,
+        jobs: &JobQueue,
+        storage: &Storage,
+        usage: &UploadUsage,
+    ) -> Result<Reclaimed, ClipError> {
+        let mut reclaimed = Reclaimed::default();
+
+        let mut entries = match fs::read_dir(storage.temp_directory()).await {
+            Ok(entries) => entries,
+            Err(_) => return Ok(reclaimed),
+        };
+
+        let now = Utc::now();
+        while let Some(entry) = entries.next_entry().await? {
+            let session_id = match entry.file_name().to_str().map(SessionId::parse) {
+                Some(Ok(session_id)) => session_id,
+                _ => continue,
+            };
+
+            if !entry.file_type().await?.is_dir() || jobs.is_active(&session_id) {
+                continue;
+            }
+
+            match Self::sweep_session(session_id, policy, session_ttl, db, storage, now).await {
+                Ok(Some(session)) => {
+                    usage.forget(&session_id);
+                    reclaimed += session;
+                }
+                Ok(None) => {}
+                Err(err) => log::warn!("Retention sweep skipped {}: {}", session_id, err),
+            }
+        }
+
+        Ok(reclaimed)
+    }
+
+    /// Called when an upload socket closes. Leftovers of interrupted writes and clips that
+    /// never received a chunk are removed, and the session directory too once nothing is
+    /// left in it. Held chunks and finalized clips stay for the client to resume or merge.
+    pub async fn release(session_id: SessionId, root: &Path) -> Result<Reclaimed, ClipError> {
+        let mut reclaimed = Reclaimed::default();
+        let directory = session_id.directory(root);
+
+        let mut entries = match fs::read_dir(&directory).await {
+            Ok(entries) => entries,
+            Err(_) => return Ok(reclaimed),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.to_str().unwrap_or_default();
+
+            let is_clip = name
+                .split('.')
+                .next()
+                .map(|name| ClipId::parse(name).is_ok())
+                .unwrap_or(false);
+
+            if !is_clip {
+                continue;
+            }
+
+            if name.ends_with(".partial") {
+                reclaimed += Self::remove_file(&entry.path()).await?;
+            } else if name.ends_with(".parts") {
+                let mut parts = fs::read_dir(entry.path()).await?;
+                while let Some(part) = parts.next_entry().await? {
+                    if part.path().extension().is_some_and(|ext| ext == "partial") {
+                        reclaimed += Self::remove_file(&part.path()).await?;
+                    }
+                }
+
+                // Fails while chunks are held, which is exactly when it should stay.
+                let _ = fs::remove_dir(entry.path()).await;
+            }
+        }
+
+        if fs::remove_dir(&directory).await.is_ok() {
+            reclaimed.sessions += 1;
+        }
+
+        Ok(reclaimed)
+    }
+
+    async fn sweep_session(
+        session_id: SessionId,
+        policy: RetentionPolicy,
+        session_ttl: Duration,
+        db: &Database,
+        storage: &Storage,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Reclaimed>, ClipError> {
+        let root = storage.temp_directory();
+        let directory = session_id.directory(root);
+        let usage = Self::usage(&directory).await?;
+        let session = db.get_session(session_id.to_string().as_str()).await?;
+
+        let merged_at = Self::merged_at(&session_id, root).await;
+        let submitted_at = session
+            .as_ref()
+            .and_then(|session| session.submitted_at)
+            .or(merged_at);
+        let last_activity = session
+            .as_ref()
+            .map(|session| session.last_seen_at)
+            .into_iter()
+            .chain(usage.modified.map(DateTime::<Utc>::from))
+            .max();
+
+        let reason = match (submitted_at, last_activity) {
+            (Some(submitted_at), _) if submitted_at + policy.submitted_retention() <= now => {
+                "submitted"
+            }
+            (Some(_), _) => return Ok(None),
+            (None, Some(last_activity))
+                if last_activity + policy.session_idle(session_ttl) > now =>
+            {
+                return Ok(None)
+            }
+            (None, _) => "idle",
+        };
+
+        storage.remove_session(&session_id).await?;
+        fs::remove_dir_all(&directory).await?;
+
+        if session.is_some() {
+            db.delete_session(session_id.to_string().as_str()).await?;
+        }
+
+        log::info!(
+            "Removed {} session {}, reclaimed {} bytes in {} files",
+            reason,
+            session_id,
+            usage.bytes,
+            usage.files
+        );
+
+        Ok(Some(Reclaimed {
+            sessions: 1,
+            files: usage.files,
+            bytes: usage.bytes,
+        }))
+    }
+
+    /// When the newest merged output on disk was written, video and voice merges alike. The
+    /// in-memory database is lost on restart, so the files on disk also count as a merge.
+    async fn merged_at(session_id: &SessionId, root: &Path) -> Option<DateTime<Utc>> {
+        let extensions = std::iter::once("webm").chain(
+            AudioFormat::ALL
+                .iter()
+                .map(|audio_format| audio_format.extension()),
+        );
+
+        let mut merged_at = None;
+        for extension in extensions {
+            let modified = fs::metadata(session_id.output_path(root, "output", extension))
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .map(DateTime::<Utc>::from);
+
+            merged_at = merged_at.max(modified);
+        }
+
+        merged_at
+    }
+
+    async fn usage(directory: &Path) -> Result<Usage, ClipError> {
+        let mut usage = Usage {
+            modified: fs::metadata(directory).await?.modified().ok(),
+            ..Usage::default()
+        };
+        let mut pending: Vec<PathBuf> = vec![directory.to_path_buf()];
+
+        while let Some(directory) = pending.pop() {
+            let mut entries = fs::read_dir(&directory).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let metadata = entry.metadata().await?;
+
+                if let Ok(modified) = metadata.modified() {
+                    usage.modified = usage.modified.max(Some(modified));
+                }
+
+                if metadata.is_dir() {
+                    pending.push(entry.path());
+                } else {
+                    usage.files += 1;
+                    usage.bytes += metadata.len();
+                }
+            }
+        }
+
+        Ok(usage)
+    }
+
+    async fn remove_file(path: &Path) -> Result<Reclaimed, ClipError> {
+        let bytes = fs::metadata(path).await?.len();
+        fs::remove_file(path).await?;
+
+        Ok(Reclaimed {
+            sessions: 0,
+            files: 1,
+            bytes,
+        })
+    }
+}
+
+/// Sweeps `TEMP_DIRECTORY` once on startup and then every `RETENTION_SWEEP_MINUTES`.
+pub struct RetentionSweeper {
+    pub policy: RetentionPolicy,
+    pub session_ttl: Duration,
+    pub db: Database,
+    pub jobs: JobQueue,
+    pub storage: Storage,
+    pub usage: UploadUsage,
+}
+
+impl Actor for RetentionSweeper {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.sweep(ctx);
+
+        ctx.run_interval(self.policy.sweep_interval(), |act, ctx| act.sweep(ctx));
+    }
+}
+
+impl RetentionSweeper {
+    fn sweep(&mut self, ctx: &mut Context<Self>) {
+        let policy = self.policy;
+        let session_ttl = self.session_ttl;
+        let db = self.db.clone();
+        let jobs = self.jobs.clone();
+        let storage = self.storage.clone();
+        let usage = self.usage.clone();
+
+        // Waiting keeps a slow sweep from overlapping the next one.
+        ctx.wait(
+            async move {
+                match RetentionService::sweep(policy, session_ttl, &db, &jobs, &storage, &usage)
+                    .await
+                {
+                    Ok(reclaimed) if reclaimed.sessions > 0 => log::info!(
+                        "Retention sweep reclaimed {} bytes in {} files from {} sessions",
+                        reclaimed.bytes,
+                        reclaimed.files,
+                        reclaimed.sessions
+                    ),
+                    Ok(_) => {}
+                    Err(err) => log::error!("Retention sweep failed: {}", err),
+                }
+            }
+            .into_actor(self),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::storage::StorageSettings;
+
+    async fn session_directory() -> (PathBuf, SessionId) {
+        let root = std::env::temp_dir().join(format!("retention-test-{}", SessionId::generate()));
+        let session_id = SessionId::generate();
+        fs::create_dir_all(session_id.directory(&root))
+            .await
+            .unwrap();
+
+        (root, session_id)
+    }
+
+    async fn write(path: &Path, contents: &[u8]) {
+        fs::create_dir_all(path.parent().unwrap()).await.unwrap();
+        fs::write(path, contents).await.unwrap();
+    }
+
+    async fn exists(path: &Path) -> bool {
+        fs::metadata(path).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn release_keeps_held_parts_and_drops_partial_files() {
+        let (root, session_id) = session_directory().await;
+        let held = ClipId::generate().parts_directory(&root, &session_id);
+        let finalized = ClipId::generate().path(&root, &session_id);
+
+        write(&held.join("0000000000.part"), b"chunk").await;
+        write(&held.join("0000000001.partial"), b"cut").await;
+        write(&finalized, b"clip").await;
+        write(&finalized.with_extension("webm.partial"), b"joined").await;
+
+        let reclaimed = RetentionService::release(session_id, &root).await.unwrap();
+
+        assert_eq!(reclaimed.files, 2);
+        assert_eq!(reclaimed.bytes, 9);
+        assert_eq!(reclaimed.sessions, 0);
+        assert!(exists(&held.join("0000000000.part")).await);
+        assert!(!exists(&held.join("0000000001.partial")).await);
+        assert!(exists(&finalized).await);
+        assert!(!exists(&finalized.with_extension("webm.partial")).await);
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn release_removes_a_session_directory_left_empty() {
+        let (root, session_id) = session_directory().await;
+        let parts = ClipId::generate().parts_directory(&root, &session_id);
+
+        write(&parts.join("0000000000.partial"), b"cut").await;
+
+        let reclaimed = RetentionService::release(session_id, &root).await.unwrap();
+
+        assert_eq!(reclaimed.files, 1);
+        assert_eq!(reclaimed.sessions, 1);
+        assert!(!exists(&session_id.directory(&root)).await);
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    async fn sweep_after(
+        root: &Path,
+        session_id: SessionId,
+        policy: RetentionPolicy,
+        elapsed: Duration,
+    ) -> Option<Reclaimed> {
+        let storage = Storage::from_settings(&StorageSettings::default(), root).unwrap();
+
+        RetentionService::sweep_session(
+            session_id,
+            policy,
+            Duration::hours(24),
+            &Database::memory(),
+            &storage,
+            Utc::now() + elapsed,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn unmerged_sessions_are_removed_once_idle_for_the_session_ttl() {
+        let (root, session_id) = session_directory().await;
+        write(&ClipId::generate().path(&root, &session_id), b"clip").await;
+        let policy = RetentionPolicy::default();
+
+        let kept = sweep_after(&root, session_id, policy, Duration::hours(23)).await;
+        assert!(kept.is_none());
+        assert!(exists(&session_id.directory(&root)).await);
+
+        let removed = sweep_after(&root, session_id, policy, Duration::hours(25)).await;
+        assert_eq!(removed.unwrap().files, 1);
+        assert!(!exists(&session_id.directory(&root)).await);
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn session_idle_minutes_overrides_the_session_ttl() {
+        let (root, session_id) = session_directory().await;
+        write(&ClipId::generate().path(&root, &session_id), b"clip").await;
+        let policy = RetentionPolicy {
+            session_idle_minutes: Some(30),
+            ..RetentionPolicy::default()
+        };
+
+        let removed = sweep_after(&root, session_id, policy, Duration::hours(1)).await;
+        assert!(removed.is_some());
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn merged_sessions_are_kept_for_the_submitted_retention() {
+        for extension in ["webm", "ogg", "mp3"] {
+            let (root, session_id) = session_directory().await;
+            write(
+                &session_id.output_path(&root, "output", extension),
+                b"merged",
+            )
+            .await;
+            let policy = RetentionPolicy::default();
+
+            let kept = sweep_after(&root, session_id, policy, Duration::days(2)).await;
+            assert!(kept.is_none(), "{} merge removed as idle", extension);
+
+            let removed = sweep_after(&root, session_id, policy, Duration::days(31)).await;
+            assert!(removed.is_some(), "{} merge kept", extension);
+            assert!(!exists(&session_id.directory(&root)).await);
+
+            fs::remove_dir_all(&root).await.unwrap();
+        }
+    }
+}
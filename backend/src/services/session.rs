@@ -0,0 +1,147 @@
+use crate::helpers::errors::{ClipError, ClipErrorType};
+use crate::helpers::utilities::Utilities;
+use crate::models::ids::SessionId;
+use crate::settings::Settings;
+use actix_web::{web, HttpRequest};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionClaims {
+    pub sub: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IssuedSession {
+    pub session_id: String,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub struct SessionService {}
+
+impl SessionService {
+    /// Starts a session whose token stays valid for `ttl`.
+    pub fn issue(secret: &str, ttl: Duration) -> Result<IssuedSession, ClipError> {
+        let session_id = SessionId::generate().to_string();
+
+        let issued_at = Utc::now();
+        let expires_at = issued_at + ttl;
+
+        let claims = SessionClaims {
+            sub: session_id.clone(),
+            iat: issued_at.timestamp(),
+            exp: expires_at.timestamp(),
+        };
+
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .map_err(|err| ClipError::from(err.to_string()))?;
+
+        Ok(IssuedSession {
+            session_id,
+            token,
+            expires_at,
+        })
+    }
+
+    /// Verifies the token's signature and expiry and returns the session id it carries.
+    pub fn verify(token: &str, secret: &str) -> Result<SessionId, ClipError> {
+        let claims = decode::<SessionClaims>(
+            token,
+            &DecodingKey::from_secret(secret.as_bytes()),
+            &Validation::default(),
+        )
+        .map_err(|err| {
+            let mut error = ClipError::from(format!("Invalid session: {}", err));
+            error.set_type(ClipErrorType::Unauthorized);
+            error
+        })?
+        .claims;
+
+        SessionId::parse(claims.sub.as_str())
+    }
+
+    /// Reads the `X-FDot-Session` cookie and verifies the signed session token it holds.
+    pub fn verify_request(req: &HttpRequest) -> Result<SessionId, ClipError> {
+        let token = match req.cookies() {
+            Ok(cookies) => Utilities::get_cookie_value(cookies.deref(), "X-FDot-Session"),
+            Err(_) => String::from(""),
+        };
+
+        if token.is_empty() {
+            let mut error = ClipError::from(String::from("Missing session"));
+            error.set_type(ClipErrorType::Unauthorized);
+
+            return Err(error);
+        }
+
+        let settings = req
+            .app_data::<web::Data<Settings>>()
+            .expect("SETTINGS_NOT_REGISTERED");
+
+        Self::verify(token.as_str(), settings.session_secret.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "a-test-secret-that-is-long-enough";
+
+    #[test]
+    fn issued_tokens_verify_to_their_session() {
+        let issued = SessionService::issue(SECRET, Duration::hours(1)).unwrap();
+
+        let session_id = SessionService::verify(&issued.token, SECRET).unwrap();
+
+        assert_eq!(session_id.to_string(), issued.session_id);
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let issued = SessionService::issue(SECRET, Duration::hours(-1)).unwrap();
+
+        let err = SessionService::verify(&issued.token, SECRET).unwrap_err();
+
+        assert_eq!(err.code(), "unauthorized");
+    }
+
+    #[test]
+    fn tokens_signed_with_another_secret_are_rejected() {
+        let issued = SessionService::issue(SECRET, Duration::hours(1)).unwrap();
+
+        let err = SessionService::verify(&issued.token, "another-secret-that-is-long-enough")
+            .unwrap_err();
+
+        assert_eq!(err.code(), "unauthorized");
+    }
+
+    #[test]
+    fn tokens_carrying_anything_but_a_session_id_are_rejected() {
+        let now = Utc::now();
+        let claims = SessionClaims {
+            sub: String::from("../etc"),
+            iat: now.timestamp(),
+            exp: (now + Duration::hours(1)).timestamp(),
+        };
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(SECRET.as_bytes()),
+        )
+        .unwrap();
+
+        let err = SessionService::verify(&token, SECRET).unwrap_err();
+
+        assert_eq!(err.code(), "invalid_id");
+    }
+}
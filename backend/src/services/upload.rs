@@ -0,0 +1,783 @@
+use crate::helpers::errors::{ClipError, ClipErrorType};
+use crate::models::ids::{ClipId, SessionId};
+use crate::models::ClipRecord;
+use crate::services::database::Database;
+use crate::services::ffprobe::FFProbeService;
+use crate::services::storage::Storage;
+use crate::settings::FFmpegSettings;
+use actix_web::web::Bytes;
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+const DEFAULT_CHUNK_MAX_BYTES: usize = 2 * 1024 * 1024;
+const DEFAULT_CLIP_MAX_BYTES: u64 = 256 * 1024 * 1024;
+const DEFAULT_SESSION_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Byte limits applied to uploads, the `[upload]` settings or `CHUNK_MAX_BYTES`,
+/// `CLIP_MAX_BYTES` and `SESSION_MAX_BYTES`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadQuota {
+    /// Largest single chunk frame accepted, also used as the WebSocket frame size.
+    pub max_chunk_bytes: usize,
+    pub max_clip_bytes: u64,
+    pub max_session_bytes: u64,
+}
+
+impl Default for UploadQuota {
+    fn default() -> UploadQuota {
+        UploadQuota {
+            max_chunk_bytes: DEFAULT_CHUNK_MAX_BYTES,
+            max_clip_bytes: DEFAULT_CLIP_MAX_BYTES,
+            max_session_bytes: DEFAULT_SESSION_MAX_BYTES,
+        }
+    }
+}
+
+/// Bytes held per session and per clip, counted as chunks are written so the quota check
+/// doesn't rescan the session directory for every chunk. A session is counted from disk the
+/// first time a chunk arrives for it after a restart, or after it was forgotten.
+#[derive(Debug, Clone, Default)]
+pub struct UploadUsage {
+    sessions: Arc<Mutex<HashMap<SessionId, SessionUsage>>>,
+}
+
+#[derive(Debug, Default)]
+struct SessionUsage {
+    bytes: u64,
+    clips: HashMap<ClipId, u64>,
+}
+
+impl UploadUsage {
+    /// Drops the session's counts, once its directory is removed.
+    pub fn forget(&self, session_id: &SessionId) {
+        self.sessions.lock().unwrap().remove(session_id);
+    }
+
+    fn is_counted(&self, session_id: &SessionId) -> bool {
+        self.sessions.lock().unwrap().contains_key(session_id)
+    }
+
+    /// Counts a chunk of `incoming` bytes that replaces `replaced` held ones, unless it
+    /// would take the clip or the session past its quota.
+    fn reserve(
+        &self,
+        session_id: SessionId,
+        clip_id: &ClipId,
+        replaced: u64,
+        incoming: u64,
+        quota: UploadQuota,
+    ) -> Result<(), ClipError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.entry(session_id).or_default();
+        let clip = session.clips.get(clip_id).copied().unwrap_or(0);
+
+        let clip_bytes = clip.saturating_sub(replaced) + incoming;
+        if clip_bytes > quota.max_clip_bytes {
+            return Err(UploadService::quota_exceeded(format!(
+                "Clip {} exceeds its {} byte quota",
+                clip_id, quota.max_clip_bytes
+            )));
+        }
+
+        let session_bytes = session.bytes.saturating_sub(replaced) + incoming;
+        if session_bytes > quota.max_session_bytes {
+            return Err(UploadService::quota_exceeded(format!(
+                "Session exceeds its {} byte quota",
+                quota.max_session_bytes
+            )));
+        }
+
+        session.clips.insert(clip_id.clone(), clip_bytes);
+        session.bytes = session_bytes;
+
+        Ok(())
+    }
+
+    /// Takes back a reservation whose chunk was never written.
+    fn cancel(&self, session_id: &SessionId, clip_id: &ClipId, replaced: u64, incoming: u64) {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        if let Some(session) = sessions.get_mut(session_id) {
+            if let Some(clip) = session.clips.get_mut(clip_id) {
+                *clip = clip.saturating_sub(incoming) + replaced;
+            }
+            session.bytes = session.bytes.saturating_sub(incoming) + replaced;
+        }
+    }
+
+    fn insert(&self, session_id: SessionId, usage: SessionUsage) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .entry(session_id)
+            .or_insert(usage);
+    }
+}
+
+/// Clip uploads are received as numbered chunks and kept on disk under
+/// `<clip id>.parts/` until the client finalizes them, so a dropped socket
+/// only costs the chunks that were never acknowledged. Nothing but the chunk
+/// currently being written is held in memory.
+pub struct UploadService {}
+
+impl UploadService {
+    pub async fn begin(session_id: SessionId, root: &Path) -> Result<ClipId, ClipError> {
+        let clip_id = ClipId::generate();
+
+        fs::create_dir_all(clip_id.parts_directory(root, &session_id)).await?;
+
+        Ok(clip_id)
+    }
+
+    /// Stores one chunk. Resending a chunk that is already held overwrites it.
+    pub async fn write_chunk(
+        session_id: SessionId,
+        clip_id: ClipId,
+        seq: u32,
+        data: Bytes,
+        root: &Path,
+        quota: UploadQuota,
+        usage: &UploadUsage,
+    ) -> Result<(), ClipError> {
+        let parts = Self::open_parts_directory(&session_id, &clip_id, root).await?;
+        let path = Self::part_path(&parts, seq);
+
+        if !usage.is_counted(&session_id) {
+            usage.insert(session_id, Self::session_usage(&session_id, root).await?);
+        }
+
+        let replaced = match fs::metadata(&path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        let incoming = data.len() as u64;
+
+        usage.reserve(session_id, &clip_id, replaced, incoming, quota)?;
+
+        if let Err(err) = Self::write_part(&path, &data).await {
+            usage.cancel(&session_id, &clip_id, replaced, incoming);
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn write_part(path: &Path, data: &[u8]) -> Result<(), ClipError> {
+        // Written under a temporary name so a chunk cut off mid-write is never reported as held.
+        let partial = path.with_extension("partial");
+
+        let mut file = fs::File::create(&partial).await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+
+        fs::rename(&partial, path).await?;
+
+        Ok(())
+    }
+
+    /// Sequence numbers of the chunks held for an unfinished clip, in ascending order.
+    pub async fn received_chunks(
+        session_id: &SessionId,
+        clip_id: &ClipId,
+        root: &Path,
+    ) -> Result<Vec<u32>, ClipError> {
+        let parts = Self::open_parts_directory(session_id, clip_id, root).await?;
+
+        let mut received = vec![];
+        let mut entries = fs::read_dir(parts).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+
+            if let Some(seq) = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".part"))
+                .and_then(|seq| seq.parse::<u32>().ok())
+            {
+                received.push(seq);
+            }
+        }
+
+        received.sort_unstable();
+
+        Ok(received)
+    }
+
+    /// Whether the clip file has already been written by `finalize`.
+    pub async fn is_finalized(session_id: &SessionId, clip_id: &ClipId, root: &Path) -> bool {
+        fs::metadata(clip_id.path(root, session_id))
+            .await
+            .map(|metadata| metadata.is_file())
+            .unwrap_or(false)
+    }
+
+    /// Checks that every clip a merge asks for was finalized in this session, and that none
+    /// is listed twice. Clips recorded before an in-memory store was restarted are known by
+    /// their file in the session directory.
+    pub async fn check_ownership(
+        session_id: SessionId,
+        clip_ids: Vec<String>,
+        root: PathBuf,
+        db: Database,
+    ) -> Result<(), ClipError> {
+        let session = session_id.to_string();
+
+        for (clip_idx, clip_id) in clip_ids.iter().enumerate() {
+            if clip_ids[..clip_idx].contains(clip_id) {
+                return Err(Self::unknown_clip(format!(
+                    "Clip {} is listed twice",
+                    clip_id
+                )));
+            }
+
+            let owned = match ClipId::parse(clip_id.as_str()) {
+                Ok(parsed) => {
+                    db.get_clip(session.as_str(), clip_id.as_str())
+                        .await?
+                        .is_some()
+                        || Self::is_finalized(&session_id, &parsed, &root).await
+                }
+                Err(_) => false,
+            };
+
+            if !owned {
+                return Err(Self::unknown_clip(format!(
+                    "Clip {} does not belong to this session",
+                    clip_id
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Joins chunks `0..chunk_count` into the clip file, probes it, publishes it to the
+    /// clip store and records the clip.
+    ///
+    /// Finalizing a clip twice is not an error, the second call returns the stored record.
+    pub async fn finalize(
+        session_id: SessionId,
+        clip_id: ClipId,
+        chunk_count: u32,
+        ffmpeg: &FFmpegSettings,
+        db: Database,
+        storage: Storage,
+    ) -> Result<ClipRecord, ClipError> {
+        let root = storage.temp_directory();
+
+        if Self::is_finalized(&session_id, &clip_id, root).await {
+            return Self::finalized_clip(session_id, clip_id, root, ffmpeg, db).await;
+        }
+
+        let received = Self::received_chunks(&session_id, &clip_id, root).await?;
+
+        if chunk_count == 0 || !received.iter().copied().eq(0..chunk_count) {
+            let missing = (0..chunk_count)
+                .filter(|seq| received.binary_search(seq).is_err())
+                .collect::<Vec<u32>>();
+
+            return Err(ClipError::from(format!(
+                "Cannot finalize {} with {} chunks, missing {:?}, holding {:?}",
+                clip_id, chunk_count, missing, received
+            )));
+        }
+
+        let parts = clip_id.parts_directory(root, &session_id);
+        let path = clip_id.path(root, &session_id);
+        let partial = path.with_extension("webm.partial");
+
+        let mut file = fs::File::create(&partial).await?;
+        for seq in 0..chunk_count {
+            let mut part = fs::File::open(Self::part_path(&parts, seq)).await?;
+            tokio::io::copy(&mut part, &mut file).await?;
+        }
+        file.sync_all().await?;
+
+        // Anything ffprobe can't read would only fail later in the merge.
+        if let Err(err) = FFProbeService::probe(ffmpeg, &partial).await {
+            fs::remove_file(&partial).await?;
+            return Err(err);
+        }
+
+        fs::rename(&partial, &path).await?;
+        fs::remove_dir_all(&parts).await?;
+
+        storage.publish(&path).await?;
+
+        Self::finalized_clip(session_id, clip_id, root, ffmpeg, db).await
+    }
+
+    /// The record of a finalized clip, probing and storing it if the database has none.
+    pub async fn finalized_clip(
+        session_id: SessionId,
+        clip_id: ClipId,
+        root: &Path,
+        ffmpeg: &FFmpegSettings,
+        db: Database,
+    ) -> Result<ClipRecord, ClipError> {
+        let session = session_id.to_string();
+
+        if let Some(record) = db
+            .get_clip(session.as_str(), clip_id.to_string().as_str())
+            .await?
+        {
+            if record.metadata.is_some() {
+                return Ok(record);
+            }
+        }
+
+        let path = clip_id.path(root, &session_id);
+        let metadata = FFProbeService::probe(ffmpeg, &path).await?;
+
+        let record = ClipRecord {
+            id: clip_id.to_string(),
+            session_id: session,
+            duration: Some(metadata.duration),
+            byte_size: fs::metadata(&path).await?.len() as i64,
+            metadata: Some(metadata),
+            created_at: Utc::now(),
+        };
+
+        db.insert_clip(record.clone()).await?;
+
+        Ok(record)
+    }
+
+    /// Bytes uploaded to a session so far, finalized clips and held chunks alike.
+    async fn session_usage(session_id: &SessionId, root: &Path) -> Result<SessionUsage, ClipError> {
+        let mut usage = SessionUsage::default();
+
+        let mut entries = match fs::read_dir(session_id.directory(root)).await {
+            Ok(entries) => entries,
+            Err(_) => return Ok(usage),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let clip_id = match path
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .map(ClipId::parse)
+            {
+                Some(Ok(clip_id)) => clip_id,
+                _ => continue,
+            };
+
+            let bytes = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("parts") => Self::held_bytes(&path).await?,
+                Some("webm") => entry.metadata().await?.len(),
+                _ => continue,
+            };
+
+            usage.bytes += bytes;
+            usage.clips.insert(clip_id, bytes);
+        }
+
+        Ok(usage)
+    }
+
+    /// Size of the chunks held in a parts directory.
+    async fn held_bytes(parts: &Path) -> Result<u64, ClipError> {
+        let mut total = 0;
+
+        let mut entries = fs::read_dir(parts).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path().extension().is_some_and(|ext| ext == "part") {
+                total += entry.metadata().await?.len();
+            }
+        }
+
+        Ok(total)
+    }
+
+    async fn open_parts_directory(
+        session_id: &SessionId,
+        clip_id: &ClipId,
+        root: &Path,
+    ) -> Result<PathBuf, ClipError> {
+        let parts = clip_id.parts_directory(root, session_id);
+
+        let is_dir = fs::metadata(&parts)
+            .await
+            .map(|metadata| metadata.is_dir())
+            .unwrap_or(false);
+
+        if !is_dir {
+            let mut error = ClipError::from(format!("Unknown clip upload: {}", clip_id));
+            error.set_type(ClipErrorType::InvalidId);
+
+            return Err(error);
+        }
+
+        Ok(parts)
+    }
+
+    fn part_path(parts: &Path, seq: u32) -> PathBuf {
+        parts.join(format!("{:010}.part", seq))
+    }
+
+    fn quota_exceeded(message: String) -> ClipError {
+        let mut error = ClipError::from(message);
+        error.set_type(ClipErrorType::QuotaExceeded);
+        error
+    }
+    fn unknown_clip(message: String) -> ClipError {
+        let mut error = ClipError::from(message);
+        error.set_type(ClipErrorType::UnknownClip);
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ClipMetadata;
+    use crate::services::storage::StorageSettings;
+
+    fn temp_root() -> PathBuf {
+        std::env::temp_dir().join(format!("upload-test-{}", SessionId::generate()))
+    }
+
+    async fn write(
+        session_id: &SessionId,
+        clip_id: &ClipId,
+        seq: u32,
+        data: &'static [u8],
+        root: &Path,
+    ) -> Result<(), ClipError> {
+        write_within(
+            session_id,
+            clip_id,
+            seq,
+            data,
+            root,
+            UploadQuota::default(),
+            &UploadUsage::default(),
+        )
+        .await
+    }
+
+    async fn write_within(
+        session_id: &SessionId,
+        clip_id: &ClipId,
+        seq: u32,
+        data: &'static [u8],
+        root: &Path,
+        quota: UploadQuota,
+        usage: &UploadUsage,
+    ) -> Result<(), ClipError> {
+        UploadService::write_chunk(
+            *session_id,
+            clip_id.clone(),
+            seq,
+            Bytes::from_static(data),
+            root,
+            quota,
+            usage,
+        )
+        .await
+    }
+
+    fn quota(max_clip_bytes: u64, max_session_bytes: u64) -> UploadQuota {
+        UploadQuota {
+            max_clip_bytes,
+            max_session_bytes,
+            ..UploadQuota::default()
+        }
+    }
+
+    /// An ffprobe stand-in that reports one VP8 stream of a second and a half.
+    #[cfg(unix)]
+    async fn fake_ffprobe(root: &Path) -> FFmpegSettings {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = root.join("ffprobe");
+        fs::write(
+            &path,
+            "#!/bin/sh\necho '{\"streams\":[{\"codec_type\":\"video\",\"codec_name\":\"vp8\"}],\
+             \"format\":{\"format_name\":\"matroska,webm\",\"duration\":\"1.5\"}}'\n",
+        )
+        .await
+        .unwrap();
+        fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .await
+            .unwrap();
+
+        FFmpegSettings {
+            ffprobe_path: path,
+            ..FFmpegSettings::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn resending_a_chunk_overwrites_it() {
+        let root = temp_root();
+        let session_id = SessionId::generate();
+        let clip_id = UploadService::begin(session_id, &root).await.unwrap();
+
+        write(&session_id, &clip_id, 0, b"first", &root)
+            .await
+            .unwrap();
+        write(&session_id, &clip_id, 0, b"again", &root)
+            .await
+            .unwrap();
+
+        let parts = clip_id.parts_directory(&root, &session_id);
+        assert_eq!(
+            fs::read(UploadService::part_path(&parts, 0)).await.unwrap(),
+            b"again"
+        );
+        assert_eq!(
+            UploadService::received_chunks(&session_id, &clip_id, &root)
+                .await
+                .unwrap(),
+            vec![0]
+        );
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn received_chunks_are_sorted_and_skip_partial_writes() {
+        let root = temp_root();
+        let session_id = SessionId::generate();
+        let clip_id = UploadService::begin(session_id, &root).await.unwrap();
+
+        write(&session_id, &clip_id, 2, b"c", &root).await.unwrap();
+        write(&session_id, &clip_id, 0, b"a", &root).await.unwrap();
+        fs::write(
+            clip_id
+                .parts_directory(&root, &session_id)
+                .join("0000000001.partial"),
+            b"b",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            UploadService::received_chunks(&session_id, &clip_id, &root)
+                .await
+                .unwrap(),
+            vec![0, 2]
+        );
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn chunks_of_a_clip_that_was_never_begun_are_rejected() {
+        let root = temp_root();
+        let session_id = SessionId::generate();
+        let clip_id = ClipId::generate();
+
+        assert_eq!(
+            UploadService::received_chunks(&session_id, &clip_id, &root)
+                .await
+                .unwrap_err()
+                .code(),
+            "invalid_id"
+        );
+        assert_eq!(
+            write(&session_id, &clip_id, 0, b"a", &root)
+                .await
+                .unwrap_err()
+                .code(),
+            "invalid_id"
+        );
+    }
+
+    #[tokio::test]
+    async fn finalize_rejects_missing_and_extra_chunks() {
+        let root = temp_root();
+        let session_id = SessionId::generate();
+        let clip_id = UploadService::begin(session_id, &root).await.unwrap();
+        let storage = Storage::from_settings(&StorageSettings::default(), &root).unwrap();
+
+        write(&session_id, &clip_id, 0, b"a", &root).await.unwrap();
+        write(&session_id, &clip_id, 2, b"c", &root).await.unwrap();
+
+        for chunk_count in [0, 2, 3] {
+            let err = UploadService::finalize(
+                session_id,
+                clip_id.clone(),
+                chunk_count,
+                &FFmpegSettings::default(),
+                Database::memory(),
+                storage.clone(),
+            )
+            .await
+            .unwrap_err();
+
+            assert_eq!(err.code(), "error");
+        }
+        assert!(!UploadService::is_finalized(&session_id, &clip_id, &root).await);
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn finalizing_twice_returns_the_recorded_clip() {
+        let root = temp_root();
+        let session_id = SessionId::generate();
+        let clip_id = UploadService::begin(session_id, &root).await.unwrap();
+        let storage = Storage::from_settings(&StorageSettings::default(), &root).unwrap();
+        let ffmpeg = fake_ffprobe(&root).await;
+        let db = Database::memory();
+
+        write(&session_id, &clip_id, 0, b"ab", &root).await.unwrap();
+        write(&session_id, &clip_id, 1, b"cd", &root).await.unwrap();
+
+        let first = UploadService::finalize(
+            session_id,
+            clip_id.clone(),
+            2,
+            &ffmpeg,
+            db.clone(),
+            storage.clone(),
+        )
+        .await
+        .unwrap();
+        let second =
+            UploadService::finalize(session_id, clip_id.clone(), 2, &ffmpeg, db.clone(), storage)
+                .await
+                .unwrap();
+
+        assert_eq!(
+            fs::read(clip_id.path(&root, &session_id)).await.unwrap(),
+            b"abcd"
+        );
+        assert!(!clip_id.parts_directory(&root, &session_id).exists());
+        assert_eq!(first.byte_size, 4);
+        assert_eq!(first.duration, Some(1.5));
+        assert_eq!(second.id, first.id);
+        assert_eq!(second.created_at, first.created_at);
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_ownership_rejects_duplicate_and_foreign_clips() {
+        let root = temp_root();
+        let session_id = SessionId::generate();
+        let db = Database::memory();
+        let owned = ClipId::generate();
+        let foreign = ClipId::generate();
+
+        db.insert_clip(ClipRecord {
+            id: owned.to_string(),
+            session_id: session_id.to_string(),
+            duration: Some(1.0),
+            byte_size: 1,
+            metadata: Some(ClipMetadata::default()),
+            created_at: Utc::now(),
+        })
+        .await
+        .unwrap();
+        db.insert_clip(ClipRecord {
+            id: foreign.to_string(),
+            session_id: SessionId::generate().to_string(),
+            duration: Some(1.0),
+            byte_size: 1,
+            metadata: Some(ClipMetadata::default()),
+            created_at: Utc::now(),
+        })
+        .await
+        .unwrap();
+
+        let check = |clip_ids: Vec<String>| {
+            UploadService::check_ownership(session_id, clip_ids, root.clone(), db.clone())
+        };
+
+        assert!(check(vec![owned.to_string()]).await.is_ok());
+        for clip_ids in [
+            vec![owned.to_string(), owned.to_string()],
+            vec![owned.to_string(), foreign.to_string()],
+            vec![String::from("../clip")],
+        ] {
+            assert_eq!(check(clip_ids).await.unwrap_err().code(), "unknown_clip");
+        }
+    }
+
+    #[tokio::test]
+    async fn a_clip_may_fill_its_quota_but_not_exceed_it() {
+        let root = temp_root();
+        let session_id = SessionId::generate();
+        let clip_id = UploadService::begin(session_id, &root).await.unwrap();
+        let quota = quota(4, 100);
+        let usage = UploadUsage::default();
+        let write =
+            |seq, data| write_within(&session_id, &clip_id, seq, data, &root, quota, &usage);
+
+        write(0, b"ab").await.unwrap();
+        write(1, b"cd").await.unwrap();
+        assert_eq!(write(2, b"e").await.unwrap_err().code(), "quota_exceeded");
+
+        // A resent chunk only counts once.
+        write(1, b"cd").await.unwrap();
+        assert_eq!(write(1, b"cde").await.unwrap_err().code(), "quota_exceeded");
+        assert_eq!(
+            UploadService::received_chunks(&session_id, &clip_id, &root)
+                .await
+                .unwrap(),
+            vec![0, 1]
+        );
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_session_quota_counts_every_clip() {
+        let root = temp_root();
+        let session_id = SessionId::generate();
+        let first = UploadService::begin(session_id, &root).await.unwrap();
+        let second = UploadService::begin(session_id, &root).await.unwrap();
+        let quota = quota(100, 5);
+        let usage = UploadUsage::default();
+
+        write_within(&session_id, &first, 0, b"abc", &root, quota, &usage)
+            .await
+            .unwrap();
+        write_within(&session_id, &second, 0, b"de", &root, quota, &usage)
+            .await
+            .unwrap();
+        let err = write_within(&session_id, &second, 1, b"f", &root, quota, &usage)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.code(), "quota_exceeded");
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn usage_is_counted_from_disk_after_a_restart() {
+        let root = temp_root();
+        let session_id = SessionId::generate();
+        let finalized = ClipId::generate();
+        let clip_id = UploadService::begin(session_id, &root).await.unwrap();
+        let quota = quota(100, 6);
+
+        fs::write(finalized.path(&root, &session_id), b"abc")
+            .await
+            .unwrap();
+        write(&session_id, &clip_id, 0, b"de", &root).await.unwrap();
+
+        let usage = UploadUsage::default();
+        write_within(&session_id, &clip_id, 1, b"f", &root, quota, &usage)
+            .await
+            .unwrap();
+        let err = write_within(&session_id, &clip_id, 2, b"g", &root, quota, &usage)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.code(), "quota_exceeded");
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+}
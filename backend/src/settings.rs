@@ -0,0 +1,632 @@
+use crate::helpers::errors::ClipError;
+use crate::models::PipLayout;
+use crate::services::database::DatabaseBackend;
+use crate::services::retention::RetentionPolicy;
+use crate::services::storage::StorageSettings;
+use crate::services::upload::UploadQuota;
+use reqwest::Url;
+use serde::Deserialize;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_SETTINGS_PATH: &str = "settings.toml";
+const DEFAULT_MERGE_CONCURRENCY: usize = 2;
+const DEFAULT_PROFILES_PATH: &str = "profiles.toml";
+const DEFAULT_FINISHED_JOB_RETENTION_HOURS: u32 = 1;
+const DEFAULT_SESSION_TTL_HOURS: u32 = 24;
+const MIN_SESSION_SECRET_LENGTH: usize = 32;
+const PLACEHOLDER_SESSION_SECRET: &str = "change-me-in-production";
+
+/// Where the HTTP server listens and who may call it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: u16,
+    pub workers: usize,
+    /// Origins the widget is served from, e.g. `http://127.0.0.1:42069`.
+    pub cors_origins: Vec<String>,
+}
+
+impl Default for ServerSettings {
+    fn default() -> ServerSettings {
+        ServerSettings {
+            host: String::from("127.0.0.1"),
+            port: 9011,
+            workers: 4,
+            cors_origins: vec![String::from("http://127.0.0.1:42069")],
+        }
+    }
+}
+
+/// How the upload and submit sockets notice a client that went away.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebSocketSettings {
+    pub heartbeat_interval_seconds: u64,
+    /// How long before lack of client response causes a timeout.
+    pub client_timeout_seconds: u64,
+}
+
+impl Default for WebSocketSettings {
+    fn default() -> WebSocketSettings {
+        WebSocketSettings {
+            heartbeat_interval_seconds: 5,
+            client_timeout_seconds: 100,
+        }
+    }
+}
+
+impl WebSocketSettings {
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval_seconds)
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_secs(self.client_timeout_seconds)
+    }
+}
+
+/// The ffmpeg and ffprobe binaries and how merges use them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FFmpegSettings {
+    /// A binary name looked up on `PATH`, or a path to one.
+    pub ffmpeg_path: PathBuf,
+    pub ffprobe_path: PathBuf,
+    /// How many merges and exports may encode at once.
+    pub merge_concurrency: usize,
+    pub encoding_profiles: PathBuf,
+    /// How long finished merge jobs stay queryable through `GET /jobs/{id}`.
+    pub finished_job_retention_hours: u32,
+}
+
+impl Default for FFmpegSettings {
+    fn default() -> FFmpegSettings {
+        FFmpegSettings {
+            ffmpeg_path: PathBuf::from("ffmpeg"),
+            ffprobe_path: PathBuf::from("ffprobe"),
+            merge_concurrency: DEFAULT_MERGE_CONCURRENCY,
+            encoding_profiles: PathBuf::from(DEFAULT_PROFILES_PATH),
+            finished_job_retention_hours: DEFAULT_FINISHED_JOB_RETENTION_HOURS,
+        }
+    }
+}
+
+impl FFmpegSettings {
+    pub fn finished_job_retention(&self) -> chrono::Duration {
+        chrono::Duration::hours(i64::from(self.finished_job_retention_hours))
+    }
+}
+
+/// Everything the backend is configured with, loaded once at startup from the TOML file at
+/// `SETTINGS_FILE` (`settings.toml` by default, optional) with environment variables taking
+/// precedence over it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Where session directories are written. Required.
+    pub temp_directory: PathBuf,
+    /// `postgres` unless an in-memory store that is lost on restart is asked for.
+    pub database_backend: DatabaseBackend,
+    /// PostgreSQL connection string. Required by the `postgres` backend.
+    pub database_url: Option<String>,
+    /// Signs session tokens. Required.
+    pub session_secret: String,
+    /// How long an issued session token stays valid.
+    pub session_ttl_hours: u32,
+    pub server: ServerSettings,
+    pub websocket: WebSocketSettings,
+    pub ffmpeg: FFmpegSettings,
+    pub upload: UploadQuota,
+    pub retention: RetentionPolicy,
+    pub storage: StorageSettings,
+    /// Default layout of camera overlays on screen recordings.
+    pub pip: PipLayout,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            temp_directory: PathBuf::new(),
+            database_backend: DatabaseBackend::default(),
+            database_url: None,
+            session_secret: String::new(),
+            session_ttl_hours: DEFAULT_SESSION_TTL_HOURS,
+            server: ServerSettings::default(),
+            websocket: WebSocketSettings::default(),
+            ffmpeg: FFmpegSettings::default(),
+            upload: UploadQuota::default(),
+            retention: RetentionPolicy::default(),
+            storage: StorageSettings::default(),
+            pip: PipLayout::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Reads and validates the settings. Every problem found is reported at once.
+    pub fn load() -> Result<Settings, ClipError> {
+        let mut settings = Self::read_file()?;
+        let mut problems = vec![];
+
+        settings.apply_env(&mut problems);
+        settings.validate(&mut problems);
+
+        if !problems.is_empty() {
+            return Err(ClipError::from(format!(
+                "Invalid settings: {}",
+                problems.join("; ")
+            )));
+        }
+
+        Ok(settings)
+    }
+
+    pub fn session_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(i64::from(self.session_ttl_hours))
+    }
+
+    fn read_file() -> Result<Settings, ClipError> {
+        let (path, required) = match dotenv::var("SETTINGS_FILE") {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_SETTINGS_PATH), false),
+        };
+
+        if !path.exists() {
+            if required {
+                return Err(ClipError::from(format!(
+                    "SETTINGS_FILE {} does not exist",
+                    path.display()
+                )));
+            }
+
+            return Ok(Settings::default());
+        }
+
+        let contents = std::fs::read_to_string(&path)?;
+
+        toml::from_str(contents.as_str()).map_err(|err| {
+            ClipError::from(format!("Invalid settings in {}: {}", path.display(), err))
+        })
+    }
+
+    fn apply_env(&mut self, problems: &mut Vec<String>) {
+        env("TEMP_DIRECTORY", &mut self.temp_directory, problems);
+        env("DATABASE_BACKEND", &mut self.database_backend, problems);
+        env_option("DATABASE_URL", &mut self.database_url, problems);
+        env("SESSION_SECRET", &mut self.session_secret, problems);
+        env("SESSION_TTL_HOURS", &mut self.session_ttl_hours, problems);
+
+        env("BIND_ADDRESS", &mut self.server.host, problems);
+        env("PORT", &mut self.server.port, problems);
+        env("WORKERS", &mut self.server.workers, problems);
+        if let Ok(origins) = dotenv::var("CORS_ORIGINS") {
+            self.server.cors_origins = origins
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect();
+        }
+
+        let websocket = &mut self.websocket;
+        env(
+            "HEARTBEAT_INTERVAL_SECONDS",
+            &mut websocket.heartbeat_interval_seconds,
+            problems,
+        );
+        env(
+            "CLIENT_TIMEOUT_SECONDS",
+            &mut websocket.client_timeout_seconds,
+            problems,
+        );
+
+        let ffmpeg = &mut self.ffmpeg;
+        env("FFMPEG_PATH", &mut ffmpeg.ffmpeg_path, problems);
+        env("FFPROBE_PATH", &mut ffmpeg.ffprobe_path, problems);
+        env("MERGE_CONCURRENCY", &mut ffmpeg.merge_concurrency, problems);
+        env("ENCODING_PROFILES", &mut ffmpeg.encoding_profiles, problems);
+        env(
+            "FINISHED_JOB_RETENTION_HOURS",
+            &mut ffmpeg.finished_job_retention_hours,
+            problems,
+        );
+
+        let upload = &mut self.upload;
+        env("CHUNK_MAX_BYTES", &mut upload.max_chunk_bytes, problems);
+        env("CLIP_MAX_BYTES", &mut upload.max_clip_bytes, problems);
+        env("SESSION_MAX_BYTES", &mut upload.max_session_bytes, problems);
+
+        let retention = &mut self.retention;
+        env_option(
+            "SESSION_IDLE_MINUTES",
+            &mut retention.session_idle_minutes,
+            problems,
+        );
+        env(
+            "SUBMITTED_RETENTION_HOURS",
+            &mut retention.submitted_retention_hours,
+            problems,
+        );
+        env(
+            "RETENTION_SWEEP_MINUTES",
+            &mut retention.sweep_minutes,
+            problems,
+        );
+
+        let storage = &mut self.storage;
+        env("STORAGE_BACKEND", &mut storage.backend, problems);
+        if let Ok(directory) = dotenv::var("STORAGE_DIRECTORY") {
+            storage.directory = Some(PathBuf::from(directory));
+        }
+        env_option("S3_ENDPOINT", &mut storage.s3.endpoint, problems);
+        env_option("S3_BUCKET", &mut storage.s3.bucket, problems);
+        env("S3_REGION", &mut storage.s3.region, problems);
+        env_option("S3_ACCESS_KEY_ID", &mut storage.s3.access_key_id, problems);
+        env_option(
+            "S3_SECRET_ACCESS_KEY",
+            &mut storage.s3.secret_access_key,
+            problems,
+        );
+
+        let pip = &mut self.pip;
+        env("PIP_CORNER", &mut pip.corner, problems);
+        env("PIP_SIZE", &mut pip.size, problems);
+        env("PIP_SHAPE", &mut pip.shape, problems);
+        env("PIP_MARGIN", &mut pip.margin, problems);
+    }
+
+    fn validate(&self, problems: &mut Vec<String>) {
+        if self.temp_directory.as_os_str().is_empty() {
+            problems.push(String::from("TEMP_DIRECTORY is not set"));
+        } else if let Err(err) = Self::check_writable(&self.temp_directory) {
+            problems.push(format!(
+                "TEMP_DIRECTORY {} is not writable: {}",
+                self.temp_directory.display(),
+                err
+            ));
+        }
+
+        if self.database_backend == DatabaseBackend::Postgres && self.database_url.is_none() {
+            problems.push(String::from(
+                "DATABASE_URL is not set, DATABASE_BACKEND=memory keeps data in memory instead",
+            ));
+        }
+
+        if self.session_secret.is_empty() {
+            problems.push(String::from("SESSION_SECRET is not set"));
+        } else if self.session_secret == PLACEHOLDER_SESSION_SECRET {
+            problems.push(String::from(
+                "SESSION_SECRET is still the placeholder, generate a random one",
+            ));
+        } else if self.session_secret.len() < MIN_SESSION_SECRET_LENGTH {
+            problems.push(format!(
+                "SESSION_SECRET must be at least {} characters",
+                MIN_SESSION_SECRET_LENGTH
+            ));
+        }
+        if self.session_ttl_hours == 0 {
+            problems.push(String::from("SESSION_TTL_HOURS must be at least 1"));
+        }
+
+        if self.server.workers == 0 {
+            problems.push(String::from("WORKERS must be at least 1"));
+        }
+        if self.server.cors_origins.is_empty() {
+            problems.push(String::from("CORS_ORIGINS is empty"));
+        }
+        for origin in &self.server.cors_origins {
+            let valid = Url::parse(origin)
+                .map(|url| url.origin().ascii_serialization() == *origin)
+                .unwrap_or(false);
+
+            if !valid {
+                problems.push(format!(
+                    "CORS origin {} is not a scheme://host[:port] origin",
+                    origin
+                ));
+            }
+        }
+
+        if self.websocket.heartbeat_interval_seconds == 0
+            || self.websocket.client_timeout_seconds <= self.websocket.heartbeat_interval_seconds
+        {
+            problems.push(String::from(
+                "CLIENT_TIMEOUT_SECONDS must be longer than a non-zero HEARTBEAT_INTERVAL_SECONDS",
+            ));
+        }
+
+        for (key, binary) in [
+            ("FFMPEG_PATH", &self.ffmpeg.ffmpeg_path),
+            ("FFPROBE_PATH", &self.ffmpeg.ffprobe_path),
+        ] {
+            if let Err(err) = Self::check_binary(binary) {
+                problems.push(format!(
+                    "{} {} can't be run: {}",
+                    key,
+                    binary.display(),
+                    err
+                ));
+            }
+        }
+        if self.ffmpeg.merge_concurrency == 0 {
+            problems.push(String::from("MERGE_CONCURRENCY must be at least 1"));
+        }
+
+        let upload = &self.upload;
+        if upload.max_chunk_bytes == 0 || upload.max_clip_bytes == 0 {
+            problems.push(String::from(
+                "CHUNK_MAX_BYTES and CLIP_MAX_BYTES must be greater than 0",
+            ));
+        }
+        if upload.max_clip_bytes > upload.max_session_bytes {
+            problems.push(String::from(
+                "CLIP_MAX_BYTES is larger than SESSION_MAX_BYTES",
+            ));
+        }
+
+        if self.retention.sweep_minutes == 0 {
+            problems.push(String::from("RETENTION_SWEEP_MINUTES must be at least 1"));
+        }
+
+        if let Err(err) = self.pip.validate() {
+            problems.push(err.to_string());
+        }
+    }
+
+    /// Creates the directory if need be and writes a file to it.
+    fn check_writable(directory: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(directory)?;
+
+        let probe = directory.join(format!(".write-check-{}", std::process::id()));
+        std::fs::write(&probe, b"")?;
+        std::fs::remove_file(&probe)
+    }
+
+    fn check_binary(binary: &Path) -> Result<(), String> {
+        let status = Command::new(binary)
+            .arg("-version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|err| err.to_string())?;
+
+        if !status.success() {
+            return Err(format!("-version exited with {}", status));
+        }
+
+        Ok(())
+    }
+}
+
+/// Replaces `target` with the environment variable `key` when it is set.
+fn env<T>(key: &str, target: &mut T, problems: &mut Vec<String>)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(value) = dotenv::var(key) {
+        match value.trim().parse() {
+            Ok(value) => *target = value,
+            Err(err) => problems.push(format!("{}={} is invalid: {}", key, value, err)),
+        }
+    }
+}
+
+/// Like `env`, an empty variable unsets the value.
+fn env_option<T>(key: &str, target: &mut Option<T>, problems: &mut Vec<String>)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(value) = dotenv::var(key) {
+        let value = value.trim();
+
+        if value.is_empty() {
+            *target = None;
+            return;
+        }
+
+        match value.parse() {
+            Ok(value) => *target = Some(value),
+            Err(err) => problems.push(format!("{}={} is invalid: {}", key, value, err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ids::SessionId;
+
+    /// Settings that pass validation, `true` stands in for ffmpeg and ffprobe.
+    fn valid() -> Settings {
+        Settings {
+            temp_directory: std::env::temp_dir()
+                .join(format!("settings-test-{}", SessionId::generate())),
+            database_backend: DatabaseBackend::Memory,
+            session_secret: "s".repeat(MIN_SESSION_SECRET_LENGTH),
+            ffmpeg: FFmpegSettings {
+                ffmpeg_path: PathBuf::from("true"),
+                ffprobe_path: PathBuf::from("true"),
+                ..FFmpegSettings::default()
+            },
+            ..Settings::default()
+        }
+    }
+
+    /// Breaks one setting.
+    type Invalidate = fn(&mut Settings);
+
+    fn problems(settings: &Settings) -> Vec<String> {
+        let mut problems = vec![];
+        settings.validate(&mut problems);
+
+        if settings.temp_directory.exists() {
+            std::fs::remove_dir_all(&settings.temp_directory).unwrap();
+        }
+
+        problems
+    }
+
+    #[test]
+    fn environment_variables_take_precedence_over_the_file() {
+        let mut settings: Settings = toml::from_str(
+            "[server]
+             port = 9100
+             workers = 2",
+        )
+        .unwrap();
+        std::env::set_var("PORT", "9200");
+
+        settings.apply_env(&mut vec![]);
+
+        assert_eq!(settings.server.port, 9200);
+        assert_eq!(settings.server.workers, 2);
+        assert_eq!(settings.server.host, ServerSettings::default().host);
+    }
+
+    #[test]
+    fn unparsable_environment_variables_are_reported() {
+        let mut port = 9011_u16;
+        let mut problems = vec![];
+        std::env::set_var("SETTINGS_TEST_PORT", "ninety");
+
+        env("SETTINGS_TEST_PORT", &mut port, &mut problems);
+
+        assert_eq!(port, 9011);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("SETTINGS_TEST_PORT=ninety is invalid"));
+    }
+
+    #[test]
+    fn an_empty_optional_variable_unsets_the_setting() {
+        let mut idle_minutes = Some(30_u32);
+        let mut problems = vec![];
+
+        std::env::set_var("SETTINGS_TEST_IDLE_MINUTES", " 45 ");
+        env_option(
+            "SETTINGS_TEST_IDLE_MINUTES",
+            &mut idle_minutes,
+            &mut problems,
+        );
+        assert_eq!(idle_minutes, Some(45));
+
+        std::env::set_var("SETTINGS_TEST_IDLE_MINUTES", "");
+        env_option(
+            "SETTINGS_TEST_IDLE_MINUTES",
+            &mut idle_minutes,
+            &mut problems,
+        );
+        assert_eq!(idle_minutes, None);
+        assert!(problems.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn valid_settings_have_no_problems() {
+        assert_eq!(problems(&valid()), Vec::<String>::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn each_problem_is_reported() {
+        let cases: Vec<(Invalidate, &str)> = vec![
+            (
+                |settings| settings.temp_directory = PathBuf::new(),
+                "TEMP_DIRECTORY is not set",
+            ),
+            (
+                |settings| settings.temp_directory = PathBuf::from("/dev/null/temp"),
+                "TEMP_DIRECTORY /dev/null/temp is not writable",
+            ),
+            (
+                |settings| settings.database_backend = DatabaseBackend::Postgres,
+                "DATABASE_URL is not set",
+            ),
+            (
+                |settings| settings.session_secret = String::new(),
+                "SESSION_SECRET is not set",
+            ),
+            (
+                |settings| settings.session_secret = PLACEHOLDER_SESSION_SECRET.to_string(),
+                "SESSION_SECRET is still the placeholder",
+            ),
+            (
+                |settings| settings.session_secret = String::from("short"),
+                "SESSION_SECRET must be at least 32 characters",
+            ),
+            (
+                |settings| settings.session_ttl_hours = 0,
+                "SESSION_TTL_HOURS must be at least 1",
+            ),
+            (
+                |settings| settings.server.workers = 0,
+                "WORKERS must be at least 1",
+            ),
+            (
+                |settings| settings.server.cors_origins.clear(),
+                "CORS_ORIGINS is empty",
+            ),
+            (
+                |settings| settings.server.cors_origins = vec![String::from("http://host/app")],
+                "CORS origin http://host/app is not a scheme://host[:port] origin",
+            ),
+            (
+                |settings| settings.websocket.heartbeat_interval_seconds = 0,
+                "CLIENT_TIMEOUT_SECONDS must be longer",
+            ),
+            (
+                |settings| settings.websocket.client_timeout_seconds = 5,
+                "CLIENT_TIMEOUT_SECONDS must be longer",
+            ),
+            (
+                |settings| settings.ffmpeg.ffmpeg_path = PathBuf::from("no-such-ffmpeg"),
+                "FFMPEG_PATH no-such-ffmpeg can't be run",
+            ),
+            (
+                |settings| settings.ffmpeg.ffprobe_path = PathBuf::from("false"),
+                "FFPROBE_PATH false can't be run: -version exited with",
+            ),
+            (
+                |settings| settings.ffmpeg.merge_concurrency = 0,
+                "MERGE_CONCURRENCY must be at least 1",
+            ),
+            (
+                |settings| settings.upload.max_chunk_bytes = 0,
+                "CHUNK_MAX_BYTES and CLIP_MAX_BYTES must be greater than 0",
+            ),
+            (
+                |settings| settings.upload.max_clip_bytes = settings.upload.max_session_bytes + 1,
+                "CLIP_MAX_BYTES is larger than SESSION_MAX_BYTES",
+            ),
+            (
+                |settings| settings.retention.sweep_minutes = 0,
+                "RETENTION_SWEEP_MINUTES must be at least 1",
+            ),
+            (
+                |settings| settings.pip.size = 0.9,
+                "Picture-in-picture size 0.9 is not between 0.1 and 0.5",
+            ),
+        ];
+
+        for (invalidate, expected) in cases {
+            let mut settings = valid();
+            invalidate(&mut settings);
+
+            let problems = problems(&settings);
+
+            assert_eq!(problems.len(), 1, "{:?}", problems);
+            assert!(
+                problems[0].starts_with(expected),
+                "{:?} does not start with {:?}",
+                problems[0],
+                expected
+            );
+        }
+    }
+}
@@ -7,9 +7,9 @@ use wasm_bindgen_futures::spawn_local;
 use wasm_bindgen_futures::JsFuture;
 
 use web_sys::{
-    window, AnalyserNode, AudioContext, Blob, BlobEvent, BlobPropertyBag, HtmlMediaElement,
-    HtmlVideoElement, MediaDeviceInfo, MediaDeviceKind, MediaRecorder, MediaRecorderOptions,
-    MediaStream, MediaStreamConstraints,
+    window, AnalyserNode, AudioContext, Blob, BlobEvent, BlobPropertyBag, DragEvent,
+    HtmlInputElement, HtmlMediaElement, MediaDeviceInfo, MediaDeviceKind, MediaRecorder,
+    MediaRecorderOptions, MediaStream,
 };
 
 use yew::prelude::*;
@@ -18,23 +18,32 @@ use yew_agent::{Bridge, Bridged, Dispatched, Dispatcher};
 
 use super::loading_animated::Loading;
 
+use crate::capture::CaptureSource;
+use crate::config::WidgetConfig;
 use crate::form::FormBuilder;
 use crate::models::{
-    ActiveDevices, CameraContext, CameraContextAction, CameraView, ClipDetailRequest, ClipDetails,
-    ConstraintDeviceId, ConstraintOptions, DeviceError, DeviceType, FieldValue, Request,
+    ActiveDevices, AudioFormat, CameraContext, CameraContextAction, CameraView, ClipDetailRequest,
+    ClipDetails, ClipHistory, ClipTrack, ConstraintDeviceId, ConstraintOptions, DeviceError,
+    DeviceType, FieldValue, Request, TrimHandle,
 };
 
 use crate::service::camera::CameraService;
 use crate::utilities::Utilities;
 
+/// How often the recorder hands a chunk to the upload, in milliseconds.
+const RECORDER_TIMESLICE_MS: i32 = 1000;
+
 pub struct Camera {
     view: CameraView,
 
     camera_id: String,
     is_camera_preview_active: bool,
+    capture_source: CaptureSource,
 
     stream: MediaStream,
     recorder: MediaRecorder,
+    /// Records the camera next to a screen recording, uploaded as the clip's overlay.
+    overlay_recorder: Option<MediaRecorder>,
 
     duration: f64,
     preview_duration: f64,
@@ -65,7 +74,10 @@ pub struct Camera {
     active_devices: ActiveDevices,
 
     discard_hover_state: bool,
-    last_discarded_clip: Option<ClipDetails>,
+    /// Edits to `clips` since the last recording, for undo and redo.
+    history: ClipHistory,
+    /// Index of the clip being dragged in the clip list.
+    dragged_clip: Option<usize>,
 
     _cs: Dispatcher<CameraService>,
     producer: Box<dyn Bridge<CameraService>>,
@@ -73,14 +85,19 @@ pub struct Camera {
     is_recording: bool,
     is_playing: bool,
     is_mute: bool,
+
+    is_merging: bool,
+    merge_progress: Option<f64>,
+    clip_error: Option<String>,
 }
 
 pub enum Msg {
     SetStreamRecorder(MediaStream, MediaRecorder),
-    CameraServiceMessenger(CameraContext),
+    CameraServiceMessenger(Box<CameraContext>),
     StartRecording(),
     StopRecording(),
     SetView(CameraView),
+    SetCaptureSource(CaptureSource),
     SetDevice(Event, DeviceType),
     SetDeviceID(String, DeviceType),
     ToggleCameraPreview(),
@@ -89,6 +106,11 @@ pub enum Msg {
     DiscardHoverState(bool),
     OnDiscardClick(),
     OnClipUndo(),
+    OnClipRedo(),
+    DeleteClip(usize),
+    DragClip(Option<usize>),
+    DropClip(usize),
+    TrimClip(Event, usize, TrimHandle),
     PlaySavedClips(),
     PreviewOnPlayToggle(),
     PreviewOnMuteToggle(),
@@ -100,6 +122,9 @@ pub enum Msg {
 pub struct Props {
     pub duration: f64,
     pub view: CameraView,
+    /// Records the microphone alone, for users who would rather not be on camera.
+    #[prop_or_default]
+    pub voice: bool,
 }
 
 impl Component for Camera {
@@ -132,14 +157,23 @@ impl Component for Camera {
             view: current_view.clone(),
 
             _cs: CameraService::dispatcher(),
-            producer: CameraService::bridge(ctx.link().callback(Msg::CameraServiceMessenger)),
+            producer: CameraService::bridge(
+                ctx.link()
+                    .callback(|context| Msg::CameraServiceMessenger(Box::new(context))),
+            ),
 
             camera_id,
             is_camera_preview_active: false,
+            capture_source: if ctx.props().voice {
+                CaptureSource::Microphone
+            } else {
+                CaptureSource::default()
+            },
 
             stream: stream.clone(),
             video_element,
             recorder: MediaRecorder::new_with_media_stream(&stream).unwrap(),
+            overlay_recorder: None,
 
             current_timestamp: 0.0,
 
@@ -180,11 +214,16 @@ impl Component for Camera {
             },
 
             discard_hover_state: false,
-            last_discarded_clip: None,
+            history: ClipHistory::default(),
+            dragged_clip: None,
 
             is_playing: false,
             is_recording: false,
             is_mute: false,
+
+            is_merging: false,
+            merge_progress: None,
+            clip_error: None,
         };
 
         camera.init_devices();
@@ -231,24 +270,37 @@ impl Component for Camera {
 
                 let current_timestamp = self.get_current_timestamp() / ctx.props().duration * 100.0;
 
+                let on_redo_click = ctx
+                    .link()
+                    .callback(move |_event: MouseEvent| Msg::OnClipRedo());
+
                 let mut hide_undo_button: String = String::from(
                     "absolute right-[-30px] cursor-pointer material-symbols-outlined ",
                 );
 
-                if let None = self.last_discarded_clip {
+                if !self.history.can_undo() {
                     hide_undo_button.push_str("hidden");
                 }
 
+                let mut hide_redo_button: String = String::from(
+                    "absolute right-[-55px] cursor-pointer material-symbols-outlined ",
+                );
+
+                if !self.history.can_redo() {
+                    hide_redo_button.push_str("hidden");
+                }
+
                 let mut show_discard_button:String = String::from("bg-white text-purple py-[2px] px-[8px] rounded-full border-[2px] border-white hover:bg-transparent hover:text-white duration-200 ");
 
                 if self.clips.len() == 0 {
                     hide_undo_button.push_str(" !right-[-10px]");
+                    hide_redo_button.push_str(" !right-[-35px]");
                     show_discard_button.push_str("hidden");
                 }
 
                 let mut show_controls:String = String::from("controls text-white h-[100px] w-full absolute bottom-[15px] z-10 flex flex-row flex-wrap justify-center items-center hidden");
 
-                if self.video_ouput_error.valid {
+                if self.capture_error().valid {
                     show_controls = show_controls.replace("hidden", "");
                 } else {
                     if !show_controls.contains("hidden") {
@@ -262,14 +314,41 @@ impl Component for Camera {
                     <div class="video-editor">
                         <div id={self.camera_id.clone()} class="video-wrapper absolute top-0 bottom-0 w-full">
                             <div class="device-error"
-                                style={if !self.video_ouput_error.valid {"display:flex;"}else{"display:none"}}>
+                                style={if !self.capture_error().valid {"display:flex;"}else{"display:none"}}>
                                 <span class="material-symbols-outlined text-6xl">
                                     {"error"}
                                 </span>
                                 <p>{"something went wrong! check your device settings."}</p>
                             </div>
 
-                            <Loading load={self.video_ouput_error.valid} />
+                            <Loading load={self.capture_error().valid} />
+                        </div>
+
+                        <div class="voice-meter"
+                            style={if self.is_voice() && self.capture_error().valid {"display:flex;"}else{"display:none;"}}>
+                            <span class={classes!("material-symbols-outlined", self.is_recording.then_some("recording"))}>
+                                {"mic"}
+                            </span>
+                            <div class="mic-level-wrapper">
+                                <div class="mic-level duration-200" style={self.get_microphone_state_styles()}></div>
+                            </div>
+                        </div>
+
+                        <div class="capture-sources"
+                            style={if self.is_recording || self.is_voice() {"display:none;"}else{"display:flex;"}}>
+                            {
+                                CaptureSource::ALL.iter().map(|source|{
+                                    let source = *source;
+                                    let on_source_click = ctx.link().callback(move |_event: MouseEvent| Msg::SetCaptureSource(source));
+
+                                    html!{
+                                        <button title={source.label()} onclick={on_source_click}
+                                            class={classes!("capture-source", (self.capture_source == source).then_some("active"))}>
+                                            <span class="material-symbols-outlined">{source.icon()}</span>
+                                        </button>
+                                    }
+                                }).collect::<Html>()
+                            }
                         </div>
 
                         <div class={show_controls}>
@@ -287,6 +366,9 @@ impl Component for Camera {
                                     <span onclick={on_undo_click} class={hide_undo_button}>
                                         {"undo"}
                                     </span>
+                                    <span onclick={on_redo_click} class={hide_redo_button}>
+                                        {"redo"}
+                                    </span>
                                 </div>
                             </div>
 
@@ -312,19 +394,76 @@ impl Component for Camera {
                              <div class="progress-bar bg-purple h-[15px] absolute bottom-0 flex flex-row overflow-hidden w-full">
                                 <div class="current-timestamp" style={self.set_percent_style(current_timestamp)}></div>
                                 {
-                                    self.clips.iter().map(|clip|{
+                                    self.clips.iter().enumerate().map(|(index, clip)|{
 
                                         let percentage_from_total = clip.duration / ctx.props().duration * 100.0;
 
+                                        let on_trim_start = ctx.link().callback(move |event: Event| {
+                                            Msg::TrimClip(event, index, TrimHandle::Start)
+                                        });
+                                        let on_trim_end = ctx.link().callback(move |event: Event| {
+                                            Msg::TrimClip(event, index, TrimHandle::End)
+                                        });
+
                                         html!{
                                             <div class={classes!({self.set_last_clip_pulse_animation()}, "segments")}
-                                             style={self.set_percent_style(percentage_from_total)}></div>
+                                             style={self.set_percent_style(percentage_from_total)}>
+                                                <div class="trimmed left-0"
+                                                    style={self.set_percent_style(clip.start / clip.duration * 100.0)}></div>
+                                                <div class="trimmed right-0"
+                                                    style={self.set_percent_style((clip.duration - clip.end) / clip.duration * 100.0)}></div>
+                                                <input type="range" class="trim-handle" aria-label="Trim clip start"
+                                                    min="0" max={clip.duration.to_string()} step="0.1"
+                                                    value={clip.start.to_string()} onchange={on_trim_start} />
+                                                <input type="range" class="trim-handle" aria-label="Trim clip end"
+                                                    min="0" max={clip.duration.to_string()} step="0.1"
+                                                    value={clip.end.to_string()} onchange={on_trim_end} />
+                                            </div>
                                         }
 
                                     }).collect::<Html>()
                                 }
                             </div>
                         </div>
+                        <ol class="clip-list"
+                            style={if has_clips && !self.is_recording {"display:flex;"}else{"display:none;"}}>
+                            {
+                                self.clips.iter().enumerate().map(|(index, clip)|{
+
+                                    let on_drag_start = ctx.link().callback(move |event: DragEvent| {
+                                        //Firefox only starts a drag that carries data
+                                        if let Some(data) = event.data_transfer() {
+                                            let _ = data.set_data("text/plain", index.to_string().as_str());
+                                        }
+                                        Msg::DragClip(Some(index))
+                                    });
+                                    let on_drag_end = ctx.link().callback(|_event: DragEvent| Msg::DragClip(None));
+                                    let on_drop = ctx.link().callback(move |event: DragEvent| {
+                                        event.prevent_default();
+                                        Msg::DropClip(index)
+                                    });
+                                    let on_delete = ctx.link().callback(move |_event: MouseEvent| Msg::DeleteClip(index));
+
+                                    html!{
+                                        <li draggable="true"
+                                            class={classes!("clip-list-item", (self.dragged_clip == Some(index)).then_some("dragging"))}
+                                            ondragstart={on_drag_start}
+                                            ondragend={on_drag_end}
+                                            ondragover={Callback::from(|event: DragEvent| event.prevent_default())}
+                                            ondrop={on_drop}>
+                                            <span class="material-symbols-outlined cursor-grab">{"drag_indicator"}</span>
+                                            <span class="clip-name">{format!("Clip {}", index + 1)}</span>
+                                            <span class="clip-duration">{format!("{:.1}s", clip.end - clip.start)}</span>
+                                            <span onclick={on_delete} title="Delete clip"
+                                                class="material-symbols-outlined cursor-pointer hover:opacity-75 duration-200">
+                                                {"delete"}
+                                            </span>
+                                        </li>
+                                    }
+
+                                }).collect::<Html>()
+                            }
+                        </ol>
                         <div class="actions flex flex-row justify-between items-center pt-5">
                                 <button onclick={&on_device_click} class="text-purple flex flex-row items-center justify-center">
                                         <span class="material-symbols-outlined top-[2px] relative">
@@ -364,6 +503,25 @@ impl Component for Camera {
                         </button>
                         <div id={format!("{}-preview", self.camera_id.clone())}
                              class="video-wrapper absolute top-0 bottom-0 "></div>
+                        <div class="voice-meter"
+                            style={if self.is_voice() && !self.is_merging {"display:flex;"}else{"display:none;"}}>
+                            <span class="material-symbols-outlined">{"graphic_eq"}</span>
+                        </div>
+                        <Loading load={self.is_merging && self.merge_progress.is_none()} />
+                        <div class="merge-progress"
+                            style={if self.is_merging && self.merge_progress.is_some() {"display:flex;"}else{"display:none;"}}>
+                            <div class="merge-progress-bar">
+                                <div class="segments" style={self.set_percent_style(self.merge_progress.unwrap_or_default())}></div>
+                            </div>
+                            <p class="text-white">{format!("Merging your clips {:.0}%", self.merge_progress.unwrap_or_default())}</p>
+                        </div>
+                        <div class="device-error"
+                            style={if self.clip_error.is_some() {"display:flex;"}else{"display:none"}}>
+                            <span class="material-symbols-outlined text-6xl">
+                                {"error"}
+                            </span>
+                            <p>{self.clip_error.clone().unwrap_or_default()}</p>
+                        </div>
                         <div class="actions w-full px-[20px] h-[100px]
                             absolute bottom-[15px] text-white text-[50px] flex flex-row justify-between items-center">
                             <span onclick={&on_play_toggle} style={if !self.is_playing {"display:block;"}else{"display:none;"}}
@@ -548,7 +706,7 @@ impl Component for Camera {
                     self.stream = context.stream.unwrap();
 
                     let mut recorder_optiona = MediaRecorderOptions::new();
-                    recorder_optiona.mime_type("video/webm");
+                    recorder_optiona.mime_type(self.capture_source.mime_type());
 
                     self.recorder =
                         MediaRecorder::new_with_media_stream_and_media_recorder_options(
@@ -557,6 +715,30 @@ impl Component for Camera {
                         )
                         .unwrap();
 
+                    let camera_overlay = CaptureSource::camera_overlay();
+
+                    let overlay_recorder = camera_overlay
+                        .as_ref()
+                        .map(|camera| {
+                            MediaRecorder::new_with_media_stream_and_media_recorder_options(
+                                camera,
+                                &recorder_optiona,
+                            )
+                        })
+                        .transpose();
+
+                    //The screen is still recorded, just without the camera
+                    self.overlay_recorder = match overlay_recorder {
+                        Ok(overlay_recorder) => overlay_recorder,
+                        Err(err) => {
+                            log::error!("{:?}", err);
+                            self.clip_error = Some(String::from(
+                                "Your camera can't be recorded, only the screen will be",
+                            ));
+                            None
+                        }
+                    };
+
                     let document = window().unwrap().document().unwrap();
 
                     let video_ele: HtmlMediaElement = document
@@ -576,9 +758,30 @@ impl Component for Camera {
                     let video_wrapper =
                         document.get_element_by_id(self.camera_id.as_str()).unwrap();
                     video_wrapper.set_inner_html("");
-                    video_wrapper.append_child(&video_ele).unwrap();
 
-                    let _ = video_ele.play();
+                    //Voice feedback shows the mic level instead
+                    if !self.is_voice() {
+                        video_wrapper.append_child(&video_ele).unwrap();
+
+                        let _ = video_ele.play();
+                    }
+
+                    //The camera is recorded too, show it next to the screen
+                    if let Some(camera) = camera_overlay {
+                        let overlay_ele: HtmlMediaElement = document
+                            .create_element("video")
+                            .unwrap()
+                            .dyn_into::<HtmlMediaElement>()
+                            .unwrap();
+
+                        overlay_ele.set_class_name("camera-overlay");
+                        overlay_ele.set_src_object(Some(&camera));
+                        overlay_ele.set_muted(true);
+
+                        video_wrapper.append_child(&overlay_ele).unwrap();
+
+                        let _ = overlay_ele.play();
+                    }
 
                     self.audio_analyser.set_fft_size(512);
                     self.audio_analyser.set_min_decibels(-127.0);
@@ -624,7 +827,13 @@ impl Component for Camera {
                 }
                 CameraContextAction::SendDeviceError => {
                     let error = context.device_error.unwrap();
-                    match error.device_type {
+                    //Only the microphone is opened for voice feedback
+                    let device_type = if self.is_voice() {
+                        DeviceType::Microphone
+                    } else {
+                        error.device_type.clone()
+                    };
+                    match device_type {
                         DeviceType::Camera => {
                             self.video_ouput_error = error;
                         }
@@ -635,25 +844,32 @@ impl Component for Camera {
                     }
                     return true;
                 }
-                CameraContextAction::SendClipDetails => {
+                CameraContextAction::AddedClip => {
+                    self.history.clear();
                     self.clips.push(context.clip_details.unwrap());
                     self.timestamp = 0.0;
                     return true;
                 }
-                CameraContextAction::AddedClip => {
-                    let clip_details = context.clip_details.unwrap();
-                    let last_chunk = self
-                        .chunks
-                        .get(self.chunks.length() - 1)
-                        .dyn_into::<JsValue>()
-                        .unwrap()
-                        .dyn_into::<Blob>()
-                        .unwrap();
-
-                    self.get_clip_detail_from_blob(last_chunk, clip_details.id.clone());
+                CameraContextAction::UploadProgress => {}
+                CameraContextAction::MergeQueued | CameraContextAction::MergeStarted => {
+                    self.is_merging = true;
+                    return true;
+                }
+                CameraContextAction::MergeProgress => {
+                    self.merge_progress = context.progress;
+                    return true;
                 }
                 CameraContextAction::MergedClip => {
+                    self.is_merging = false;
+                    self.merge_progress = None;
                     self.play_saved_clip(ctx);
+                    return true;
+                }
+                CameraContextAction::ClipError => {
+                    self.is_merging = false;
+                    self.merge_progress = None;
+                    self.clip_error = context.error;
+                    return true;
                 }
             },
             Msg::Timestamp() => {
@@ -674,7 +890,8 @@ impl Component for Camera {
 
                 self.is_recording = true;
 
-                self.last_discarded_clip = None;
+                //Undoing past a recording could bring back more than the time limit
+                self.history.clear();
 
                 self.timestamp_timer = Interval::new(100, move || {
                     link.clone().send_message(Msg::Timestamp());
@@ -689,6 +906,13 @@ impl Component for Camera {
 
                 self.stop_recorder();
             }
+            Msg::SetCaptureSource(source) => {
+                if source != self.capture_source && !self.is_recording {
+                    self.capture_source = source;
+                    self.init_devices();
+                }
+                return true;
+            }
             Msg::SetView(view) => {
                 self.preview_timestamp_timer = Interval::new(1000, || {});
 
@@ -773,16 +997,48 @@ impl Component for Camera {
                 return true;
             }
             Msg::OnClipUndo() => {
-                if let Some(clip) = &self.last_discarded_clip {
-                    self.clips.push(clip.clone());
-                    self.last_discarded_clip = None;
+                if self.history.undo(&mut self.clips) {
                     self.timestamp = 0.0;
                 }
                 return true;
             }
+            Msg::OnClipRedo() => {
+                if self.history.redo(&mut self.clips) {
+                    self.timestamp = 0.0;
+                }
+                return true;
+            }
+            Msg::DeleteClip(index) => {
+                self.delete_clip(index);
+                return true;
+            }
+            Msg::DragClip(index) => {
+                self.dragged_clip = index;
+                return true;
+            }
+            Msg::DropClip(index) => {
+                if let Some(from) = self.dragged_clip.take() {
+                    self.move_clip(from, index);
+                }
+                return true;
+            }
+            Msg::TrimClip(event, index, handle) => {
+                let seconds = event
+                    .target()
+                    .and_then(|target| target.dyn_into::<HtmlInputElement>().ok())
+                    .map(|input| input.value_as_number());
+
+                if let Some(seconds) = seconds {
+                    self.trim_clip(index, handle, seconds);
+                }
+                return true;
+            }
             Msg::PlaySavedClips() => {
                 if !self.is_recording {
                     self.is_playing = true;
+                    self.is_merging = true;
+                    self.merge_progress = None;
+                    self.clip_error = None;
                     self.view = CameraView::Preview;
 
                     self.merge_stored_clips();
@@ -892,6 +1148,18 @@ impl Camera {
         self.duration - (self.timestamp + clips_total_duration)
     }
 
+    /// Recording voice feedback, from the microphone alone.
+    pub fn is_voice(&self) -> bool {
+        self.capture_source == CaptureSource::Microphone
+    }
+    /// The error of the device the editor can't record without.
+    fn capture_error(&self) -> &DeviceError {
+        if self.is_voice() {
+            &self.audio_input_error
+        } else {
+            &self.video_ouput_error
+        }
+    }
     pub fn get_microphone_state_styles(&self) -> String {
         format!("width:{}%;", self.audio_average_percent)
     }
@@ -932,38 +1200,28 @@ impl Camera {
 
         let media = window.navigator().media_devices().unwrap();
 
-        let mut constraints = MediaStreamConstraints::new();
-
-        match &self.active_devices.camera {
+        let camera = match &self.active_devices.camera {
             Some(id) => {
                 let contstraint_options = ConstraintOptions {
                     device_id: ConstraintDeviceId { exact: id.clone() },
                 };
-                constraints.video(
-                    &JsValue::from_serde(&contstraint_options).expect("contstraint_options_error"),
-                );
+                JsValue::from_serde(&contstraint_options).expect("contstraint_options_error")
             }
 
-            None => {
-                constraints.video(&JsValue::from(true));
-            }
-        }
+            None => JsValue::from(true),
+        };
 
-        match &self.active_devices.microphone {
+        let microphone = match &self.active_devices.microphone {
             Some(id) => {
                 let contstraint_options = ConstraintOptions {
                     device_id: ConstraintDeviceId { exact: id.clone() },
                 };
-                constraints.audio(
-                    &JsValue::from_serde(&contstraint_options).expect("contstraint_options_error"),
-                );
-            }
-            None => {
-                constraints.audio(&JsValue::from(true));
+                JsValue::from_serde(&contstraint_options).expect("contstraint_options_error")
             }
-        }
+            None => JsValue::from(true),
+        };
 
-        let user_media = media.get_user_media_with_constraints(&constraints).unwrap();
+        let capture_source = self.capture_source;
 
         self.audio_context = AudioContext::new().unwrap();
         self.audio_analyser = self.audio_context.create_analyser().unwrap();
@@ -991,11 +1249,8 @@ impl Camera {
         });
 
         spawn_local(async move {
-            let _user_media = JsFuture::from(user_media).await;
-
-            match _user_media {
-                Ok(media) => {
-                    let stream: MediaStream = media.try_into().unwrap();
+            match capture_source.open(camera, microphone).await {
+                Ok(stream) => {
                     CameraService::dispatcher().send(Request::SendUserMedia(stream));
                 }
                 Err(err) => {
@@ -1006,81 +1261,40 @@ impl Camera {
     }
 
     pub fn start_recorder(&mut self, _ctx: &Context<Self>) {
+        Self::start_track(&self.recorder, ClipTrack::Main);
+
+        if let Some(overlay_recorder) = &self.overlay_recorder {
+            Self::start_track(overlay_recorder, ClipTrack::Camera);
+        }
+    }
+    /// Starts `recorder`, uploading what it records as a `track` clip.
+    fn start_track(recorder: &MediaRecorder, track: ClipTrack) {
         let ondata_callback = Closure::wrap(Box::new(move |e: BlobEvent| {
             let data = e.data().expect("expect_data");
             let _size = data.size();
 
-            CameraService::dispatcher().send(Request::SendBlobChunk(data))
+            CameraService::dispatcher().send(Request::SendBlobChunk(data, track))
         }) as Box<dyn FnMut(BlobEvent)>);
 
-        self.recorder
-            .set_ondataavailable(Some(ondata_callback.as_ref().unchecked_ref()));
+        recorder.set_ondataavailable(Some(ondata_callback.as_ref().unchecked_ref()));
 
         ondata_callback.forget();
 
-        self.recorder.start().unwrap();
-    }
-
-    pub fn get_clip_detail_from_blob(&self, chunk: Blob, id: String) {
-        let doc = window().unwrap().document().unwrap();
+        let onstop_callback = Closure::wrap(Box::new(move |_e: Event| {
+            CameraService::dispatcher().send(Request::FinalizeClip(track))
+        }) as Box<dyn FnMut(Event)>);
 
-        let chunk_array = Array::new();
-        chunk_array.push(&chunk);
+        recorder.set_onstop(Some(onstop_callback.as_ref().unchecked_ref()));
 
-        let mut options = BlobPropertyBag::new();
-        options.type_("video/webm");
+        onstop_callback.forget();
 
-        let blob = Blob::new_with_blob_sequence_and_options(&chunk_array, &options).unwrap();
-        let blob_url = web_sys::Url::create_object_url_with_blob(&blob).unwrap();
+        CameraService::dispatcher().send(Request::BeginClip(track));
 
-        let vid_ele = doc
-            .create_element("video")
-            .unwrap()
-            .dyn_into::<HtmlMediaElement>()
+        recorder
+            .start_with_time_slice(RECORDER_TIMESLICE_MS)
             .unwrap();
-
-        vid_ele.set_src(blob_url.as_str());
-        vid_ele.load();
-
-        let onloadedmetadata_callback = Closure::wrap(Box::new(move |e: Event| {
-            let video_element_target = e.target().unwrap().dyn_into::<HtmlVideoElement>().unwrap();
-
-            let _chunk = chunk.clone();
-            let _id = id.clone();
-
-            if video_element_target.duration() == f64::INFINITY {
-                video_element_target.set_current_time(f64::MAX);
-
-                let ontimeupdate_cb = Closure::wrap(Box::new(move |_e: Event| {
-                    let __chunk = _chunk.clone();
-                    let __id = _id.clone();
-
-                    let temp_element_target =
-                        _e.target().unwrap().dyn_into::<HtmlVideoElement>().unwrap();
-
-                    temp_element_target.set_ontimeupdate(None);
-                    temp_element_target.set_current_time(f64::from(0));
-                    temp_element_target.set_onloadedmetadata(None);
-
-                    let clip_detail = ClipDetails {
-                        id: __id,
-                        duration: temp_element_target.duration(),
-                        chunk: __chunk,
-                    };
-
-                    CameraService::dispatcher().send(Request::SendClipDetails(clip_detail));
-                }) as Box<dyn FnMut(Event)>);
-
-                video_element_target
-                    .set_ontimeupdate(Some(ontimeupdate_cb.as_ref().unchecked_ref()));
-                ontimeupdate_cb.forget();
-            }
-        }) as Box<dyn FnMut(Event)>);
-
-        vid_ele.set_onloadedmetadata(Some(onloadedmetadata_callback.as_ref().unchecked_ref()));
-
-        onloadedmetadata_callback.forget();
     }
+
     pub fn send_recording(&self) {
         let doc = window().unwrap().document().unwrap();
 
@@ -1112,6 +1326,14 @@ impl Camera {
         self.video_element.load();
     }
     pub fn play_saved_clip(&mut self, ctx: &Context<Self>) {
+        let config = match WidgetConfig::current() {
+            Ok(config) => config,
+            Err(err) => {
+                log::error!("{}", err);
+                return;
+            }
+        };
+
         let link = ctx.link().clone();
 
         let doc = window()
@@ -1120,7 +1342,7 @@ impl Camera {
             .expect("expect_document");
 
         let vid_ele = doc
-            .create_element("video")
+            .create_element(if self.is_voice() { "audio" } else { "video" })
             .unwrap()
             .dyn_into::<HtmlMediaElement>()
             .unwrap();
@@ -1132,8 +1354,18 @@ impl Camera {
             .set_attribute("id", format!("{}-preview-player", &vid_id).as_str())
             .unwrap_throw();
 
-        let mut video_uri = Utilities::config("api_url");
-        video_uri.push_str(format!("clip/session?v={}", Utilities::rnd_id("cache-")).as_str());
+        // Browsers that can't play WebM or Opus, i.e. Safari, get the MP4 or MP3 export.
+        let video_uri = if self.is_voice() {
+            if vid_ele.can_play_type("audio/ogg; codecs=opus").is_empty() {
+                config.api_endpoint("clip/session?format=mp3")
+            } else {
+                config.api_endpoint("clip/session?format=ogg")
+            }
+        } else if vid_ele.can_play_type("video/webm").is_empty() {
+            config.api_endpoint("clip/session?format=mp4")
+        } else {
+            config.api_endpoint("clip/session")
+        };
         vid_ele.set_src(&video_uri);
         vid_ele.load();
 
@@ -1154,21 +1386,69 @@ impl Camera {
         let clip_ids = self
             .clips
             .iter()
-            .map(|c| ClipDetailRequest {
-                id: c.id.clone(),
-                duration: c.duration.clone(),
-            })
+            .map(|c| c.to_request())
             .collect::<Vec<ClipDetailRequest>>();
 
-        CameraService::dispatcher().send(Request::OnPlayback(clip_ids));
+        //Browsers that can't play Opus, i.e. Safari, get MP3 straight from the merge
+        let audio_format = self.is_voice().then(|| {
+            let plays_opus = window()
+                .and_then(|window| window.document())
+                .and_then(|doc| doc.create_element("audio").ok())
+                .and_then(|element| element.dyn_into::<HtmlMediaElement>().ok())
+                .map(|element| !element.can_play_type("audio/ogg; codecs=opus").is_empty())
+                .unwrap_or(true);
+
+            if plays_opus {
+                AudioFormat::Ogg
+            } else {
+                AudioFormat::Mp3
+            }
+        });
+
+        CameraService::dispatcher().send(Request::OnPlayback(clip_ids, audio_format));
     }
     pub fn discard_last_clip(&mut self) {
-        if let Some(clip) = self.clips.pop() {
-            self.last_discarded_clip = Some(clip);
+        if !self.clips.is_empty() {
+            self.delete_clip(self.clips.len() - 1);
+        }
+    }
+    pub fn delete_clip(&mut self, index: usize) {
+        if index < self.clips.len() {
+            self.history.record(&self.clips);
+            self.clips.remove(index);
             self.timestamp = 0.0;
         }
     }
+    /// Moves the clip at `from` to `to`, shifting the clips in between.
+    pub fn move_clip(&mut self, from: usize, to: usize) {
+        let mut clips = self.clips.clone();
+
+        if crate::models::move_clip(&mut clips, from, to) {
+            self.history.record(&self.clips);
+            self.clips = clips;
+        }
+    }
+    /// Moves a clip's in or out handle to `seconds`, keeping at least
+    /// `MIN_TRIMMED_DURATION` between them.
+    pub fn trim_clip(&mut self, index: usize, handle: TrimHandle, seconds: f64) {
+        let clip = match self.clips.get(index) {
+            Some(clip) => clip,
+            None => return,
+        };
+
+        if let Some((start, end)) =
+            ClipDetails::trim_points(clip.start, clip.end, clip.duration, handle, seconds)
+        {
+            self.history.record(&self.clips);
+            self.clips[index].start = start;
+            self.clips[index].end = end;
+        }
+    }
     pub fn stop_recorder(&mut self) {
         let _ = self.recorder.stop().unwrap();
+
+        if let Some(overlay_recorder) = &self.overlay_recorder {
+            let _ = overlay_recorder.stop();
+        }
     }
 }
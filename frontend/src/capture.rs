@@ -0,0 +1,182 @@
+use js_sys::Array;
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    window, DisplayMediaStreamConstraints, MediaStream, MediaStreamConstraints, MediaStreamTrack,
+};
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Capture>> = const { RefCell::new(None) };
+}
+
+/// What the recorder is fed with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaptureSource {
+    #[default]
+    Camera,
+    /// A screen, window or tab chosen by the user, with the microphone.
+    Screen,
+    /// The screen, with the camera recorded alongside it as a clip of its own that the
+    /// server draws over a corner of the screen when merging.
+    ScreenWithCamera,
+    /// The microphone alone, for voice feedback. Not offered in the source selector.
+    Microphone,
+}
+
+impl CaptureSource {
+    pub const ALL: [CaptureSource; 3] = [
+        CaptureSource::Camera,
+        CaptureSource::Screen,
+        CaptureSource::ScreenWithCamera,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CaptureSource::Camera => "Camera",
+            CaptureSource::Screen => "Screen",
+            CaptureSource::ScreenWithCamera => "Screen and camera",
+            CaptureSource::Microphone => "Microphone",
+        }
+    }
+
+    /// Material symbol shown in the source selector.
+    pub fn icon(&self) -> &'static str {
+        match self {
+            CaptureSource::Camera => "videocam",
+            CaptureSource::Screen => "screen_share",
+            CaptureSource::ScreenWithCamera => "picture_in_picture",
+            CaptureSource::Microphone => "mic",
+        }
+    }
+
+    /// Container the `MediaRecorder` writes this source's clips in.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            CaptureSource::Camera | CaptureSource::Screen | CaptureSource::ScreenWithCamera => {
+                "video/webm"
+            }
+            CaptureSource::Microphone => "audio/webm",
+        }
+    }
+
+    /// Opens the devices this source records from and returns a single stream with one
+    /// video, unless it is the microphone alone, and the microphone's audio for the
+    /// `MediaRecorder`. `camera` and `microphone`
+    /// are `getUserMedia` constraints. Whatever the previous source opened is stopped.
+    pub async fn open(&self, camera: JsValue, microphone: JsValue) -> Result<MediaStream, JsValue> {
+        ACTIVE.with(|active| active.borrow_mut().take());
+
+        let capture = match self {
+            CaptureSource::Camera => Capture::sources(vec![user_media(camera, microphone).await?]),
+            CaptureSource::Screen => {
+                let screen = display_media().await?;
+                let audio = user_media(JsValue::FALSE, microphone).await?;
+
+                Capture::sources(vec![screen, audio])
+            }
+            CaptureSource::ScreenWithCamera => {
+                let screen = display_media().await?;
+                let audio = user_media(JsValue::FALSE, microphone).await?;
+                let camera = user_media(camera, JsValue::FALSE).await?;
+
+                let mut capture = Capture::sources(vec![screen, audio]);
+                capture.camera = Some(camera);
+
+                capture
+            }
+            CaptureSource::Microphone => {
+                Capture::sources(vec![user_media(JsValue::FALSE, microphone).await?])
+            }
+        };
+
+        let stream = capture.stream.clone();
+        ACTIVE.with(|active| *active.borrow_mut() = Some(capture));
+
+        Ok(stream)
+    }
+
+    /// The camera of the open `ScreenWithCamera` source, recorded into a clip of its own.
+    pub fn camera_overlay() -> Option<MediaStream> {
+        ACTIVE.with(|active| {
+            active
+                .borrow()
+                .as_ref()
+                .and_then(|capture| capture.camera.clone())
+        })
+    }
+}
+
+/// The stream handed to the recorder and what feeds it. Dropping it stops the devices.
+struct Capture {
+    stream: MediaStream,
+    sources: Vec<MediaStream>,
+    /// Video only, its audio is already in `stream`.
+    camera: Option<MediaStream>,
+}
+
+impl Capture {
+    /// Records the first video track and every audio track of `sources`.
+    fn sources(sources: Vec<MediaStream>) -> Capture {
+        let tracks = Array::new();
+
+        if let Some(video) = sources
+            .iter()
+            .find_map(|source| source.get_video_tracks().iter().next())
+        {
+            tracks.push(&video);
+        }
+        for source in sources.iter() {
+            for track in source.get_audio_tracks().iter() {
+                tracks.push(&track);
+            }
+        }
+
+        let stream = MediaStream::new_with_tracks(&tracks).unwrap_throw();
+
+        Capture {
+            stream,
+            sources,
+            camera: None,
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        for source in self
+            .sources
+            .iter()
+            .chain(&self.camera)
+            .chain([&self.stream])
+        {
+            for track in source.get_tracks().iter() {
+                track.unchecked_into::<MediaStreamTrack>().stop();
+            }
+        }
+    }
+}
+
+async fn user_media(video: JsValue, audio: JsValue) -> Result<MediaStream, JsValue> {
+    let media = window().unwrap().navigator().media_devices()?;
+
+    let mut constraints = MediaStreamConstraints::new();
+    constraints.video(&video);
+    constraints.audio(&audio);
+
+    let stream = JsFuture::from(media.get_user_media_with_constraints(&constraints)?).await?;
+
+    Ok(stream.unchecked_into())
+}
+
+async fn display_media() -> Result<MediaStream, JsValue> {
+    let media = window().unwrap().navigator().media_devices()?;
+
+    let mut constraints = DisplayMediaStreamConstraints::new();
+    constraints.video(&JsValue::TRUE);
+
+    let stream = JsFuture::from(media.get_display_media_with_constraints(&constraints)?).await?;
+
+    Ok(stream.unchecked_into())
+}
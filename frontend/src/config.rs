@@ -0,0 +1,389 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use wasm_bindgen::JsValue;
+use web_sys::{window, Element};
+
+/// Id of the `<script type="application/json">` tag holding the page's widget config.
+pub const CONFIG_SCRIPT_ID: &str = "feedback-widget-config";
+/// The widget is mounted into the first element with this attribute once the module loads.
+pub const MOUNT_ATTRIBUTE: &str = "data-feedback-widget";
+
+const DEFAULT_MAX_DURATION: f64 = 10.0;
+const DEFAULT_LOCALE: &str = "en";
+
+/// The `data-*` attributes read from the mount element and the keys they set.
+const DATA_ATTRIBUTES: [(&str, &str); 5] = [
+    ("data-api-url", "apiUrl"),
+    ("data-ws-url", "wsUrl"),
+    ("data-max-duration", "maxDuration"),
+    ("data-theme", "theme"),
+    ("data-locale", "locale"),
+];
+
+thread_local! {
+    static CURRENT: RefCell<Option<Rc<WidgetConfig>>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+impl Theme {
+    /// Class added to the widget's root element.
+    pub fn class(&self) -> &'static str {
+        match self {
+            Theme::Light => "theme-light",
+            Theme::Dark => "theme-dark",
+        }
+    }
+}
+
+/// How a mounted widget talks to the backend and presents itself. Keys are camelCase in
+/// every source, e.g. `{"apiUrl": "http://127.0.0.1:9011/", "wsUrl": "ws://127.0.0.1:9011/ws/"}`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct WidgetConfig {
+    /// Base URL of the backend's HTTP API.
+    pub api_url: String,
+    /// Base URL of the backend's WebSocket routes.
+    pub ws_url: String,
+    /// Longest video feedback that can be recorded, in seconds.
+    #[serde(default = "default_max_duration")]
+    pub max_duration: f64,
+    #[serde(default)]
+    pub theme: Theme,
+    /// BCP 47 language tag of the page the widget is embedded in.
+    #[serde(default = "default_locale")]
+    pub locale: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// A required key is missing, unknown or holds a value of the wrong type or range.
+    Invalid(String),
+    /// The element a widget was to be mounted into does not exist.
+    MissingTarget(String),
+    /// Only one widget can be mounted per page.
+    AlreadyMounted,
+    /// Read before any widget was mounted.
+    NotLoaded,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Invalid(message) => {
+                write!(f, "Invalid feedback widget config: {}", message)
+            }
+            ConfigError::MissingTarget(selector) => {
+                write!(
+                    f,
+                    "No element matches {} to mount the feedback widget",
+                    selector
+                )
+            }
+            ConfigError::AlreadyMounted => {
+                write!(f, "A feedback widget is already mounted on this page")
+            }
+            ConfigError::NotLoaded => write!(f, "The feedback widget is not mounted"),
+        }
+    }
+}
+
+impl From<ConfigError> for JsValue {
+    fn from(err: ConfigError) -> JsValue {
+        JsValue::from(js_sys::Error::new(err.to_string().as_str()))
+    }
+}
+
+impl WidgetConfig {
+    /// Reads the config of a widget mounted into `element`. The JSON script tag is read
+    /// first, the element's `data-*` attributes override it and `overrides`, passed to
+    /// `initWidget`, override both.
+    pub fn load(
+        element: &Element,
+        overrides: Option<Map<String, Value>>,
+    ) -> Result<WidgetConfig, ConfigError> {
+        let script = window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id(CONFIG_SCRIPT_ID))
+            .and_then(|script| script.text_content());
+        let attributes: Vec<(&str, String)> = DATA_ATTRIBUTES
+            .iter()
+            .filter_map(|(attribute, _)| {
+                element
+                    .get_attribute(attribute)
+                    .map(|value| (*attribute, value))
+            })
+            .collect();
+
+        Self::merge(script.as_deref(), &attributes, overrides)
+    }
+
+    /// Builds the config from the script tag's text, the mount element's `data-*`
+    /// attributes and the `initWidget` overrides, each overriding the one before.
+    fn merge(
+        script: Option<&str>,
+        attributes: &[(&str, String)],
+        overrides: Option<Map<String, Value>>,
+    ) -> Result<WidgetConfig, ConfigError> {
+        let mut values = Self::script_values(script)?;
+        values.extend(Self::attribute_values(attributes)?);
+        values.extend(overrides.unwrap_or_default());
+
+        let config: WidgetConfig = serde_json::from_value(Value::Object(values))
+            .map_err(|err| ConfigError::Invalid(err.to_string()))?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Makes this the config `WidgetConfig::current` returns.
+    pub fn install(self) -> Result<Rc<WidgetConfig>, ConfigError> {
+        CURRENT.with(|current| {
+            let mut current = current.borrow_mut();
+            if current.is_some() {
+                return Err(ConfigError::AlreadyMounted);
+            }
+
+            let config = Rc::new(self);
+            *current = Some(config.clone());
+
+            Ok(config)
+        })
+    }
+
+    /// The mounted widget's config, for services that are not handed it as props.
+    pub fn current() -> Result<Rc<WidgetConfig>, ConfigError> {
+        CURRENT.with(|current| current.borrow().clone().ok_or(ConfigError::NotLoaded))
+    }
+
+    /// `path` below `api_url`.
+    pub fn api_endpoint(&self, path: &str) -> String {
+        join(self.api_url.as_str(), path)
+    }
+
+    /// `path` below `ws_url`.
+    pub fn ws_endpoint(&self, path: &str) -> String {
+        join(self.ws_url.as_str(), path)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !self.api_url.starts_with("http://") && !self.api_url.starts_with("https://") {
+            return Err(ConfigError::Invalid(format!(
+                "apiUrl {} is not an http(s) URL",
+                self.api_url
+            )));
+        }
+
+        if !self.ws_url.starts_with("ws://") && !self.ws_url.starts_with("wss://") {
+            return Err(ConfigError::Invalid(format!(
+                "wsUrl {} is not a ws(s) URL",
+                self.ws_url
+            )));
+        }
+
+        if !self.max_duration.is_finite() || self.max_duration <= 0.0 {
+            return Err(ConfigError::Invalid(format!(
+                "maxDuration {} is not a positive number of seconds",
+                self.max_duration
+            )));
+        }
+
+        if self.locale.trim().is_empty() {
+            return Err(ConfigError::Invalid(String::from("locale is empty")));
+        }
+
+        Ok(())
+    }
+
+    fn script_values(text: Option<&str>) -> Result<Map<String, Value>, ConfigError> {
+        match text {
+            Some(text) => serde_json::from_str(text).map_err(|err| {
+                ConfigError::Invalid(format!(
+                    "#{} is not a JSON object: {}",
+                    CONFIG_SCRIPT_ID, err
+                ))
+            }),
+            None => Ok(Map::new()),
+        }
+    }
+
+    fn attribute_values(attributes: &[(&str, String)]) -> Result<Map<String, Value>, ConfigError> {
+        let mut values = Map::new();
+
+        for (attribute, value) in attributes {
+            let key = match DATA_ATTRIBUTES.iter().find(|(name, _)| name == attribute) {
+                Some((_, key)) => *key,
+                None => continue,
+            };
+
+            let value = if key == "maxDuration" {
+                let seconds = value
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|seconds| seconds.is_finite())
+                    .ok_or_else(|| {
+                        ConfigError::Invalid(format!("{} {:?} is not a number", attribute, value))
+                    })?;
+
+                Value::from(seconds)
+            } else {
+                Value::from(value.as_str())
+            };
+
+            values.insert(key.to_string(), value);
+        }
+
+        Ok(values)
+    }
+}
+
+fn default_max_duration() -> f64 {
+    DEFAULT_MAX_DURATION
+}
+
+fn default_locale() -> String {
+    DEFAULT_LOCALE.to_string()
+}
+
+fn join(base: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SCRIPT: &str = r#"{"apiUrl": "http://script/", "wsUrl": "ws://script/"}"#;
+
+    fn object(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(values) => values,
+            _ => panic!("not an object"),
+        }
+    }
+
+    fn invalid(result: Result<WidgetConfig, ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid(message)) => message,
+            other => panic!("expected an invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn merge_defaults_optional_keys() {
+        let config = WidgetConfig::merge(Some(SCRIPT), &[], None).unwrap();
+
+        assert_eq!(config.api_url, "http://script/");
+        assert_eq!(config.ws_url, "ws://script/");
+        assert_eq!(config.max_duration, DEFAULT_MAX_DURATION);
+        assert_eq!(config.theme, Theme::Light);
+        assert_eq!(config.locale, DEFAULT_LOCALE);
+    }
+
+    #[test]
+    fn merge_precedence() {
+        let attributes = [
+            ("data-api-url", String::from("http://attribute/")),
+            ("data-ws-url", String::from("ws://attribute/")),
+            ("data-max-duration", String::from(" 30 ")),
+            ("data-theme", String::from("dark")),
+        ];
+        let overrides = object(json!({"apiUrl": "https://init/", "maxDuration": 5.5}));
+
+        let config = WidgetConfig::merge(Some(SCRIPT), &attributes, Some(overrides)).unwrap();
+
+        assert_eq!(config.api_url, "https://init/");
+        assert_eq!(config.ws_url, "ws://attribute/");
+        assert_eq!(config.max_duration, 5.5);
+        assert_eq!(config.theme, Theme::Dark);
+    }
+
+    #[test]
+    fn merge_without_script_tag() {
+        let attributes = [
+            ("data-api-url", String::from("http://attribute/")),
+            ("data-ws-url", String::from("wss://attribute/")),
+            ("data-locale", String::from("de-CH")),
+        ];
+
+        let config = WidgetConfig::merge(None, &attributes, None).unwrap();
+
+        assert_eq!(config.ws_url, "wss://attribute/");
+        assert_eq!(config.locale, "de-CH");
+    }
+
+    #[test]
+    fn merge_rejects_bad_sources() {
+        assert!(invalid(WidgetConfig::merge(Some("[1, 2]"), &[], None)).contains(CONFIG_SCRIPT_ID));
+        assert_eq!(
+            invalid(WidgetConfig::merge(
+                Some(SCRIPT),
+                &[("data-max-duration", String::from("ten"))],
+                None,
+            )),
+            "data-max-duration \"ten\" is not a number"
+        );
+        assert!(invalid(WidgetConfig::merge(None, &[], None)).contains("apiUrl"));
+
+        let unknown = object(json!({"color": "red"}));
+        assert!(invalid(WidgetConfig::merge(Some(SCRIPT), &[], Some(unknown))).contains("color"));
+    }
+
+    #[test]
+    fn validate_rejects_every_problem() {
+        let cases = [
+            (
+                json!({"apiUrl": "ftp://api/"}),
+                "apiUrl ftp://api/ is not an http(s) URL",
+            ),
+            (
+                json!({"wsUrl": "http://ws/"}),
+                "wsUrl http://ws/ is not a ws(s) URL",
+            ),
+            (
+                json!({"maxDuration": 0}),
+                "maxDuration 0 is not a positive number of seconds",
+            ),
+            (
+                json!({"maxDuration": -3}),
+                "maxDuration -3 is not a positive number of seconds",
+            ),
+            (json!({"locale": "  "}), "locale is empty"),
+        ];
+
+        for (overrides, message) in cases {
+            let result = WidgetConfig::merge(Some(SCRIPT), &[], Some(object(overrides)));
+
+            assert_eq!(invalid(result), message);
+        }
+
+        let nan = [("data-max-duration", String::from("NaN"))];
+        assert_eq!(
+            invalid(WidgetConfig::merge(Some(SCRIPT), &nan, None)),
+            "data-max-duration \"NaN\" is not a number"
+        );
+    }
+
+    #[test]
+    fn endpoints_join_with_one_slash() {
+        let config = WidgetConfig::merge(Some(SCRIPT), &[], None).unwrap();
+
+        assert_eq!(config.api_endpoint("/feedback"), "http://script/feedback");
+        assert_eq!(config.ws_endpoint("clips"), "ws://script/clips");
+    }
+}
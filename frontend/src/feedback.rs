@@ -1,16 +1,18 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use crate::camera::Camera;
+use crate::config::WidgetConfig;
 use crate::form::FormBuilder;
 use crate::models::{
-    CameraView, FeedbackMsg, FeedbackStep, FeedbackVideo, FieldValue, FormField, Msg,
+    CameraView, FeedbackContext, FeedbackContextAction, FeedbackMsg, FeedbackStep, FeedbackVideo,
+    FieldValue, FormField, Msg,
 };
 
 use crate::service::feedback::{FeedbackService, Request};
+use crate::service::session::SessionService;
 use crate::utilities::Utilities;
 
-use wasm_bindgen::JsCast;
-use web_sys::{window, HtmlDocument};
 use yew::prelude::*;
 use yew_agent::{Bridge, Bridged, Dispatched, Dispatcher};
 
@@ -20,11 +22,22 @@ pub struct FeedbackWidget {
     previous_step: FeedbackStep,
     active: bool,
     video_form: FormBuilder<FeedbackVideo>,
+    voice_form: FormBuilder<FeedbackVideo>,
+    /// Whether the editor records voice feedback rather than video, kept from the last
+    /// feedback type picked.
+    voice: bool,
     message_form: FormBuilder<FeedbackMsg>,
+    message_error: Option<String>,
+    is_submitting: bool,
     _fs: Dispatcher<FeedbackService>,
     producer: Box<dyn Bridge<FeedbackService>>,
 }
 
+#[derive(PartialEq, Properties)]
+pub struct Props {
+    pub config: Rc<WidgetConfig>,
+}
+
 impl FeedbackWidget {
     fn is_step_active(&self, step: FeedbackStep) -> String {
         let mut classes = String::from("step ");
@@ -53,6 +66,21 @@ impl FeedbackWidget {
 
         *_value = value.to_string();
     }
+    fn on_feedback_service(&mut self, context: FeedbackContext) {
+        match context.context_type {
+            Some(FeedbackContextAction::MessageSubmitted) => {
+                self.is_submitting = false;
+                self.message_error = None;
+                self.previous_step = self.active_step.clone();
+                self.active_step = FeedbackStep::ThankYou;
+            }
+            Some(FeedbackContextAction::MessageFailed) => {
+                self.is_submitting = false;
+                self.message_error = context.error;
+            }
+            None => {}
+        }
+    }
     fn set_message_form_field(&mut self, field_value: FieldValue) {
         let value = Utilities::string_to_static_str(field_value.value.clone());
         let field = self
@@ -67,20 +95,10 @@ impl FeedbackWidget {
 }
 impl Component for FeedbackWidget {
     type Message = Msg;
-    type Properties = ();
+    type Properties = Props;
 
     fn create(ctx: &Context<Self>) -> Self {
-        let window = window().unwrap();
-        let document = window.document().unwrap();
-        let html_document = document.dyn_into::<HtmlDocument>().unwrap();
-
-        let mut cookie = Utilities::rnd_id("X-FDot-Session=");
-        cookie.push_str("; path=/");
-        let mut _cookie = Utilities::rnd_id("teseter=");
-        _cookie.push_str("; path=/");
-
-        html_document.set_cookie(_cookie.as_str()).unwrap();
-        html_document.set_cookie(cookie.as_str()).unwrap();
+        SessionService::start(&ctx.props().config);
 
         let link = ctx.link();
 
@@ -137,7 +155,7 @@ impl Component for FeedbackWidget {
             FeedbackService::dispatcher().send(Request::OnMessageSubmission(
                 serde_json::to_string::<HashMap<String, String>>(&data).unwrap(),
             ));
-            Msg::SetStep(FeedbackStep::ThankYou)
+            Msg::SubmittingMessage()
         });
 
         let feedback_vid = FeedbackVideo {
@@ -163,6 +181,22 @@ impl Component for FeedbackWidget {
             },
         };
 
+        let mut feedback_voice_form = FormBuilder::new(
+            Utilities::rnd_id("feedback-"),
+            feedback_vid.clone(),
+            String::from("start recording"),
+        );
+
+        let voice_fields = feedback_voice_form.field_keys.clone();
+
+        feedback_voice_form.on_submit = link.callback(move |event: FocusEvent| {
+            event.prevent_default();
+
+            let _data = Utilities::form_data(event, voice_fields.clone());
+
+            Msg::SetStep(FeedbackStep::VoiceEditor)
+        });
+
         let mut feedback_video_form = FormBuilder::new(
             Utilities::rnd_id("feedback-"),
             feedback_vid,
@@ -188,7 +222,11 @@ impl Component for FeedbackWidget {
             previous_step: FeedbackStep::None,
             active: false,
             video_form: feedback_video_form,
+            voice_form: feedback_voice_form,
+            voice: false,
             message_form: feedback_msg_form,
+            message_error: None,
+            is_submitting: false,
             _fs: FeedbackService::dispatcher(),
             producer: FeedbackService::bridge(ctx.link().callback(Msg::FeedbackService)),
         }
@@ -196,6 +234,7 @@ impl Component for FeedbackWidget {
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let link = ctx.link();
+        let config = &ctx.props().config;
 
         let on_toggle_click = link.callback(|_event: MouseEvent| Msg::Toggle());
 
@@ -205,6 +244,9 @@ impl Component for FeedbackWidget {
         let on_vid_selection =
             link.callback(|_event: MouseEvent| Msg::SetStep(FeedbackStep::Video));
 
+        let on_voice_selection =
+            link.callback(|_event: MouseEvent| Msg::SetStep(FeedbackStep::Voice));
+
         let _on_startover_click =
             link.callback(|_event: MouseEvent| Msg::SetStep(FeedbackStep::TypeSelection));
 
@@ -218,10 +260,12 @@ impl Component for FeedbackWidget {
 
         let msg_form_html = self.message_form.html();
         let video_form_html = self.video_form.html();
+        let voice_form_html = self.voice_form.html();
 
         html! {
             <>
-                <div id={self.widget_id.clone()} class={classes!({self.is_widget_active("feedback-widget")})}>
+                <div id={self.widget_id.clone()} lang={config.locale.clone()}
+                     class={classes!({self.is_widget_active("feedback-widget")}, config.theme.class())}>
                     <div class={classes!({self.is_widget_active("pane")})}>
                         <div class="title ">
                             <h3>{"Leave some "}<b>{"Feedback"}</b></h3>
@@ -250,6 +294,15 @@ impl Component for FeedbackWidget {
                                         <p class="text-[12px] text-light-purple ">{"Interested in sharing your experience. "}</p>
                                     </div>
                                 </div>
+                                <div class="type-selector" onclick={on_voice_selection}>
+                                    <span class="material-symbols-outlined text-white text-[40px]">
+                                        {"mic"}
+                                    </span>
+                                    <div class="w-[80%]">
+                                        <h3 class="text-white"><b>{"Voice Feedback"}</b></h3>
+                                        <p class="text-[12px] text-light-purple ">{"Talk us through it, no camera needed. "}</p>
+                                    </div>
+                                </div>
                             </div>
                             <div class={classes!({self.is_step_active(FeedbackStep::Message)})}>
                                 <div class="flex flex-wrap justify-between items-center mb-[15px]">
@@ -261,7 +314,13 @@ impl Component for FeedbackWidget {
                                         <p class="text-[12px] ">{"Interested in sharing your experience. "}</p>
                                     </div>
                                 </div>
-                                {msg_form_html}
+                                <p class="text-[12px] text-brand-red mb-[10px]"
+                                   style={if self.message_error.is_some() {"display:block;"}else{"display:none;"}}>
+                                    {self.message_error.clone().unwrap_or_default()}
+                                </p>
+                                <div style={if self.is_submitting {"pointer-events:none;opacity:.5;"}else{""}}>
+                                    {msg_form_html}
+                                </div>
                             </div>
                             <div class={classes!({self.is_step_active(FeedbackStep::Video)})}>
                                 <div class="flex flex-wrap justify-between items-center mb-[15px]">
@@ -275,8 +334,23 @@ impl Component for FeedbackWidget {
                                 </div>
                                 {video_form_html}
                             </div>
-                            <div class={classes!({self.is_step_active(FeedbackStep::VideoEditor)})}>
-                                <Camera duration={f64::from(10)} view={CameraView::Editor}/>
+                            <div class={classes!({self.is_step_active(FeedbackStep::Voice)})}>
+                                <div class="flex flex-wrap justify-between items-center mb-[15px]">
+                                    <span class="material-symbols-outlined text-[60px]">
+                                        {"mic"}
+                                    </span>
+                                    <div class="w-[80%]">
+                                        <h3 ><b>{"Voice Feedback"}</b></h3>
+                                        <p class="text-[12px] ">{"Talk us through it, no camera needed. "}</p>
+                                    </div>
+                                </div>
+                                {voice_form_html}
+                            </div>
+                            <div class={classes!("step", matches!(self.active_step,
+                                FeedbackStep::VideoEditor | FeedbackStep::VoiceEditor).then_some("active"))}>
+                                //Remounted when the mode changes so clips of both kinds never mix
+                                <Camera key={if self.voice {"voice"} else {"video"}} duration={config.max_duration}
+                                        view={CameraView::Editor} voice={self.voice}/>
                             </div>
                             <div class={classes!({self.is_step_active(FeedbackStep::ThankYou)})}>
                                 <div class="w-full text-center">
@@ -314,6 +388,12 @@ impl Component for FeedbackWidget {
                     self.previous_step = previous_step;
                 }
 
+                match self.active_step {
+                    FeedbackStep::Video | FeedbackStep::VideoEditor => self.voice = false,
+                    FeedbackStep::Voice | FeedbackStep::VoiceEditor => self.voice = true,
+                    _ => {}
+                }
+
                 return true;
             }
             Msg::UpdateVideoFormFieldValue(event) => {
@@ -345,8 +425,14 @@ impl Component for FeedbackWidget {
                 self.active_step = FeedbackStep::TypeSelection;
                 return true;
             }
+            Msg::SubmittingMessage() => {
+                self.is_submitting = true;
+                self.message_error = None;
+                return true;
+            }
             Msg::FeedbackService(context) => {
-                log::info!("{:?} <-- component", context);
+                self.on_feedback_service(context);
+                return true;
             }
         }
         return false;
@@ -1,7 +1,66 @@
 pub mod camera;
+pub mod capture;
+pub mod config;
 pub mod feedback;
 pub mod form;
 pub mod loading_animated;
 pub mod models;
 pub mod service;
 pub mod utilities;
+
+use config::{ConfigError, WidgetConfig, MOUNT_ATTRIBUTE};
+use feedback::{FeedbackWidget, Props};
+use serde_json::{Map, Value};
+use wasm_bindgen::prelude::*;
+use web_sys::{window, Element};
+
+/// Runs when the module loads. Mounts the widget into the first element carrying
+/// `data-feedback-widget`, if the page has one, otherwise the page calls `initWidget`.
+#[wasm_bindgen(start)]
+pub fn start() -> Result<(), JsValue> {
+    wasm_logger::init(wasm_logger::Config::default());
+
+    match find(format!("[{}]", MOUNT_ATTRIBUTE).as_str()) {
+        Some(element) => Ok(mount(element, None)?),
+        None => Ok(()),
+    }
+}
+
+/// Mounts the widget into the element `selector` matches. Keys in `config` take precedence
+/// over the JSON script tag and the element's `data-*` attributes. Throws when the config is
+/// invalid or incomplete.
+#[wasm_bindgen(js_name = initWidget)]
+pub fn init_widget(selector: &str, config: JsValue) -> Result<(), JsValue> {
+    let element = find(selector).ok_or_else(|| ConfigError::MissingTarget(selector.to_string()))?;
+
+    let overrides = if config.is_undefined() || config.is_null() {
+        None
+    } else {
+        let json = String::from(js_sys::JSON::stringify(&config)?);
+
+        Some(
+            serde_json::from_str::<Map<String, Value>>(json.as_str())
+                .map_err(|err| ConfigError::Invalid(format!("not an object: {}", err)))?,
+        )
+    };
+
+    Ok(mount(element, overrides)?)
+}
+
+fn find(selector: &str) -> Option<Element> {
+    window()?.document()?.query_selector(selector).ok()?
+}
+
+fn mount(element: Element, overrides: Option<Map<String, Value>>) -> Result<(), ConfigError> {
+    let config = match WidgetConfig::load(&element, overrides).and_then(WidgetConfig::install) {
+        Ok(config) => config,
+        Err(err) => {
+            log::error!("{}", err);
+            return Err(err);
+        }
+    };
+
+    yew::start_app_with_props_in_element::<FeedbackWidget>(element, Props { config });
+
+    Ok(())
+}
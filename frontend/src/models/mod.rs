@@ -1,3 +1,6 @@
+pub use feedback_protocol::{
+    AudioFormat, ClipDetailRequest, ClipEnvelope, ClipMessage, ClipMetadata, PROTOCOL_VERSION,
+};
 use field_accessor::FieldAccessor;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::JsValue;
@@ -5,16 +8,29 @@ use web_sys::{Blob, MediaDeviceInfo, MediaRecorder, MediaStream};
 use yew::Event;
 
 pub enum Request {
-    SendBlobChunk(Blob),
-    OnPlayback(Vec<ClipDetailRequest>),
+    BeginClip(ClipTrack),
+    SendBlobChunk(Blob, ClipTrack),
+    FinalizeClip(ClipTrack),
+    OnUploadMessage(String),
+    OnUploadDisconnected(),
+    ResumeUploads(),
+    OnPlayback(Vec<ClipDetailRequest>, Option<AudioFormat>),
     OnSubmission(),
     SendDeviceList(Vec<MediaDeviceInfo>),
     SendUserMedia(MediaStream),
-    SendClipDetails(ClipDetails),
     SendDeviceError(JsValue),
     SendMicFrequency(),
 }
 
+/// Which of the recorders running at once a chunk comes from.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ClipTrack {
+    /// The clip shown in the editor, from the camera or the screen.
+    Main,
+    /// The camera recorded alongside the screen, merged into the main clip as an overlay.
+    Camera,
+}
+
 #[derive(Clone, Serialize, Debug)]
 pub struct DeviceSettingForm {
     pub video: FormField,
@@ -32,6 +48,8 @@ pub struct CameraContext {
     pub device_error: Option<DeviceError>,
     pub clip_details: Option<ClipDetails>,
     pub merged_clip: Option<Vec<u8>>,
+    pub progress: Option<f64>,
+    pub error: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -40,10 +58,14 @@ pub enum CameraContextAction {
     AddedClip,
     SendDeviceList,
     SendUserMedia,
-    SendClipDetails,
     SendMicFrequency,
     SendDeviceError,
+    UploadProgress,
+    MergeQueued,
+    MergeStarted,
+    MergeProgress,
     MergedClip,
+    ClipError,
 }
 
 impl CameraContextAction {}
@@ -56,12 +78,6 @@ pub struct DeviceInfo {
     pub group_id: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ClipDetailRequest {
-    pub id: String,
-    pub duration: f64,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ConstraintOptions {
     pub device_id: ConstraintDeviceId,
@@ -91,6 +107,151 @@ pub struct ClipDetails {
     pub id: String,
     pub duration: f64,
     pub chunk: Blob,
+    /// Where the editor's trim handles sit, in seconds into the clip.
+    pub start: f64,
+    pub end: f64,
+    /// Id of the camera clip recorded alongside a screen recording.
+    pub overlay: Option<String>,
+}
+
+impl ClipDetails {
+    pub fn new(id: String, duration: f64, chunk: Blob) -> ClipDetails {
+        ClipDetails {
+            id,
+            duration,
+            chunk,
+            start: 0.0,
+            end: duration,
+            overlay: None,
+        }
+    }
+
+    /// Trim points of a clip whose `handle` is dragged to `seconds`, kept inside the clip and
+    /// at least `MIN_TRIMMED_DURATION` apart. `None` when they don't change.
+    pub fn trim_points(
+        start: f64,
+        end: f64,
+        duration: f64,
+        handle: TrimHandle,
+        seconds: f64,
+    ) -> Option<(f64, f64)> {
+        if !seconds.is_finite() {
+            return None;
+        }
+
+        let trimmed = match handle {
+            TrimHandle::Start => (
+                seconds.clamp(0.0, (end - MIN_TRIMMED_DURATION).max(0.0)),
+                end,
+            ),
+            TrimHandle::End => (
+                start,
+                seconds.clamp((start + MIN_TRIMMED_DURATION).min(duration), duration),
+            ),
+        };
+
+        (trimmed != (start, end)).then_some(trimmed)
+    }
+
+    /// What the merge is asked for, the trim points only when they cut something off.
+    pub fn to_request(&self) -> ClipDetailRequest {
+        ClipDetailRequest {
+            id: self.id.clone(),
+            duration: self.duration,
+            start: (self.start > 0.0).then_some(self.start),
+            end: (self.end < self.duration).then_some(self.end),
+            overlay: self.overlay.clone(),
+        }
+    }
+}
+
+/// Trim handles can't be dragged closer together than this, in seconds.
+pub const MIN_TRIMMED_DURATION: f64 = 0.5;
+
+/// Earlier and undone states of the editor's clip list, newest last.
+#[derive(Debug, Clone)]
+pub struct ClipHistory<T = ClipDetails> {
+    undo: Vec<Vec<T>>,
+    redo: Vec<Vec<T>>,
+}
+
+impl<T> Default for ClipHistory<T> {
+    fn default() -> ClipHistory<T> {
+        ClipHistory {
+            undo: vec![],
+            redo: vec![],
+        }
+    }
+}
+
+impl<T: Clone> ClipHistory<T> {
+    /// Oldest states are dropped past this many.
+    const LIMIT: usize = 50;
+
+    /// Keeps `clips` as they were before an edit. Whatever was undone can't be redone after.
+    pub fn record(&mut self, clips: &[T]) {
+        self.undo.push(clips.to_vec());
+        if self.undo.len() > Self::LIMIT {
+            self.undo.remove(0);
+        }
+
+        self.redo.clear();
+    }
+
+    /// Puts `clips` back the way they were before the latest edit.
+    pub fn undo(&mut self, clips: &mut Vec<T>) -> bool {
+        match self.undo.pop() {
+            Some(previous) => {
+                self.redo.push(std::mem::replace(clips, previous));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies the latest undone edit to `clips` again.
+    pub fn redo(&mut self, clips: &mut Vec<T>) -> bool {
+        match self.redo.pop() {
+            Some(next) => {
+                self.undo.push(std::mem::replace(clips, next));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+/// Moves the clip at `from` to `to`, shifting the ones in between. `false` when either
+/// index is out of range or they are the same.
+pub fn move_clip<T>(clips: &mut Vec<T>, from: usize, to: usize) -> bool {
+    if from == to || from >= clips.len() || to >= clips.len() {
+        return false;
+    }
+
+    let clip = clips.remove(from);
+    clips.insert(to, clip);
+
+    true
+}
+
+/// The in or out handle of a clip in the editor timeline.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TrimHandle {
+    Start,
+    End,
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -112,8 +273,10 @@ pub enum FeedbackStep {
     TypeSelection,
     Message,
     Video,
+    Voice,
     DeviceSettings,
     VideoEditor,
+    VoiceEditor,
     ThankYou,
     None,
     GoBack,
@@ -127,7 +290,20 @@ pub enum Msg {
     SetMessageFormFieldValue(FieldValue),
     Toggle(),
     Close(),
-    FeedbackService(String),
+    SubmittingMessage(),
+    FeedbackService(FeedbackContext),
+}
+
+#[derive(Clone, Debug)]
+pub struct FeedbackContext {
+    pub context_type: Option<FeedbackContextAction>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum FeedbackContextAction {
+    MessageSubmitted,
+    MessageFailed,
 }
 
 #[derive(Clone, Serialize, Debug)]
@@ -172,3 +348,129 @@ pub struct FieldValue {
     pub id: String,
     pub value: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_and_redo_round_trip_an_edit() {
+        let mut history = ClipHistory::default();
+        let mut clips = vec![1, 2];
+
+        history.record(&clips);
+        clips.push(3);
+
+        assert!(history.undo(&mut clips));
+        assert_eq!(clips, [1, 2]);
+        assert!(history.redo(&mut clips));
+        assert_eq!(clips, [1, 2, 3]);
+        assert!(!history.can_redo());
+        assert!(history.can_undo());
+    }
+
+    #[test]
+    fn a_new_edit_clears_what_was_undone() {
+        let mut history = ClipHistory::default();
+        let mut clips = vec![1];
+
+        history.record(&clips);
+        clips.push(2);
+        history.undo(&mut clips);
+        history.record(&clips);
+        clips.push(3);
+
+        assert!(!history.can_redo());
+        assert!(!history.redo(&mut clips));
+        assert_eq!(clips, [1, 3]);
+    }
+
+    #[test]
+    fn the_oldest_states_are_dropped_past_the_limit() {
+        let mut history = ClipHistory::default();
+        let mut clips = vec![];
+
+        for clip in 0..ClipHistory::<usize>::LIMIT + 5 {
+            history.record(&clips);
+            clips.push(clip);
+        }
+
+        let mut undone = 0;
+        while history.undo(&mut clips) {
+            undone += 1;
+        }
+
+        assert_eq!(undone, ClipHistory::<usize>::LIMIT);
+        assert_eq!(clips, [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn clips_move_to_the_first_and_last_index() {
+        let mut clips = vec!['a', 'b', 'c', 'd'];
+
+        assert!(move_clip(&mut clips, 2, 0));
+        assert_eq!(clips, ['c', 'a', 'b', 'd']);
+        assert!(move_clip(&mut clips, 0, 3));
+        assert_eq!(clips, ['a', 'b', 'd', 'c']);
+    }
+
+    #[test]
+    fn moves_out_of_range_or_in_place_do_nothing() {
+        let mut clips = vec!['a', 'b'];
+
+        assert!(!move_clip(&mut clips, 1, 1));
+        assert!(!move_clip(&mut clips, 0, 2));
+        assert!(!move_clip(&mut clips, 2, 0));
+        assert_eq!(clips, ['a', 'b']);
+    }
+
+    #[test]
+    fn a_start_handle_dragged_past_the_end_stops_short_of_it() {
+        assert_eq!(
+            ClipDetails::trim_points(0.0, 10.0, 10.0, TrimHandle::Start, 12.0),
+            Some((9.5, 10.0))
+        );
+        assert_eq!(
+            ClipDetails::trim_points(2.0, 10.0, 10.0, TrimHandle::Start, -1.0),
+            Some((0.0, 10.0))
+        );
+    }
+
+    #[test]
+    fn an_end_handle_dragged_before_the_start_stops_short_of_it() {
+        assert_eq!(
+            ClipDetails::trim_points(2.0, 10.0, 10.0, TrimHandle::End, 1.0),
+            Some((2.0, 2.5))
+        );
+        assert_eq!(
+            ClipDetails::trim_points(2.0, 8.0, 10.0, TrimHandle::End, 11.0),
+            Some((2.0, 10.0))
+        );
+    }
+
+    #[test]
+    fn a_clip_shorter_than_the_minimum_stays_whole() {
+        assert_eq!(
+            ClipDetails::trim_points(0.0, 0.3, 0.3, TrimHandle::Start, 0.2),
+            None
+        );
+        assert_eq!(
+            ClipDetails::trim_points(0.0, 0.3, 0.3, TrimHandle::End, 0.1),
+            None
+        );
+    }
+
+    #[test]
+    fn a_position_that_is_not_a_number_is_ignored() {
+        for seconds in [f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                ClipDetails::trim_points(0.0, 10.0, 10.0, TrimHandle::Start, seconds),
+                None
+            );
+            assert_eq!(
+                ClipDetails::trim_points(0.0, 10.0, 10.0, TrimHandle::End, seconds),
+                None
+            );
+        }
+    }
+}
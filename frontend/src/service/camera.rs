@@ -1,22 +1,84 @@
 use gloo_net::websocket::Message;
+use gloo_timers::callback::Timeout;
 
+use futures::channel::mpsc::{unbounded, UnboundedSender};
 use futures::{SinkExt, StreamExt};
-use std::collections::HashSet;
+use js_sys::Array;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::{spawn_local, JsFuture};
-use web_sys::Blob;
+use web_sys::{Blob, BlobPropertyBag};
 
 use yew_agent::{Agent, AgentLink, Context, HandlerId};
 
 use crate::models::{
-    CameraContext, CameraContextAction, ClipDetailRequest, ClipDetails, DeviceError, DeviceType,
-    Msg, Request,
+    CameraContext, CameraContextAction, ClipDetails, ClipEnvelope, ClipMessage, ClipTrack,
+    DeviceError, DeviceType, Msg, Request, PROTOCOL_VERSION,
 };
+use crate::utilities::Utilities;
 
 use super::web_socket::WebSocketService;
 
+/// How long to wait before reopening a dropped `/ws/clips` connection.
+const RECONNECT_DELAY_MS: u32 = 1000;
+/// Clip id length and seq bytes of a chunk frame, sent ahead of the clip id.
+const CHUNK_HEADER_BYTES: usize = 5;
+
+/// A clip being streamed to `/ws/clips`, kept until the server has finalized it.
+struct ClipUpload {
+    correlation_id: String,
+    clip_id: Option<String>,
+    /// Longest chunk frame the server takes, named along with the clip id.
+    max_chunk_bytes: Option<usize>,
+    chunks: Vec<Blob>,
+    unacked: BTreeSet<u32>,
+    is_recording: bool,
+    is_finalizing: bool,
+    track: ClipTrack,
+    /// Correlation id of the main clip's upload a camera clip was recorded with.
+    paired_with: Option<String>,
+}
+
+impl ClipUpload {
+    /// A recorded slice cut into pieces that fit in one chunk frame. Kept whole while the
+    /// server hasn't named its frame limit.
+    fn split(&self, chunk: &Blob) -> Vec<Blob> {
+        let piece_bytes = match (&self.clip_id, self.max_chunk_bytes) {
+            (Some(clip_id), Some(max_chunk_bytes)) => max_chunk_bytes
+                .saturating_sub(CHUNK_HEADER_BYTES + clip_id.len())
+                .max(1) as f64,
+            _ => return vec![chunk.clone()],
+        };
+
+        let size = chunk.size();
+        let mut pieces = vec![];
+        let mut start = 0.0;
+
+        while start < size {
+            let end = (start + piece_bytes).min(size);
+            pieces.push(chunk.slice_with_f64_and_f64(start, end).unwrap());
+            start = end;
+        }
+
+        if pieces.is_empty() {
+            pieces.push(chunk.clone());
+        }
+
+        pieces
+    }
+}
+
 pub struct CameraService {
     link: AgentLink<Self>,
     subscribers: HashSet<HandlerId>,
+    uploads: Vec<ClipUpload>,
+    /// Finalized screen recordings waiting for the camera clip recorded with them, by the
+    /// correlation id of their upload.
+    held_clips: HashMap<String, ClipDetails>,
+    /// Camera clip ids finalized before the screen recording they belong with.
+    finished_overlays: HashMap<String, String>,
+    upload_socket: Option<UnboundedSender<Message>>,
+    reconnect_timer: Option<Timeout>,
 }
 
 impl Agent for CameraService {
@@ -29,6 +91,11 @@ impl Agent for CameraService {
         Self {
             link,
             subscribers: HashSet::new(),
+            uploads: vec![],
+            held_clips: HashMap::new(),
+            finished_overlays: HashMap::new(),
+            upload_socket: None,
+            reconnect_timer: None,
         }
     }
 
@@ -46,58 +113,107 @@ impl Agent for CameraService {
             device_error: None,
             clip_details: None,
             merged_clip: None,
+            progress: None,
+            error: None,
         };
 
         match _msg {
-            Request::SendBlobChunk(chunk) => {
-                context.context_type = Some(CameraContextAction::SendBlobChunk);
-                context.chunk = Some(chunk);
-
-                let subs = self.subscribers.clone();
-                let link = self.link.clone();
-                let mut _context = context.clone();
-
-                for sub in subs.iter().filter(|s| s.is_respondable()) {
-                    link.respond(*sub, _context.clone());
-                }
-
-                has_sent_to_subs = true;
+            Request::BeginClip(track) => {
+                let correlation_id = Utilities::rnd_id("upload-");
+
+                //The main recorder is started first
+                let paired_with = match track {
+                    ClipTrack::Main => None,
+                    ClipTrack::Camera => self
+                        .recording_index(ClipTrack::Main)
+                        .map(|index| self.uploads[index].correlation_id.clone()),
+                };
 
-                if let Some(ws) = WebSocketService::public("clips") {
-                    let (mut write, mut read) = ws.context.split();
-                    let _buffer = JsFuture::from(context.chunk.clone().unwrap().array_buffer());
+                self.uploads.push(ClipUpload {
+                    correlation_id: correlation_id.clone(),
+                    clip_id: None,
+                    max_chunk_bytes: None,
+                    chunks: vec![],
+                    unacked: BTreeSet::new(),
+                    is_recording: true,
+                    is_finalizing: false,
+                    track,
+                    paired_with,
+                });
+
+                self.send_upload_message(correlation_id, ClipMessage::BeginClip);
+                return;
+            }
+            Request::SendBlobChunk(chunk, track) => {
+                context.context_type = Some(CameraContextAction::SendBlobChunk);
+                context.chunk = Some(chunk.clone());
 
-                    spawn_local(async move {
-                        let buffer = _buffer.await;
+                if let Some(index) = self.recording_index(track) {
+                    let upload = &mut self.uploads[index];
+                    let mut pieces = vec![];
 
-                        let buffer_array = js_sys::Uint8Array::new(&buffer.unwrap());
-                        let bytes = buffer_array.to_vec();
+                    for piece in upload.split(&chunk) {
+                        let seq = upload.chunks.len() as u32;
 
-                        write.send(Message::Bytes(bytes)).await.unwrap();
-                    });
+                        upload.chunks.push(piece.clone());
+                        upload.unacked.insert(seq);
+                        pieces.push((seq, piece));
+                    }
 
-                    spawn_local(async move {
-                        while let Some(msg) = read.next().await {
-                            let message_type = match msg {
-                                Ok(it) => it,
-                                _ => continue,
-                            };
+                    if let Some(clip_id) = upload.clip_id.clone() {
+                        for (seq, piece) in pieces {
+                            self.send_chunk(clip_id.clone(), seq, piece);
+                        }
+                    }
+                }
 
-                            if let Message::Text(clip_id) = message_type {
-                                _context.context_type = Some(CameraContextAction::AddedClip);
-                                _context.clip_details = Some(ClipDetails {
-                                    id: clip_id,
-                                    duration: 0.0,
-                                    chunk: Blob::new().unwrap(),
-                                });
+                //Only the main clip is previewed in the editor
+                if track == ClipTrack::Camera {
+                    return;
+                }
+            }
+            Request::FinalizeClip(track) => {
+                if let Some(index) = self.recording_index(track) {
+                    self.uploads[index].is_recording = false;
+                    self.finalize_if_complete(index);
+                }
+                return;
+            }
+            Request::OnUploadMessage(text) => {
+                if !self.apply_upload_message(&mut context, text.as_str()) {
+                    return;
+                }
+            }
+            Request::OnUploadDisconnected() => {
+                self.upload_socket = None;
 
-                                for sub in subs.iter().filter(|s| s.is_respondable()) {
-                                    link.respond(*sub, _context.clone());
-                                }
-                            }
-                        }
-                    });
-                };
+                if !self.uploads.is_empty() {
+                    let link = self.link.clone();
+                    self.reconnect_timer = Some(Timeout::new(RECONNECT_DELAY_MS, move || {
+                        link.send_input(Request::ResumeUploads());
+                    }));
+                }
+                return;
+            }
+            Request::ResumeUploads() => {
+                self.reconnect_timer = None;
+
+                let pending = self
+                    .uploads
+                    .iter()
+                    .map(|u| (u.correlation_id.clone(), u.clip_id.clone()))
+                    .collect::<Vec<(String, Option<String>)>>();
+
+                for (correlation_id, clip_id) in pending {
+                    let message = match clip_id {
+                        Some(clip_id) => ClipMessage::ResumeQuery { clip_id },
+                        // The server never confirmed the clip, ask for it again.
+                        None => ClipMessage::BeginClip,
+                    };
+
+                    self.send_upload_message(correlation_id, message);
+                }
+                return;
             }
             Request::OnSubmission() => {}
             Request::SendDeviceList(devices) => {
@@ -132,11 +248,7 @@ impl Agent for CameraService {
                 context.context_type = Some(CameraContextAction::SendDeviceError);
                 context.device_error = Some(error);
             }
-            Request::SendClipDetails(clip_details) => {
-                context.context_type = Some(CameraContextAction::SendClipDetails);
-                context.clip_details = Some(clip_details);
-            }
-            Request::OnPlayback(clips) => {
+            Request::OnPlayback(clips, audio_format) => {
                 if let Some(ws) = WebSocketService::public("clips/submit") {
                     let (mut write, mut read) = ws.context.split();
 
@@ -145,7 +257,17 @@ impl Agent for CameraService {
                     let mut _context = context.clone();
 
                     spawn_local(async move {
-                        let req = serde_json::to_string::<Vec<ClipDetailRequest>>(&clips).unwrap();
+                        let envelope = ClipEnvelope {
+                            version: PROTOCOL_VERSION,
+                            correlation_id: Utilities::rnd_id("merge-"),
+                            message: ClipMessage::MergeRequest {
+                                clips,
+                                profile: None,
+                                layout: None,
+                                audio_format,
+                            },
+                        };
+                        let req = serde_json::to_string::<ClipEnvelope>(&envelope).unwrap();
                         write.send(Message::Text(req)).await.unwrap();
                     });
                     spawn_local(async move {
@@ -155,9 +277,8 @@ impl Agent for CameraService {
                                 _ => continue,
                             };
 
-                            if let Message::Text(_clip_data) = message_type {
-                                _context.context_type = Some(CameraContextAction::MergedClip);
-                                _context.merged_clip = None;
+                            if let Message::Text(text) = message_type {
+                                CameraService::apply_message(&mut _context, text.as_str());
 
                                 for sub in subs.iter().filter(|s| s.is_respondable()) {
                                     link.respond(*sub, _context.clone());
@@ -199,3 +320,355 @@ impl Agent for CameraService {
         false
     }
 }
+
+impl CameraService {
+    /// Maps one `/ws/clips*` text frame onto the context handed to subscribers.
+    fn apply_message(context: &mut CameraContext, text: &str) {
+        context.progress = None;
+        context.error = None;
+
+        let envelope = match serde_json::from_str::<ClipEnvelope>(text) {
+            Ok(envelope) => envelope,
+            Err(err) => {
+                context.context_type = Some(CameraContextAction::ClipError);
+                context.error = Some(format!("Malformed server message: {}", err));
+                return;
+            }
+        };
+
+        if envelope.version != PROTOCOL_VERSION {
+            context.context_type = Some(CameraContextAction::ClipError);
+            context.error = Some(format!("Unsupported protocol version {}", envelope.version));
+            return;
+        }
+
+        match envelope.message {
+            ClipMessage::UploadAck {
+                clip_id, metadata, ..
+            } => {
+                context.context_type = Some(CameraContextAction::AddedClip);
+                context.clip_details = Some(ClipDetails::new(
+                    clip_id,
+                    metadata.duration,
+                    Blob::new().unwrap(),
+                ));
+            }
+            ClipMessage::Progress { received_bytes } => {
+                context.context_type = Some(CameraContextAction::UploadProgress);
+                context.progress = Some(received_bytes as f64);
+            }
+            ClipMessage::MergeQueued { .. } => {
+                context.context_type = Some(CameraContextAction::MergeQueued);
+            }
+            ClipMessage::MergeStarted { .. } => {
+                context.context_type = Some(CameraContextAction::MergeStarted);
+            }
+            ClipMessage::MergeProgress { percent, .. } => {
+                context.context_type = Some(CameraContextAction::MergeProgress);
+                context.progress = Some(percent);
+            }
+            ClipMessage::MergeComplete { .. } => {
+                context.context_type = Some(CameraContextAction::MergedClip);
+                context.merged_clip = None;
+            }
+            ClipMessage::Error { code, message } => {
+                log::error!("{}: {}", code, message);
+                context.context_type = Some(CameraContextAction::ClipError);
+                context.error = Some(message);
+            }
+            message => {
+                context.context_type = Some(CameraContextAction::ClipError);
+                context.error = Some(format!("Unexpected message from server: {:?}", message));
+            }
+        }
+    }
+
+    /// Tracks upload state for one `/ws/clips` text frame. Returns whether the context
+    /// should be handed to subscribers.
+    fn apply_upload_message(&mut self, context: &mut CameraContext, text: &str) -> bool {
+        let envelope = match serde_json::from_str::<ClipEnvelope>(text) {
+            Ok(envelope) if envelope.version == PROTOCOL_VERSION => envelope,
+            _ => {
+                CameraService::apply_message(context, text);
+                return true;
+            }
+        };
+
+        match envelope.message {
+            ClipMessage::ClipStarted {
+                clip_id,
+                max_chunk_bytes,
+            } => {
+                let correlation_id = envelope.correlation_id;
+
+                if let Some(index) = self
+                    .uploads
+                    .iter()
+                    .position(|u| u.clip_id.is_none() && u.correlation_id == correlation_id)
+                {
+                    let upload = &mut self.uploads[index];
+                    upload.clip_id = Some(clip_id.clone());
+                    upload.max_chunk_bytes = Some(max_chunk_bytes);
+
+                    //Nothing is sent before the clip id is known, so the slices recorded so far
+                    //can still be split and numbered again
+                    let recorded = std::mem::take(&mut upload.chunks);
+                    for chunk in recorded.iter() {
+                        let pieces = upload.split(chunk);
+                        upload.chunks.extend(pieces);
+                    }
+                    upload.unacked = (0..upload.chunks.len() as u32).collect();
+
+                    self.resend_unacked(index, &clip_id);
+                    self.finalize_if_complete(index);
+                }
+                false
+            }
+            ClipMessage::ChunkAck { clip_id, seq } => {
+                if let Some(index) = self.upload_index(&clip_id) {
+                    self.uploads[index].unacked.remove(&seq);
+                    self.finalize_if_complete(index);
+                }
+                false
+            }
+            ClipMessage::ResumeState { clip_id, received } => {
+                if let Some(index) = self.upload_index(&clip_id) {
+                    let upload = &mut self.uploads[index];
+                    for seq in received.iter() {
+                        upload.unacked.remove(seq);
+                    }
+                    upload.is_finalizing = false;
+
+                    self.resend_unacked(index, &clip_id);
+                    self.finalize_if_complete(index);
+                }
+                false
+            }
+            ClipMessage::UploadAck { clip_id, .. } => {
+                let upload = match self.upload_index(&clip_id) {
+                    Some(index) => self.uploads.remove(index),
+                    None => {
+                        CameraService::apply_message(context, text);
+                        return true;
+                    }
+                };
+
+                if upload.track == ClipTrack::Camera {
+                    return self.add_overlay(context, upload, clip_id);
+                }
+
+                CameraService::apply_message(context, text);
+
+                let clip_details = match context.clip_details.as_mut() {
+                    Some(clip_details) => clip_details,
+                    None => return true,
+                };
+
+                clip_details.chunk = CameraService::join_chunks(&upload.chunks);
+                clip_details.overlay = self.finished_overlays.remove(&upload.correlation_id);
+
+                //Handed over once the camera clip recorded with it is stored too
+                let awaits_overlay = self
+                    .uploads
+                    .iter()
+                    .any(|u| u.paired_with.as_ref() == Some(&upload.correlation_id));
+
+                if awaits_overlay {
+                    self.held_clips
+                        .insert(upload.correlation_id, clip_details.clone());
+                    return false;
+                }
+                true
+            }
+            ClipMessage::Error { ref code, .. } if code == "quota_exceeded" => {
+                // The server closes the socket after this, resuming would only hit the quota again.
+                self.uploads.clear();
+                self.held_clips.clear();
+                self.finished_overlays.clear();
+
+                CameraService::apply_message(context, text);
+                true
+            }
+            _ => {
+                CameraService::apply_message(context, text);
+                true
+            }
+        }
+    }
+
+    /// Attaches a finalized camera clip to the main clip recorded with it. Returns whether
+    /// the main clip is ready to be handed to subscribers.
+    fn add_overlay(
+        &mut self,
+        context: &mut CameraContext,
+        upload: ClipUpload,
+        clip_id: String,
+    ) -> bool {
+        let paired_with = match upload.paired_with {
+            Some(paired_with) => paired_with,
+            None => return false,
+        };
+
+        match self.held_clips.remove(&paired_with) {
+            Some(mut clip_details) => {
+                clip_details.overlay = Some(clip_id);
+
+                context.context_type = Some(CameraContextAction::AddedClip);
+                context.clip_details = Some(clip_details);
+                true
+            }
+            None => {
+                self.finished_overlays.insert(paired_with, clip_id);
+                false
+            }
+        }
+    }
+
+    /// The upload the `track` recorder is currently feeding.
+    fn recording_index(&self, track: ClipTrack) -> Option<usize> {
+        self.uploads
+            .iter()
+            .rposition(|u| u.is_recording && u.track == track)
+    }
+
+    fn upload_index(&self, clip_id: &str) -> Option<usize> {
+        self.uploads
+            .iter()
+            .position(|u| u.clip_id.as_deref() == Some(clip_id))
+    }
+
+    fn resend_unacked(&mut self, index: usize, clip_id: &str) {
+        let upload = &self.uploads[index];
+        let pending = upload
+            .unacked
+            .iter()
+            .map(|seq| (*seq, upload.chunks[*seq as usize].clone()))
+            .collect::<Vec<(u32, Blob)>>();
+
+        for (seq, chunk) in pending {
+            self.send_chunk(clip_id.to_string(), seq, chunk);
+        }
+    }
+
+    /// Asks the server to join the clip once recording stopped and every chunk is acknowledged.
+    fn finalize_if_complete(&mut self, index: usize) {
+        let upload = &mut self.uploads[index];
+
+        let clip_id = match upload.clip_id.clone() {
+            Some(clip_id) => clip_id,
+            None => return,
+        };
+
+        if upload.is_recording || upload.is_finalizing || !upload.unacked.is_empty() {
+            return;
+        }
+
+        upload.is_finalizing = true;
+
+        let correlation_id = upload.correlation_id.clone();
+        let chunk_count = upload.chunks.len() as u32;
+
+        self.send_upload_message(
+            correlation_id,
+            ClipMessage::FinalizeClip {
+                clip_id,
+                chunk_count,
+            },
+        );
+    }
+
+    fn send_upload_message(&mut self, correlation_id: String, message: ClipMessage) {
+        let envelope = ClipEnvelope {
+            version: PROTOCOL_VERSION,
+            correlation_id,
+            message,
+        };
+
+        if let Some(socket) = self.upload_socket() {
+            let text = serde_json::to_string::<ClipEnvelope>(&envelope).unwrap();
+            let _ = socket.unbounded_send(Message::Text(text));
+        }
+    }
+
+    /// Sends one chunk as `[clip id length: u8][clip id][seq: u32 big endian][chunk bytes]`.
+    fn send_chunk(&mut self, clip_id: String, seq: u32, chunk: Blob) {
+        let socket = match self.upload_socket() {
+            Some(socket) => socket,
+            None => return,
+        };
+
+        let buffer = JsFuture::from(chunk.array_buffer());
+
+        spawn_local(async move {
+            let buffer = match buffer.await {
+                Ok(buffer) => buffer,
+                Err(err) => {
+                    log::error!("{:?}", err);
+                    return;
+                }
+            };
+
+            let mut frame = vec![clip_id.len() as u8];
+            frame.extend_from_slice(clip_id.as_bytes());
+            frame.extend_from_slice(&seq.to_be_bytes());
+            frame.extend(js_sys::Uint8Array::new(&buffer).to_vec());
+
+            // A lost frame stays unacknowledged and is resent after reconnecting.
+            let _ = socket.unbounded_send(Message::Bytes(frame));
+        });
+    }
+
+    /// The `/ws/clips` connection shared by all uploads, opened on first use.
+    fn upload_socket(&mut self) -> Option<UnboundedSender<Message>> {
+        if let Some(socket) = &self.upload_socket {
+            if !socket.is_closed() {
+                return Some(socket.clone());
+            }
+        }
+
+        let ws = match WebSocketService::public("clips") {
+            Some(ws) => ws,
+            None => {
+                self.link.send_input(Request::OnUploadDisconnected());
+                return None;
+            }
+        };
+
+        let (mut write, mut read) = ws.context.split();
+        let (socket, mut outgoing) = unbounded::<Message>();
+
+        spawn_local(async move {
+            while let Some(message) = outgoing.next().await {
+                if write.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let link = self.link.clone();
+        spawn_local(async move {
+            while let Some(Ok(message)) = read.next().await {
+                if let Message::Text(text) = message {
+                    link.send_input(Request::OnUploadMessage(text));
+                }
+            }
+
+            link.send_input(Request::OnUploadDisconnected());
+        });
+
+        self.upload_socket = Some(socket.clone());
+        Some(socket)
+    }
+
+    fn join_chunks(chunks: &[Blob]) -> Blob {
+        let parts = Array::new();
+        for chunk in chunks.iter() {
+            parts.push(&JsValue::from(chunk));
+        }
+
+        let mut options = BlobPropertyBag::new();
+        options.type_("video/webm");
+
+        Blob::new_with_blob_sequence_and_options(&parts, &options).unwrap()
+    }
+}
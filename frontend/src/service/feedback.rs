@@ -1,12 +1,16 @@
+use gloo_net::http::Request as HttpRequest;
 use std::collections::HashSet;
-use web_sys::ReadableStream;
+use wasm_bindgen_futures::spawn_local;
 use yew_agent::{Agent, AgentLink, Context, HandlerId};
 
+use crate::config::WidgetConfig;
+use crate::models::{FeedbackContext, FeedbackContextAction};
+
 pub enum Msg {}
 
 pub enum Request {
     OnMessageSubmission(String),
-    OnMessageSubmissionComplete(Option<ReadableStream>),
+    OnMessageSubmissionComplete(Result<(), String>),
 }
 
 pub struct FeedbackService {
@@ -17,7 +21,7 @@ impl Agent for FeedbackService {
     type Reach = Context<Self>;
     type Message = Msg;
     type Input = Request;
-    type Output = String;
+    type Output = FeedbackContext;
 
     fn create(link: AgentLink<Self>) -> Self {
         Self {
@@ -29,19 +33,29 @@ impl Agent for FeedbackService {
     fn update(&mut self, _msg: Self::Message) {}
 
     fn handle_input(&mut self, msg: Self::Input, _id: HandlerId) {
-        let mut skip_response: bool = false;
+        let mut context = FeedbackContext {
+            context_type: None,
+            error: None,
+        };
+
         match msg {
             Request::OnMessageSubmission(form_data) => {
-                skip_response = true;
-                self.on_msg_submission(form_data)
+                self.on_msg_submission(form_data);
+                return;
             }
-            Request::OnMessageSubmissionComplete(_stream) => {}
+            Request::OnMessageSubmissionComplete(result) => match result {
+                Ok(()) => {
+                    context.context_type = Some(FeedbackContextAction::MessageSubmitted);
+                }
+                Err(err) => {
+                    context.context_type = Some(FeedbackContextAction::MessageFailed);
+                    context.error = Some(err);
+                }
+            },
         }
 
-        if !skip_response {
-            for sub in self.subscribers.iter().filter(|s| s.is_respondable()) {
-                self.link.respond(*sub, String::from(""));
-            }
+        for sub in self.subscribers.iter().filter(|s| s.is_respondable()) {
+            self.link.respond(*sub, context.clone());
         }
     }
 
@@ -57,8 +71,34 @@ impl Agent for FeedbackService {
 }
 
 impl FeedbackService {
-    pub fn on_msg_submission(&self, _form_data: String) {
-        let _link = self.link.clone();
-        //Feedback Submittion
+    pub fn on_msg_submission(&self, form_data: String) {
+        let link = self.link.clone();
+
+        let url = match WidgetConfig::current() {
+            Ok(config) => config.api_endpoint("feedback"),
+            Err(err) => {
+                link.send_input(Request::OnMessageSubmissionComplete(Err(err.to_string())));
+                return;
+            }
+        };
+
+        spawn_local(async move {
+            let response = HttpRequest::post(url.as_str())
+                .header("Content-Type", "application/json")
+                .body(form_data)
+                .send()
+                .await;
+
+            let result = match response {
+                Ok(res) if res.ok() => Ok(()),
+                Ok(res) => match res.json::<Option<String>>().await {
+                    Ok(Some(message)) => Err(message),
+                    _ => Err(res.status_text()),
+                },
+                Err(err) => Err(err.to_string()),
+            };
+
+            link.send_input(Request::OnMessageSubmissionComplete(result));
+        });
     }
 }
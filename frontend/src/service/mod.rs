@@ -1,3 +1,4 @@
 pub mod camera;
 pub mod feedback;
+pub mod session;
 pub mod web_socket;
@@ -0,0 +1,60 @@
+use gloo_net::http::Request as HttpRequest;
+use serde::Deserialize;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{window, HtmlDocument};
+
+use crate::config::WidgetConfig;
+
+#[derive(Debug, Deserialize)]
+pub struct IssuedSession {
+    pub session_id: String,
+    pub token: String,
+    pub expires_at: String,
+}
+
+pub struct SessionService;
+
+impl SessionService {
+    /// Requests a signed session from the backend and stores it in the `X-FDot-Session` cookie.
+    pub fn start(config: &WidgetConfig) {
+        let url = config.api_endpoint("session");
+
+        spawn_local(async move {
+            let response = HttpRequest::post(url.as_str()).send().await;
+
+            let session = match response {
+                Ok(res) if res.ok() => res.json::<IssuedSession>().await,
+                Ok(res) => {
+                    log::error!("session request failed: {}", res.status_text());
+                    return;
+                }
+                Err(err) => Err(err),
+            };
+
+            match session {
+                Ok(session) => Self::set_cookie(&session),
+                Err(err) => log::error!("{:?}", err),
+            }
+        });
+    }
+
+    fn set_cookie(session: &IssuedSession) {
+        let html_document = window()
+            .unwrap()
+            .document()
+            .unwrap()
+            .dyn_into::<HtmlDocument>()
+            .unwrap();
+
+        let cookie = format!(
+            "X-FDot-Session={}; path=/; expires={}",
+            session.token,
+            js_sys::Date::new(&session.expires_at.as_str().into()).to_utc_string()
+        );
+
+        html_document.set_cookie(cookie.as_str()).unwrap();
+
+        log::info!("started session {}", session.session_id);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::utilities::Utilities;
+use crate::config::WidgetConfig;
 use gloo_net::websocket::futures::WebSocket;
 
 pub struct WebSocketService {
@@ -7,8 +7,13 @@ pub struct WebSocketService {
 
 impl WebSocketService {
     pub fn public(path: &'static str) -> Option<WebSocketService> {
-        let mut url = Utilities::config("ws_url");
-        url.push_str(path);
+        let url = match WidgetConfig::current() {
+            Ok(config) => config.ws_endpoint(path),
+            Err(err) => {
+                log::error!("{}", err);
+                return None;
+            }
+        };
 
         match WebSocket::open(url.as_str()) {
             Ok(context) => Some(WebSocketService { context }),
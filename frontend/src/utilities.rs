@@ -6,20 +6,6 @@ use yew::TargetCast;
 pub struct Utilities;
 
 impl Utilities {
-    pub fn config(key: &str) -> String {
-        let mut config: HashMap<String, String> = HashMap::new();
-
-        config.insert(
-            String::from("ws_url"),
-            String::from("ws://127.0.0.1:9011/ws/"),
-        );
-
-        match config.get(key) {
-            Some(val) => val.to_string(),
-            None => String::from("InvalidConfigKey"),
-        }
-    }
-
     pub fn rnd_id_f64() -> f64 {
         Math::floor(Math::random() * (999999) as f64)
     }
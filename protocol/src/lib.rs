@@ -0,0 +1,474 @@
+//! Messages exchanged over `/ws/clips*`, shared by the backend and the widget so both
+//! always agree on their shape.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+const DEFAULT_PIP_SIZE: f64 = 0.25;
+const DEFAULT_PIP_MARGIN: u32 = 16;
+/// Bounds of `PipLayout::size`, past them the camera is too small to see or hides the screen.
+const MIN_PIP_SIZE: f64 = 0.1;
+const MAX_PIP_SIZE: f64 = 0.5;
+
+/// Bumped whenever a message shape changes in a way older widgets can't read.
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// Every text frame on `/ws/clips*` is one envelope, in both directions.
+///
+/// Serialized as `{"version":2,"correlation_id":"...","type":"upload_ack",...}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipEnvelope {
+    pub version: u16,
+    pub correlation_id: String,
+    #[serde(flatten)]
+    pub message: ClipMessage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipDetailRequest {
+    pub id: String,
+    pub duration: f64,
+    /// Seconds into the clip the merge starts it at, its beginning when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<f64>,
+    /// Seconds into the clip the merge ends it at, its end when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<f64>,
+    /// Id of a camera clip recorded alongside this one, drawn over it picture-in-picture
+    /// and cut to the same trim points.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overlay: Option<String>,
+}
+
+impl ClipDetailRequest {
+    /// Checks the trim points before the merge is queued.
+    pub fn validate(&self) -> Result<(), ProtocolError> {
+        for point in [self.start, self.end].into_iter().flatten() {
+            if !point.is_finite() || point < 0.0 {
+                return Err(ProtocolError::InvalidTrim(format!(
+                    "Trim point {} of clip {} is not a positive number of seconds",
+                    point, self.id
+                )));
+            }
+        }
+
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            if start >= end {
+                return Err(ProtocolError::InvalidTrim(format!(
+                    "Trim of clip {} starts at {} after it ends at {}",
+                    self.id, start, end
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The part of a clip `duration` seconds long to keep, `None` when that is all of it.
+    /// An end past the clip's end is taken to be its end.
+    pub fn trim(&self, duration: f64) -> Result<Option<TrimRange>, ProtocolError> {
+        let start = self.start.unwrap_or(0.0);
+        let end = self.end.unwrap_or(duration).min(duration);
+
+        if start <= 0.0 && end >= duration {
+            return Ok(None);
+        }
+
+        if start >= end {
+            return Err(ProtocolError::InvalidTrim(format!(
+                "Trim of clip {} starts at {} but the clip ends at {}",
+                self.id, start, end
+            )));
+        }
+
+        Ok(Some(TrimRange { start, end }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClipMessage {
+    /// Client asks for the listed clips to be merged, in order, with the named encoding
+    /// profile or the server's default one. Camera overlays are laid out with `layout` or
+    /// the server's `[pip]` settings. Voice feedback sets `audio_format` to have only the
+    /// clips' audio merged, into that format, and the profile and layout are ignored.
+    MergeRequest {
+        clips: Vec<ClipDetailRequest>,
+        #[serde(default)]
+        profile: Option<String>,
+        #[serde(default)]
+        layout: Option<PipLayout>,
+        #[serde(default)]
+        audio_format: Option<AudioFormat>,
+    },
+    /// Client opens a new clip upload. Answered with `ClipStarted`.
+    BeginClip,
+    /// Server assigned id for a clip opened with `BeginClip`. Chunk frames for the clip carry it
+    /// and are at most `max_chunk_bytes` long, header included.
+    ClipStarted {
+        clip_id: String,
+        max_chunk_bytes: usize,
+    },
+    /// Chunk `seq` of `clip_id` is stored and does not need to be resent.
+    ChunkAck {
+        clip_id: String,
+        seq: u32,
+    },
+    /// Client asks which chunks of `clip_id` the server already holds, e.g. after reconnecting.
+    ResumeQuery {
+        clip_id: String,
+    },
+    /// Sequence numbers of the chunks held for `clip_id`, in ascending order.
+    ResumeState {
+        clip_id: String,
+        received: Vec<u32>,
+    },
+    /// Client has sent chunks `0..chunk_count` and asks for them to be joined into the clip.
+    FinalizeClip {
+        clip_id: String,
+        chunk_count: u32,
+    },
+    /// A clip has been finalized and written to the session directory. `metadata` is what
+    /// ffprobe reports for the stored file.
+    UploadAck {
+        clip_id: String,
+        byte_size: u64,
+        metadata: ClipMetadata,
+    },
+    /// Bytes received so far for a chunk still being streamed in fragments.
+    Progress {
+        received_bytes: u64,
+    },
+    /// The merge was accepted as background job `job_id`, see `GET /jobs/{id}`.
+    MergeQueued {
+        job_id: String,
+    },
+    MergeStarted {
+        job_id: String,
+        clip_count: usize,
+    },
+    /// Forwarded from ffmpeg's `-progress` output while job `job_id` runs.
+    MergeProgress {
+        job_id: String,
+        out_time: f64,
+        speed: Option<f64>,
+        frame: u64,
+        percent: f64,
+    },
+    MergeComplete {
+        job_id: String,
+        duration: f64,
+        byte_size: u64,
+    },
+    Error {
+        code: String,
+        message: String,
+    },
+}
+
+impl ClipEnvelope {
+    pub fn new(correlation_id: String, message: ClipMessage) -> ClipEnvelope {
+        ClipEnvelope {
+            version: PROTOCOL_VERSION,
+            correlation_id,
+            message,
+        }
+    }
+}
+
+/// What ffprobe reports about a stored clip.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClipMetadata {
+    /// ffprobe's `format_name`, e.g. `matroska,webm`.
+    pub container: String,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub frame_rate: Option<f64>,
+    pub duration: f64,
+    pub audio_channels: Option<i32>,
+    /// Video stream time base, e.g. `1/1000`.
+    pub time_base: Option<String>,
+    pub pixel_format: Option<String>,
+    pub audio_sample_rate: Option<i32>,
+}
+
+/// The part of a clip kept in a merge, in seconds from the clip's start.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrimRange {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl TrimRange {
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+}
+
+/// Corner of a screen recording the camera picture is drawn in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PipCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+impl FromStr for PipCorner {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<PipCorner, String> {
+        match value {
+            "top_left" => Ok(PipCorner::TopLeft),
+            "top_right" => Ok(PipCorner::TopRight),
+            "bottom_left" => Ok(PipCorner::BottomLeft),
+            "bottom_right" => Ok(PipCorner::BottomRight),
+            _ => Err(format!(
+                "expected top_left, top_right, bottom_left or bottom_right, got {}",
+                value
+            )),
+        }
+    }
+}
+
+/// Outline the camera picture is cut to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PipShape {
+    /// The camera's own aspect ratio.
+    #[default]
+    Rectangle,
+    /// Cropped to its centre square.
+    Square,
+    /// Cropped to its centre square with the corners made transparent.
+    Circle,
+}
+
+impl FromStr for PipShape {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<PipShape, String> {
+        match value {
+            "rectangle" => Ok(PipShape::Rectangle),
+            "square" => Ok(PipShape::Square),
+            "circle" => Ok(PipShape::Circle),
+            _ => Err(format!(
+                "expected rectangle, square or circle, got {}",
+                value
+            )),
+        }
+    }
+}
+
+/// How a camera clip is drawn over the screen recording it was captured with, the `[pip]`
+/// settings unless a merge asks for another.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipLayout {
+    pub corner: PipCorner,
+    /// Width of the camera picture as a fraction of the screen's.
+    pub size: f64,
+    pub shape: PipShape,
+    /// Gap between the camera picture and the screen's edges, in pixels.
+    pub margin: u32,
+}
+
+impl Default for PipLayout {
+    fn default() -> PipLayout {
+        PipLayout {
+            corner: PipCorner::default(),
+            size: DEFAULT_PIP_SIZE,
+            shape: PipShape::default(),
+            margin: DEFAULT_PIP_MARGIN,
+        }
+    }
+}
+
+impl PipLayout {
+    pub fn validate(&self) -> Result<(), ProtocolError> {
+        if !(MIN_PIP_SIZE..=MAX_PIP_SIZE).contains(&self.size) {
+            return Err(ProtocolError::InvalidLayout(format!(
+                "Picture-in-picture size {} is not between {} and {}",
+                self.size, MIN_PIP_SIZE, MAX_PIP_SIZE
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Audio-only outputs, for voice feedback recorded without a camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    /// Opus in Ogg.
+    Ogg,
+    /// For players without Opus, like older Safari.
+    Mp3,
+}
+
+impl AudioFormat {
+    pub const ALL: [AudioFormat; 2] = [AudioFormat::Ogg, AudioFormat::Mp3];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Ogg => "ogg",
+            AudioFormat::Mp3 => "mp3",
+        }
+    }
+}
+
+/// Why a message was turned down, before anything was done with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// A clip's trim points are out of order, negative or past its end.
+    InvalidTrim(String),
+    /// A picture-in-picture layout is out of bounds.
+    InvalidLayout(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::InvalidTrim(message) | ProtocolError::InvalidLayout(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope_carries_the_message_type_alongside_its_fields() {
+        let envelope = ClipEnvelope::new(
+            String::from("c1"),
+            ClipMessage::ChunkAck {
+                clip_id: String::from("clip-abc123"),
+                seq: 3,
+            },
+        );
+
+        assert_eq!(
+            serde_json::to_string(&envelope).unwrap(),
+            r#"{"version":2,"correlation_id":"c1","type":"chunk_ack","clip_id":"clip-abc123","seq":3}"#
+        );
+    }
+
+    #[test]
+    fn merge_request_options_may_be_left_out() {
+        let envelope: ClipEnvelope = serde_json::from_str(
+            r#"{"version":2,"correlation_id":"m1","type":"merge_request","clips":[]}"#,
+        )
+        .unwrap();
+
+        match envelope.message {
+            ClipMessage::MergeRequest {
+                profile,
+                layout,
+                audio_format,
+                ..
+            } => assert!(profile.is_none() && layout.is_none() && audio_format.is_none()),
+            message => panic!("parsed as {:?}", message),
+        }
+    }
+
+    fn clip(start: Option<f64>, end: Option<f64>) -> ClipDetailRequest {
+        ClipDetailRequest {
+            id: String::from("clip-abc123"),
+            duration: 10.0,
+            start,
+            end,
+            overlay: None,
+        }
+    }
+
+    #[test]
+    fn validate_accepts_missing_and_ordered_trim_points() {
+        for (start, end) in [
+            (None, None),
+            (Some(0.0), None),
+            (None, Some(4.0)),
+            (Some(1.5), Some(4.0)),
+        ] {
+            assert!(clip(start, end).validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn validate_rejects_a_start_at_or_after_the_end() {
+        for (start, end) in [(2.0, 2.0), (5.0, 1.0)] {
+            let error = clip(Some(start), Some(end)).validate().unwrap_err();
+
+            assert!(matches!(error, ProtocolError::InvalidTrim(_)));
+        }
+    }
+
+    #[test]
+    fn validate_rejects_negative_and_non_finite_points() {
+        for point in [-1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                clip(Some(point), None).validate().unwrap_err(),
+                ProtocolError::InvalidTrim(_)
+            ));
+            assert!(matches!(
+                clip(None, Some(point)).validate().unwrap_err(),
+                ProtocolError::InvalidTrim(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn trim_of_the_whole_clip_is_none() {
+        assert_eq!(clip(None, None).trim(10.0).unwrap(), None);
+        assert_eq!(clip(Some(0.0), Some(10.0)).trim(10.0).unwrap(), None);
+    }
+
+    #[test]
+    fn trim_keeps_the_chosen_part() {
+        assert_eq!(
+            clip(Some(1.5), Some(4.0)).trim(10.0).unwrap(),
+            Some(TrimRange {
+                start: 1.5,
+                end: 4.0
+            })
+        );
+        assert_eq!(
+            clip(Some(2.0), None).trim(10.0).unwrap(),
+            Some(TrimRange {
+                start: 2.0,
+                end: 10.0
+            })
+        );
+    }
+
+    #[test]
+    fn trim_cuts_an_end_past_the_clip_to_its_duration() {
+        assert_eq!(clip(None, Some(12.0)).trim(10.0).unwrap(), None);
+        assert_eq!(
+            clip(Some(3.0), Some(12.0)).trim(10.0).unwrap(),
+            Some(TrimRange {
+                start: 3.0,
+                end: 10.0
+            })
+        );
+    }
+
+    #[test]
+    fn trim_rejects_a_start_past_the_clip() {
+        for start in [10.0, 11.0] {
+            let error = clip(Some(start), None).trim(10.0).unwrap_err();
+
+            assert!(matches!(error, ProtocolError::InvalidTrim(_)));
+        }
+    }
+}